@@ -0,0 +1,204 @@
+use crate::disk::Disk;
+use crate::fs::vbr::VBR;
+use crate::fs::{DirEntry, File, FileAttributes, FsError, MIN_SECTORS};
+use crate::os::OsProfile;
+use std::path::Path;
+
+/// Directory entries are always 32 bytes, regardless of FAT type.
+const ENTRY_SIZE: u64 = 32;
+
+/// Partitions made by Partition::new start at the second track.
+const FIRST_LBA: u32 = 63;
+
+/// The largest volume that fits on a disk the geometry code can express: 1022 cylinders of 16
+/// heads, which is as far as disks under 504MB go, minus the track in front of the partition.
+const MAX_SECTOR_COUNT: u32 = 1022 * 16 * 63 - FIRST_LBA;
+
+/// Characters of a long name that fit in one directory entry.
+const LONG_NAME_CHARS: usize = 13;
+
+/// The shape of the content that goes onto a container, as far as its size is concerned:
+/// the sizes of the files in a directory and the directories below it. Names only matter for
+/// how many directory entries they take, since a name that isn't 8.3 gets long name entries
+/// in front of its short one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContentTree {
+    files: Vec<u64>,
+    directories: Vec<ContentTree>,
+    long_name_entries: usize,
+}
+
+impl ContentTree {
+    pub fn new() -> Self {
+        ContentTree::default()
+    }
+
+    /// Scan a directory on the host, recursively. Symbolic links are left out, the same as
+    /// import_dir leaves them out.
+    pub fn from_path(path: &Path) -> std::io::Result<Self> {
+        let mut tree = ContentTree::new();
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let metadata = std::fs::symlink_metadata(entry.path())?;
+            if metadata.is_symlink() {
+                continue;
+            } else if metadata.is_dir() {
+                tree.add_named_dir(&name, ContentTree::from_path(&entry.path())?);
+            } else {
+                tree.add_named_file(&name, metadata.len());
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Add a file with an 8.3 name, which takes a single directory entry.
+    pub fn add_file(&mut self, size: u64) {
+        self.files.push(size);
+    }
+
+    /// Add a subdirectory with an 8.3 name, which takes a single directory entry.
+    pub fn add_dir(&mut self, directory: ContentTree) {
+        self.directories.push(directory);
+    }
+
+    /// Add a file under its host name, counting the long name entries it may need.
+    pub fn add_named_file(&mut self, name: &str, size: u64) {
+        self.add_file(size);
+        self.long_name_entries += ContentTree::long_name_entries(name);
+    }
+
+    /// Add a subdirectory under its host name, counting the long name entries it may need.
+    pub fn add_named_dir(&mut self, name: &str, directory: ContentTree) {
+        self.add_dir(directory);
+        self.long_name_entries += ContentTree::long_name_entries(name);
+    }
+
+    /// How many long name entries go in front of the short entry for a name, on a volume
+    /// with long names enabled. Zero when the short name carries the name as-is.
+    fn long_name_entries(name: &str) -> usize {
+        let short_name = match File::normalize_name(name, &[]) {
+            Ok(short_name) => short_name,
+            Err(_) => return 0,
+        };
+        let entry = DirEntry::new(short_name, FileAttributes::default(), 0, 0);
+        if entry.get_short_name() == name {
+            return 0;
+        }
+        num::integer::div_ceil(name.encode_utf16().count(), LONG_NAME_CHARS)
+    }
+
+    /// Number of entries this directory needs for its own files and subdirectories.
+    fn entry_count(&self) -> usize {
+        self.files.len() + self.directories.len() + self.long_name_entries
+    }
+
+    /// Clusters needed for everything in this directory and below, but not the directory
+    /// itself. Every file takes whole clusters, so the slack adds up for small files.
+    fn clusters(&self, cluster_size: u64) -> u64 {
+        let files: u64 = self
+            .files
+            .iter()
+            .map(|size| num::integer::div_ceil(*size, cluster_size))
+            .sum();
+        let directories: u64 = self
+            .directories
+            .iter()
+            .map(|directory| {
+                // Subdirectories also hold "." and "..", and always take at least one cluster.
+                let bytes = (directory.entry_count() as u64 + 2) * ENTRY_SIZE;
+                num::integer::div_ceil(bytes, cluster_size) + directory.clusters(cluster_size)
+            })
+            .sum();
+        files + directories
+    }
+
+    /// The largest file anywhere in the tree.
+    fn largest_file(&self) -> u64 {
+        let files = self.files.iter().copied().max().unwrap_or(0);
+        let directories = self.directories.iter().map(ContentTree::largest_file).max();
+        files.max(directories.unwrap_or(0))
+    }
+}
+
+/// How large a disk has to be for a certain content tree and operating system.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Estimate {
+    /// Size of the disk image in bytes, a whole number of cylinders.
+    pub disk_size: usize,
+    /// Size of the partition and its volume in sectors.
+    pub sector_count: u32,
+    /// Cluster size on the volume in bytes.
+    pub cluster_size: u64,
+    /// Clusters taken by the operating system and the content, without headroom.
+    pub used_clusters: u64,
+    /// Clusters available on the volume.
+    pub total_clusters: u64,
+}
+
+impl Estimate {
+    /// Work out the smallest disk that holds the system files of an operating system plus a
+    /// content tree, with some percentage of the used clusters added as free space on top.
+    /// This follows the same rules a real build uses: the cluster size table and the FAT size
+    /// calculation in the VBR, the fixed size of the root directory and cylinder rounding of
+    /// the disk geometry. One slot of the root directory is kept free for the volume label.
+    /// Content that doesn't fit on the largest disk the geometry allows gives an error.
+    pub fn new(
+        tree: &ContentTree,
+        os: &OsProfile,
+        headroom_percent: u32,
+    ) -> Result<Estimate, FsError> {
+        if tree.largest_file() > u64::from(u32::MAX) {
+            return Err(FsError::FileTooLarge(String::from("content tree")));
+        }
+        let root_entries = usize::from(VBR::new(MIN_SECTORS).get_root_dir_entries_count());
+        if tree.entry_count() + os.get_system_files().len() + 1 > root_entries {
+            return Err(FsError::DirectoryFull(String::from("\\")));
+        }
+
+        let needed = |sector_count: u32| -> (u64, u64, u64) {
+            let boot_record = VBR::new(sector_count);
+            let cluster_size = u64::from(boot_record.get_bytes_per_sector())
+                * u64::from(boot_record.get_sectors_per_cluster());
+            let system: u64 = os
+                .get_system_files()
+                .iter()
+                .map(|(_, data)| num::integer::div_ceil(data.len() as u64, cluster_size))
+                .sum();
+            let used = system + tree.clusters(cluster_size);
+            let with_headroom =
+                used + num::integer::div_ceil(used * u64::from(headroom_percent), 100);
+            (cluster_size, used, with_headroom)
+        };
+        let fits = |sector_count: u32| {
+            let (_, _, with_headroom) = needed(sector_count);
+            with_headroom <= u64::from(VBR::new(sector_count).get_cluster_count())
+        };
+
+        if !fits(MAX_SECTOR_COUNT) {
+            return Err(FsError::VolumeFull);
+        }
+        let (mut low, mut high) = (MIN_SECTORS, MAX_SECTOR_COUNT);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if fits(middle) {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+
+        // The partition gets stretched to the end of the last cylinder, so the volume that
+        // ends up on the disk can be a little larger than the minimum found here.
+        let disk_size = Disk::cylinder_aligned_size(FIRST_LBA, low)?;
+        let sector_count = (disk_size / 512) as u32 - FIRST_LBA;
+        let (cluster_size, used_clusters, _) = needed(sector_count);
+        Ok(Estimate {
+            disk_size,
+            sector_count,
+            cluster_size,
+            used_clusters,
+            total_clusters: u64::from(VBR::new(sector_count).get_cluster_count()),
+        })
+    }
+}
@@ -1,20 +1,32 @@
 use crate::disk::chs::CHS;
+use crate::fs::fat::FAT;
+use crate::fs::{FsError, Volume};
 use crate::partition::Partition;
 use crate::sector::Sector;
+use sha2::{Digest, Sha256};
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io::*;
 use std::path::PathBuf;
 
+pub use crate::disk::verify::{BootCheck, BootReport};
+
 pub mod chs;
+pub mod estimate;
+mod vhd;
+mod verify;
 
 #[cfg(test)]
 mod tests;
 
+/// Disks from this size on get an empty geometry from calculate_geometry(), they would need
+/// translation.
+pub(crate) const MAX_DISK_SIZE: usize = 528482304;
+
 /// A Disk is the holding structure for a collection of Sectors. It also
 /// represents the interface between what the emulator gets to see, and what is
 /// present as a file on the host computer.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Disk {
     pub(crate) bootcode: [u8; 446],
     pub(crate) geometry: CHS,
@@ -129,11 +141,128 @@ impl Disk {
         reader
             .read_exact(&mut partition)
             .expect("Failed to read partition entry.");
-        loaded_disk.push_partition(Partition::from_bytes(partition));
+        let mut partition = Partition::from_bytes(partition);
+
+        // Pick up the file system on the partition, as long as it's one we understand. The
+        // partition table only knows the size, so the boot record comes from the volume.
+        if let Some(volume) = loaded_disk.read_volume(&partition) {
+            partition.boot_record = volume.get_boot_record().clone();
+            partition.FAT = FAT::from_boot_record(&partition.boot_record);
+            partition.volume = Some(volume);
+        }
+        loaded_disk.push_partition(partition);
 
         return loaded_disk;
     }
 
+    /// Collect the sectors of a partition and try to load them as a FAT volume.
+    fn read_volume(&self, partition: &Partition) -> Option<Volume> {
+        let first = partition.first_lba as usize;
+        let last = first + partition.sector_count as usize;
+        if partition.sector_count == 0 || last > self.sectors.len() {
+            return None;
+        }
+        let mut data = Vec::<u8>::with_capacity(partition.sector_count as usize * 512);
+        for sector in &self.sectors[first..last] {
+            data.extend_from_slice(&sector.get_data());
+        }
+        Volume::from_bytes(data).ok()
+    }
+
+    /// Retrieve a reference to one of the partitions on this Disk, counting from 0.
+    pub fn get_partition(&self, index: usize) -> Option<&Partition> {
+        self.partitions.get(index)
+    }
+
+    /// Retrieve a mutable reference to one of the partitions on this Disk, counting from 0.
+    pub fn get_partition_mut(&mut self, index: usize) -> Option<&mut Partition> {
+        self.partitions.get_mut(index)
+    }
+
+    /// Grow or shrink the Disk to a new size in bytes. The geometry gets recalculated, the first
+    /// partition is stretched to the end of the last full cylinder just like Partition::new does,
+    /// and its file system gets resized along with it. Nothing changes when the file system
+    /// doesn't fit in the new size. Sizes the geometry can't express, under a cylinder or
+    /// from 504MB on, fail with InvalidSize before anything changes, as do sizes that leave
+    /// the partition too small or too large for a file system.
+    pub fn resize(&mut self, mut size: usize) -> std::result::Result<(), FsError> {
+        size = (size / 512) * 512;
+        let invalid = FsError::InvalidSize(u32::try_from(size / 512).unwrap_or(u32::MAX));
+        if size >= MAX_DISK_SIZE {
+            return Err(invalid);
+        }
+        let geometry = Disk::calculate_geometry(size);
+        if geometry.cylinder == 0 {
+            return Err(invalid);
+        }
+        let old_geometry = std::mem::replace(&mut self.geometry, geometry);
+        let last_sector = CHS::new(self.geometry.cylinder - 1, self.geometry.head - 1, 63);
+        let last_lba = self.chs_to_lba(&last_sector);
+        if let Some(partition) = self.partitions.first_mut() {
+            let sector_count = (last_lba + 1).saturating_sub(partition.first_lba);
+            let resized = match partition.volume.as_mut() {
+                Some(volume) => volume
+                    .resize(sector_count)
+                    .map(|_| volume.get_boot_record().clone()),
+                None => partition.boot_record.resized(sector_count),
+            };
+            let boot_record = match resized {
+                Ok(boot_record) => boot_record,
+                Err(error) => {
+                    self.geometry = old_geometry;
+                    return Err(error);
+                }
+            };
+            partition.set_boot_record(boot_record);
+            partition.first_sector = CHS::from_lba(&self.geometry, partition.first_lba);
+            partition.last_sector = last_sector;
+            partition.last_lba = last_lba;
+            partition.sector_count = sector_count;
+        }
+        self.size = size;
+        self.sector_count = size / 512;
+        self.sectors.truncate(self.sector_count);
+        self.build_bootsector();
+        Ok(())
+    }
+
+    /// Shrink the Disk to the smallest whole number of cylinders that still holds everything on
+    /// the file system of its first partition. Returns the new size in bytes.
+    pub fn shrink_to_fit(&mut self) -> std::result::Result<usize, FsError> {
+        let (first_lba, needed) = match self.partitions.first() {
+            Some(partition) => match partition.get_volume() {
+                Some(volume) => (partition.first_lba, volume.get_minimum_sector_count()?),
+                None => return Ok(self.size),
+            },
+            None => return Ok(self.size),
+        };
+        let size = Disk::cylinder_aligned_size(first_lba, needed)?;
+        self.resize(size)?;
+        Ok(size)
+    }
+
+    /// The smallest disk size in bytes, in whole cylinders, that holds a partition of a certain
+    /// number of sectors starting at first_lba. Partitions run up to the last full cylinder, so
+    /// this adds a track at a time until the geometry leaves enough room. Partitions that only
+    /// fit on a disk of 504MB or more fail with InvalidSize, like resize() does.
+    pub fn cylinder_aligned_size(
+        first_lba: u32,
+        sector_count: u32,
+    ) -> std::result::Result<usize, FsError> {
+        let needed = u64::from(first_lba) + u64::from(sector_count);
+        let mut sectors = needed;
+        while sectors * 512 < MAX_DISK_SIZE as u64 {
+            let geometry = Disk::calculate_geometry(sectors as usize * 512);
+            let cylinder_sectors = u64::from(geometry.head) * u64::from(geometry.sector);
+            let usable = u64::from(geometry.cylinder) * cylinder_sectors;
+            if usable >= needed {
+                return Ok(usable as usize * 512);
+            }
+            sectors += 63;
+        }
+        Err(FsError::InvalidSize(sector_count))
+    }
+
     /// Calculate the CHS geometry for a Disk struct based on its size in bytes.
     /// The calculation is based on what the Bochs BIOS expects.
     pub fn calculate_geometry(size: usize) -> CHS {
@@ -201,23 +330,63 @@ impl Disk {
 
     /// Commit the in-memory Disk struct to persistent storage.
     pub fn write(&mut self) {
-        let mut f = File::create(self.path.as_path()).expect("Failed to create file.");
+        let f = File::create(self.path.as_path()).expect("Failed to create file.");
         f.set_len(u64::try_from(self.size).unwrap())
             .expect("Failed to grow file to requested size.");
-        // Collect all bytes prior to writing
-        let mut disk_data = Vec::<u8>::with_capacity(self.size);
+        let mut writer = BufWriter::new(&f);
+        self.write_image(&mut writer)
+            .expect("Error writing Disk to file.");
+        writer.flush().expect("Error writing Disk to file.");
+        drop(writer);
         for sector in &mut self.sectors {
-            for byte in sector.get_data() {
-                disk_data.push(byte);
-            }
             sector.mark_clean();
         }
-        f.write_all(&disk_data)
-            .expect("Error writing Disk to file.");
         f.sync_all()
             .expect("Error commiting the Disk to persistent storage.");
     }
 
+    /// Stream the complete image into anything that implements Write, one sector at a time.
+    /// Sectors inside a formatted partition come from its volume, all others come from this
+    /// Disk's own sectors. Whatever is in neither place is written as zeroes.
+    pub fn write_image<W: Write>(&self, sink: &mut W) -> Result<()> {
+        let sector_count = self.sector_count.max(self.sectors.len());
+        for position in 0..sector_count {
+            if let Some(data) = self.get_volume_sector(position) {
+                sink.write_all(data)?;
+            } else if position < self.sectors.len() {
+                sink.write_all(&self.sectors[position].get_data())?;
+            } else {
+                sink.write_all(&[0u8; 512])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the data for a sector in the volume of whichever formatted partition covers it.
+    fn get_volume_sector(&self, position: usize) -> Option<&[u8]> {
+        for partition in &self.partitions {
+            if let Some(volume) = partition.get_volume() {
+                let first = partition.first_lba as usize;
+                let count = volume.as_bytes().len() / 512;
+                if position >= first && position < first + count {
+                    let offset = (position - first) * 512;
+                    return Some(&volume.as_bytes()[offset..offset + 512]);
+                }
+            }
+        }
+        None
+    }
+
+    /// Calculate the SHA-256 digest of the image exactly as write() puts it on disk, as a
+    /// lowercase hexadecimal string. Identical input gives an identical digest, which makes
+    /// it useful for checking builds in CI.
+    pub fn digest(&self) -> String {
+        let mut hasher = Sha256::new();
+        self.write_image(&mut hasher)
+            .expect("Failed to calculate the digest of the Disk.");
+        format!("{:x}", hasher.finalize())
+    }
+
     /// Generate a valid MBR boot sector and put it into this Disk's sector 0.
     pub fn build_bootsector(&mut self) {
         let mut bootsector = Sector::new(0);
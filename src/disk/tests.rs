@@ -1,7 +1,14 @@
+use crate::disk::estimate::{ContentTree, Estimate};
 use crate::disk::Disk;
 use crate::disk::CHS;
+use crate::emu::BootOutcome;
+use crate::fs::File;
+use crate::fs::FsError;
+use crate::fs::Timestamp;
+use crate::os::OsProfile;
 use crate::partition::Partition;
 use crate::sector::Sector;
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::PathBuf;
 
@@ -202,3 +209,381 @@ fn disk_build_bootsector() {
     let mut bootsector = my_disk.get_sector(0);
     assert_eq!(bootsector, &reference_sector);
 }
+
+/// Build a small formatted disk the way a reproducible build would.
+fn build_reproducible_disk(seed: &str) -> Disk {
+    let mut disk = Disk::new("reproducible_test_disk.raw", 10000000);
+    let mut partition = Partition::new(&disk, 1, 63, 0);
+    let volume = partition.format();
+    volume.set_source_date_epoch(Some(Timestamp::new(1994, 5, 31, 6, 22, 0)));
+    volume.set_serial_from_seed(seed);
+    volume
+        .add_file("", File::new("README.TXT".to_string(), b"Hello".to_vec()))
+        .unwrap();
+    disk.push_partition(partition);
+    disk
+}
+
+/// Two builds from the same input must hash to the same value, a different seed must not.
+#[test]
+fn disk_digest_is_reproducible() {
+    let first = build_reproducible_disk("doom");
+    let second = build_reproducible_disk("doom");
+    let third = build_reproducible_disk("heretic");
+    assert_eq!(first.digest(), second.digest());
+    assert_ne!(first.digest(), third.digest());
+    assert_eq!(first.digest().len(), 64);
+}
+
+/// The digest has to match what actually ends up in the image file.
+#[test]
+fn disk_digest_matches_written_file() {
+    let mut disk = build_reproducible_disk("doom");
+    disk.path = PathBuf::from("a3f1c06e5b2d4e7f8a9b0c1d2e3f4a5b_digest.raw");
+    disk.write();
+    let written = fs::read(&disk.path).unwrap();
+    fs::remove_file(&disk.path).unwrap();
+    let mut hasher = Sha256::new();
+    hasher.update(&written);
+    assert_eq!(format!("{:x}", hasher.finalize()), disk.digest());
+    assert_eq!(written.len(), disk.size);
+    assert_eq!(&written[63 * 512 + 510..63 * 512 + 512], &[0x55, 0xAA]);
+}
+
+/// Files put into an image must come back out after writing and loading it again.
+#[test]
+fn disk_load_reads_volume() {
+    let mut disk = build_reproducible_disk("doom");
+    disk.path = PathBuf::from("5b7c1e0a9d8f4e3c2b1a0f9e8d7c6b5a_load.raw");
+    disk.write();
+    let loaded = Disk::load("5b7c1e0a9d8f4e3c2b1a0f9e8d7c6b5a_load.raw");
+    fs::remove_file("5b7c1e0a9d8f4e3c2b1a0f9e8d7c6b5a_load.raw").unwrap();
+    let volume = loaded.get_partition(0).unwrap().get_volume().unwrap();
+    assert_eq!(
+        &loaded.get_partition(0).unwrap().boot_record,
+        disk.get_partition(0)
+            .unwrap()
+            .get_volume()
+            .unwrap()
+            .get_boot_record()
+    );
+    assert_eq!(volume.read_file("README.TXT").unwrap(), b"Hello");
+    let entries = volume.read_dir("\\").unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        entries[0].get_modified(),
+        Timestamp::new(1994, 5, 31, 6, 22, 0)
+    );
+}
+
+/// Growing a disk stretches its partition and file system, shrinking to fit takes it back
+/// down to whole cylinders without losing anything.
+#[test]
+fn disk_resize_and_shrink_to_fit() {
+    let mut disk = build_reproducible_disk("doom");
+    disk.resize(20000000).unwrap();
+    let partition = disk.get_partition(0).unwrap();
+    let volume = partition.get_volume().unwrap();
+    assert_eq!(
+        volume.as_bytes().len(),
+        partition.sector_count as usize * 512
+    );
+    assert_eq!(
+        partition.last_lba,
+        disk.geometry.cylinder as u32 * 16 * 63 - 1
+    );
+    assert_eq!(volume.read_file("README.TXT").unwrap(), b"Hello");
+    assert_eq!(partition.partition_type, 0x04);
+
+    let size = disk.shrink_to_fit().unwrap();
+    assert_eq!(size % (16 * 63 * 512), 0);
+    assert!(size < 10000000);
+    let mut image = Vec::<u8>::new();
+    disk.write_image(&mut image).unwrap();
+    assert_eq!(image.len(), size);
+    assert_eq!(image[0x1BE + 4], 0x04);
+    let volume = disk.get_partition(0).unwrap().get_volume().unwrap();
+    assert_eq!(volume.read_file("README.TXT").unwrap(), b"Hello");
+}
+
+/// Sizes the geometry or the file system can't handle fail without changing the disk.
+#[test]
+fn disk_resize_invalid_sizes() {
+    let mut disk = Disk::new("resize_invalid_test_disk.raw", 20000000);
+    let partition = Partition::new(&disk, 1, 63, 0);
+    disk.push_partition(partition);
+    let geometry = disk.geometry.clone();
+    assert_eq!(disk.resize(300_000), Err(FsError::InvalidSize(585)));
+    assert_eq!(disk.resize(528482304), Err(FsError::InvalidSize(1032192)));
+    assert_eq!(disk.resize(3_000_000), Err(FsError::InvalidSize(4977)));
+    assert_eq!(disk.geometry, geometry);
+    assert_eq!(disk.size, 19999744);
+    assert_eq!(disk.get_partition(0).unwrap().sector_count, 38241);
+}
+
+/// Rounding up to whole cylinders stops where the geometry does, at 504MB.
+#[test]
+fn cylinder_aligned_size_limits() {
+    assert_eq!(Disk::cylinder_aligned_size(63, 1000), Ok(2 * 16 * 63 * 512));
+    let size = Disk::cylinder_aligned_size(63, 1_000_000).unwrap();
+    assert!(size >= 1_000_063 * 512 && size < 528482304);
+    assert_eq!(
+        Disk::cylinder_aligned_size(63, 1032192 - 63),
+        Err(FsError::InvalidSize(1032192 - 63))
+    );
+    assert_eq!(
+        Disk::cylinder_aligned_size(63, 8_000_000),
+        Err(FsError::InvalidSize(8_000_000))
+    );
+}
+
+/// An estimated disk has to hold the OS and the content with the requested free space left.
+#[test]
+fn estimate_fits_content() {
+    let mut games = ContentTree::new();
+    for _ in 0..300 {
+        games.add_file(20000);
+    }
+    let mut tree = ContentTree::new();
+    tree.add_file(1000);
+    tree.add_dir(games);
+    let os = OsProfile::msdos622();
+    let estimate = Estimate::new(&tree, &os, 10).unwrap();
+    assert_eq!(estimate.disk_size % (16 * 63 * 512), 0);
+    assert!(estimate.total_clusters >= estimate.used_clusters * 110 / 100);
+
+    let mut disk = Disk::new("estimate_test_disk.raw", estimate.disk_size);
+    let mut partition = Partition::new(&disk, 1, 63, 0);
+    assert_eq!(partition.sector_count, estimate.sector_count);
+    let volume = partition.format();
+    for (name, data) in os.get_system_files() {
+        volume
+            .add_file("", File::new(name.to_string(), data.to_vec()))
+            .unwrap();
+    }
+    volume
+        .add_file("", File::new("README.TXT".to_string(), vec![0; 1000]))
+        .unwrap();
+    volume.create_dir("GAMES").unwrap();
+    for index in 0..300 {
+        let file = File::new(format!("GAME{}.DAT", index), vec![0; 20000]);
+        volume.add_file("GAMES", file).unwrap();
+    }
+    let used = volume.get_cluster_count() - volume.get_free_cluster_count();
+    assert_eq!(u64::from(used), estimate.used_clusters);
+    disk.push_partition(partition);
+
+    let larger = Estimate::new(&tree, &os, 200).unwrap();
+    assert!(larger.disk_size > estimate.disk_size);
+}
+
+/// The root directory can't grow, so too many entries in it can never fit.
+#[test]
+fn estimate_root_directory_limit() {
+    let mut tree = ContentTree::new();
+    for _ in 0..510 {
+        tree.add_file(1);
+    }
+    assert!(Estimate::new(&tree, &OsProfile::none(), 0).is_ok());
+    assert_eq!(
+        Estimate::new(&tree, &OsProfile::msdos622(), 0),
+        Err(FsError::DirectoryFull("\\".to_string()))
+    );
+    // Long names take more entries, and so does the volume label.
+    tree.add_named_file("Commander Keen.txt", 1);
+    assert_eq!(
+        Estimate::new(&tree, &OsProfile::none(), 0),
+        Err(FsError::DirectoryFull("\\".to_string()))
+    );
+}
+
+/// Content beyond what the largest disk under 504MB holds is an error, not a panic.
+#[test]
+fn estimate_too_large() {
+    let mut tree = ContentTree::new();
+    tree.add_file(700_000_000);
+    assert_eq!(
+        Estimate::new(&tree, &OsProfile::none(), 0),
+        Err(FsError::VolumeFull)
+    );
+    let mut tree = ContentTree::new();
+    tree.add_file(500_000_000);
+    let estimate = Estimate::new(&tree, &OsProfile::none(), 0).unwrap();
+    assert!(estimate.disk_size < 528482304);
+}
+
+/// Scanning the host skips symbolic links, so a link back up the tree doesn't loop forever.
+#[cfg(unix)]
+#[test]
+fn estimate_from_path_skips_symlinks() {
+    let host = std::env::temp_dir().join(format!("doscontainer-estimate-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&host);
+    fs::create_dir_all(host.join("GAMES")).unwrap();
+    fs::write(host.join("GAMES/KEEN4E.EXE"), vec![0; 100]).unwrap();
+    std::os::unix::fs::symlink(&host, host.join("GAMES/LOOP")).unwrap();
+    let tree = ContentTree::from_path(&host);
+    fs::remove_dir_all(&host).unwrap();
+    let mut expected = ContentTree::new();
+    let mut games = ContentTree::new();
+    games.add_file(100);
+    expected.add_dir(games);
+    assert_eq!(tree.unwrap(), expected);
+}
+
+/// Put the MS-DOS system files on a fresh disk in the order SYS.COM would.
+fn build_bootable_disk() -> Disk {
+    let mut disk = Disk::new("bootable_test_disk.raw", 10000000);
+    let mut partition = Partition::new(&disk, 1, 63, 0);
+    let volume = partition.format();
+    for (name, data) in OsProfile::msdos622().get_system_files() {
+        volume
+            .add_file("", File::new(name.to_string(), data.to_vec()))
+            .unwrap();
+    }
+    disk.push_partition(partition);
+    disk
+}
+
+/// A disk laid out like SYS.COM does it passes every check of the boot chain.
+#[test]
+fn verify_boot_passes() {
+    let disk = build_bootable_disk();
+    let report = disk.verify_boot();
+    assert!(report.is_bootable(), "{}", report);
+    let names: Vec<&str> = report.checks.iter().map(|check| check.name).collect();
+    assert_eq!(
+        names,
+        vec![
+            "MBR signature",
+            "MBR boot code",
+            "Active partition",
+            "Volume boot record",
+            "Hidden sectors",
+            "Geometry",
+            "Partition type",
+            "System files",
+            "IO.SYS placement",
+        ]
+    );
+    assert!(report.to_string().starts_with("PASS MBR signature"));
+}
+
+/// Each broken link in the chain shows up as a failed check that says what's wrong.
+#[test]
+fn verify_boot_failures() {
+    let mut disk = Disk::new("unbootable_test_disk.raw", 10000000);
+    disk.build_bootsector();
+    let report = disk.verify_boot();
+    let last = report.checks.last().unwrap();
+    assert_eq!(last.name, "Active partition");
+    assert_eq!(last.detail, "no partition is marked active");
+
+    let mut disk = build_reproducible_disk("doom");
+    let report = disk.verify_boot();
+    assert!(!report.is_bootable());
+    let check = report
+        .checks
+        .iter()
+        .find(|c| c.name == "System files")
+        .unwrap();
+    assert!(!check.passed);
+    assert_eq!(
+        check.detail,
+        "first root entries are README.TXT and nothing"
+    );
+
+    // IO.SYS grows after MSDOS.SYS took the cluster right behind it.
+    disk = build_bootable_disk();
+    let volume = disk.get_partition_mut(0).unwrap().get_volume_mut().unwrap();
+    let size = volume.get_entry("IO.SYS").unwrap().get_size();
+    let mut handle = volume.open("IO.SYS").unwrap();
+    handle.set_len(u64::from(size) + 10000).unwrap();
+    let report = disk.verify_boot();
+    let last = report.checks.last().unwrap();
+    assert_eq!(last.name, "IO.SYS placement");
+    assert_eq!(last.detail, "starts at cluster 2 but is fragmented");
+
+    // A boot sector copied from a partition that started somewhere else.
+    let volume = disk.get_partition_mut(0).unwrap().get_volume_mut().unwrap();
+    volume.data[28..32].copy_from_slice(&32u32.to_le_bytes());
+    let report = disk.verify_boot();
+    assert!(!report.checks[4].passed);
+    assert_eq!(
+        report.checks[4].detail,
+        "BPB says 32 sectors before the partition, the table says 63"
+    );
+
+    // A FAT16 volume behind a FAT12 partition type gets mounted with the wrong FAT.
+    disk = build_bootable_disk();
+    disk.get_partition_mut(0).unwrap().partition_type = 0x01;
+    disk.build_bootsector();
+    let report = disk.verify_boot();
+    assert!(!report.checks[6].passed);
+    assert_eq!(
+        report.checks[6].detail,
+        "the table says type 01h for a FAT16 volume of 19089 sectors"
+    );
+
+    disk.get_partition_mut(0).unwrap().flag_byte = 0x00;
+    disk.build_bootsector();
+    assert!(!disk.verify_boot().checks[2].passed);
+}
+
+/// The real MS-DOS 6.22 boot code gets all the way to IO.SYS handing over to DOS.
+#[test]
+fn boot_test_boots() {
+    let disk = build_bootable_disk();
+    let result = disk.boot_test();
+    assert_eq!(result.outcome, BootOutcome::Booted, "{}", result.screen);
+    assert!(result.instructions > 0);
+}
+
+/// When the boot code gives up, the outcome carries the message it printed.
+#[test]
+fn boot_test_failures() {
+    let disk = build_reproducible_disk("doom");
+    let result = disk.boot_test();
+    assert_eq!(
+        result.outcome,
+        BootOutcome::Failed("Non-System disk or disk error".to_string())
+    );
+    assert!(result
+        .screen
+        .contains("Replace and press any key when ready"));
+
+    let mut disk = build_bootable_disk();
+    disk.get_partition_mut(0).unwrap().flag_byte = 0x00;
+    disk.build_bootsector();
+    let result = disk.boot_test();
+    assert!(matches!(result.outcome, BootOutcome::Failed(_)));
+
+    let disk = Disk::new("empty_test_disk.raw", 10000000);
+    assert_eq!(
+        disk.boot_test().outcome,
+        BootOutcome::Failed("No bootable disk".to_string())
+    );
+}
+
+/// The VHD footer carries the size and geometry, and its checksum adds up.
+#[test]
+fn vhd_footer() {
+    let disk = build_reproducible_disk("doom");
+    let footer = disk.vhd_footer();
+    assert_eq!(&footer[0..8], b"conectix");
+    assert_eq!(&footer[48..56], &(disk.size as u64).to_be_bytes());
+    assert_eq!(&footer[56..60], &[0, 19, 16, 63]);
+    let checksum = u32::from_be_bytes(footer[64..68].try_into().unwrap());
+    let sum: u32 = footer
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !(64..68).contains(index))
+        .map(|(_, byte)| u32::from(*byte))
+        .sum();
+    assert_eq!(checksum, !sum);
+    assert_eq!(footer, build_reproducible_disk("doom").vhd_footer());
+
+    let mut image = Vec::new();
+    disk.write_vhd(&mut image).unwrap();
+    assert_eq!(image.len(), disk.size + 512);
+    assert_eq!(&image[disk.size..], &footer[..]);
+}
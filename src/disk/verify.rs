@@ -0,0 +1,235 @@
+use crate::disk::Disk;
+use crate::emu::BootResult;
+use crate::fs::vbr::{BootRecordProblem, VBR};
+use crate::fs::{DirEntry, Volume};
+use std::fmt;
+
+/// The raw names DOS looks for in the first two root directory slots.
+const IO_SYS: &[u8; 11] = b"IO      SYS";
+const MSDOS_SYS: &[u8; 11] = b"MSDOS   SYS";
+
+/// The outcome of a single step in the boot chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BootCheck {
+    /// Short name of what was checked.
+    pub name: &'static str,
+    pub passed: bool,
+    /// What was found, in a way that explains a failure.
+    pub detail: String,
+}
+
+/// Everything verify_boot() looked at, in the order the machine gets to it while booting.
+/// Checks stop at the first failure that makes the rest meaningless, like a missing active
+/// partition, so the last entry is the one to look at when an image doesn't boot.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BootReport {
+    pub checks: Vec<BootCheck>,
+}
+
+impl BootReport {
+    /// Whether every check passed.
+    pub fn is_bootable(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    fn push(&mut self, name: &'static str, passed: bool, detail: String) -> bool {
+        self.checks.push(BootCheck {
+            name,
+            passed,
+            detail,
+        });
+        passed
+    }
+}
+
+impl fmt::Display for BootReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for check in &self.checks {
+            let status = if check.passed { "PASS" } else { "FAIL" };
+            writeln!(f, "{} {}: {}", status, check.name, check.detail)?;
+        }
+        Ok(())
+    }
+}
+
+impl Disk {
+    /// Actually boot the image as write() would put it on disk, in a small interpreter that
+    /// runs the MBR, the boot sector and the loader at the start of IO.SYS. Where verify_boot()
+    /// can only say whether the pieces look right, this shows what the boot code makes of them,
+    /// including the message it prints when it gives up.
+    pub fn boot_test(&self) -> BootResult {
+        crate::emu::boot(&self.geometry, |lba| self.image_sector(lba as usize))
+    }
+
+    /// Check that the image as write() would put it on disk has everything it takes to boot
+    /// DOS, without actually booting it. This follows the chain the way the machine does: the
+    /// BIOS loads the MBR, which looks for the active partition and loads its boot sector. That
+    /// one uses the BPB to find IO.SYS and MSDOS.SYS in the first two root directory slots,
+    /// and loads IO.SYS from the start of the data area assuming it's in one piece.
+    pub fn verify_boot(&self) -> BootReport {
+        let mut report = BootReport::default();
+        let mbr = self.image_sector(0);
+        report.push(
+            "MBR signature",
+            mbr[0x1FE..0x200] == [0x55, 0xAA],
+            format!("sector 0 ends in {:02X?}", &mbr[0x1FE..0x200]),
+        );
+        let has_code = mbr[..446].iter().any(|byte| *byte != 0);
+        let detail = if has_code {
+            String::from("446 bytes of boot code present")
+        } else {
+            String::from("no boot code, the BIOS has nothing to run")
+        };
+        if !report.push("MBR boot code", has_code, detail) {
+            return report;
+        }
+
+        // Only the active partition gets booted, and the DOS MBR refuses to pick one when
+        // there are several. Any other value than 0x00 or 0x80 also makes it give up.
+        let entries: Vec<&[u8]> = (0..4)
+            .map(|index| &mbr[0x1BE + index * 16..0x1CE + index * 16])
+            .filter(|entry| entry[4] != 0)
+            .collect();
+        let active: Vec<usize> = (0..entries.len())
+            .filter(|index| entries[*index][0] == 0x80)
+            .collect();
+        let invalid = entries
+            .iter()
+            .any(|entry| ![0x00, 0x80].contains(&entry[0]));
+        let detail = match (active.as_slice(), invalid) {
+            (_, true) => String::from("a partition has an invalid boot flag"),
+            ([index], false) => format!("partition {} is active", index + 1),
+            ([], false) => String::from("no partition is marked active"),
+            (_, false) => format!("{} partitions are marked active", active.len()),
+        };
+        if !report.push("Active partition", active.len() == 1 && !invalid, detail) {
+            return report;
+        }
+        let entry = entries[active[0]];
+        let first_lba = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]);
+
+        // The MBR checks for the signature before it jumps into the boot sector.
+        let sector = self.image_sector(first_lba as usize);
+        let problems: Vec<BootRecordProblem> = VBR::diagnose(&sector)
+            .into_iter()
+            .filter(|problem| {
+                problem.is_fatal() || matches!(problem, BootRecordProblem::MissingSignature(_))
+            })
+            .collect();
+        let detail = match problems.first() {
+            Some(problem) => format!("sector {}: {}", first_lba, problem),
+            None => format!("sector {} holds a valid boot record", first_lba),
+        };
+        if !report.push("Volume boot record", problems.is_empty(), detail) {
+            return report;
+        }
+        let boot_record = VBR::from_bytes(&sector);
+
+        let hidden = boot_record.get_hidden_sectors_count();
+        report.push(
+            "Hidden sectors",
+            hidden == first_lba,
+            format!(
+                "BPB says {} sectors before the partition, the table says {}",
+                hidden, first_lba
+            ),
+        );
+        let (heads, sectors) = (
+            boot_record.get_heads_count(),
+            boot_record.get_sectors_per_track(),
+        );
+        report.push(
+            "Geometry",
+            heads == u16::from(self.geometry.head) && sectors == u16::from(self.geometry.sector),
+            format!(
+                "BPB has {} heads and {} sectors per track, the disk {} and {}",
+                heads, sectors, self.geometry.head, self.geometry.sector
+            ),
+        );
+
+        // DOS goes by the partition type to pick between 12 and 16 bit FAT entries, and 0x04
+        // only has room for the 16-bit sector count. The LBA type 0x0E works for FAT16 too.
+        let (accepted, fat): (&[u8], &str) = match boot_record.get_partition_type() {
+            0x01 => (&[0x01], "FAT12"),
+            0x04 => (&[0x04, 0x06, 0x0E], "FAT16"),
+            _ => (&[0x06, 0x0E], "FAT16"),
+        };
+        report.push(
+            "Partition type",
+            accepted.contains(&entry[4]),
+            format!(
+                "the table says type {:02X}h for a {} volume of {} sectors",
+                entry[4],
+                fat,
+                boot_record.get_volume_sectors_count()
+            ),
+        );
+
+        let volume = self
+            .partitions
+            .iter()
+            .find(|partition| partition.first_lba == first_lba)
+            .and_then(|partition| partition.get_volume());
+        match volume {
+            Some(volume) => Disk::verify_system_files(volume, &mut report),
+            None => {
+                report.push(
+                    "System files",
+                    false,
+                    String::from("the active partition has no file system"),
+                );
+            }
+        }
+        report
+    }
+
+    /// The boot sector only looks at the first two slots of the root directory, so the system
+    /// files have to be exactly there. Not even a volume label can go before them.
+    fn verify_system_files(volume: &Volume, report: &mut BootReport) {
+        let offset = volume.root_dir_offset();
+        let slots: Vec<DirEntry> = (0..2)
+            .map(|index| offset + index * 32)
+            .map(|offset| DirEntry::from_bytes(&volume.as_bytes()[offset..offset + 32]))
+            .collect();
+        let found: Vec<String> = slots
+            .iter()
+            .map(|entry| match entry.get_raw_name()[0] {
+                0x00 => String::from("nothing"),
+                _ => entry.get_short_name(),
+            })
+            .collect();
+        let in_place = &slots[0].get_raw_name() == IO_SYS
+            && &slots[1].get_raw_name() == MSDOS_SYS
+            && !slots[0].attributes.vol_id
+            && !slots[1].attributes.vol_id;
+        let detail = format!("first root entries are {} and {}", found[0], found[1]);
+        if !report.push("System files", in_place, detail) {
+            return;
+        }
+
+        let io_sys = &slots[0];
+        let chain = volume.get_chain(io_sys.get_first_cluster());
+        let contiguous = io_sys.get_first_cluster() == 2
+            && chain
+                .iter()
+                .enumerate()
+                .all(|(index, cluster)| usize::from(*cluster) == index + 2);
+        let detail = match (io_sys.get_first_cluster(), contiguous) {
+            (2, true) => format!("clusters 2 to {}", chain.len() + 1),
+            (2, false) => String::from("starts at cluster 2 but is fragmented"),
+            (first, _) => format!("starts at cluster {} instead of 2", first),
+        };
+        report.push("IO.SYS placement", contiguous, detail);
+    }
+
+    /// A sector of the image exactly as write_image() would produce it.
+    pub(crate) fn image_sector(&self, position: usize) -> Vec<u8> {
+        if let Some(data) = self.get_volume_sector(position) {
+            data.to_vec()
+        } else if position < self.sectors.len() {
+            self.sectors[position].get_data().to_vec()
+        } else {
+            vec![0u8; 512]
+        }
+    }
+}
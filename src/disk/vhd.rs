@@ -0,0 +1,47 @@
+use crate::disk::Disk;
+use std::io::{Result, Write};
+
+/// Fixed size VHD images are the raw image followed by this footer. Emulators like 86Box and
+/// Virtual PC look at the geometry in there instead of guessing it from the size.
+const COOKIE: &[u8; 8] = b"conectix";
+const CREATOR: &[u8; 4] = b"dosc";
+const FIXED_DISK: u32 = 2;
+
+impl Disk {
+    /// The 512 byte footer that turns the raw image into a fixed size VHD. Everything in it
+    /// follows from the image itself: the creation time is left at the VHD epoch of
+    /// January 1st, 2000 and the unique id comes from the digest of the image, so the same
+    /// image always gets the same footer.
+    pub fn vhd_footer(&self) -> [u8; 512] {
+        let size = (self.sector_count.max(self.sectors.len()) as u64) * 512;
+        let mut footer = [0u8; 512];
+        footer[0..8].copy_from_slice(COOKIE);
+        footer[8..12].copy_from_slice(&2u32.to_be_bytes());
+        footer[12..16].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        footer[16..24].copy_from_slice(&u64::MAX.to_be_bytes());
+        footer[28..32].copy_from_slice(CREATOR);
+        footer[32..36].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        footer[36..40].copy_from_slice(b"Wi2k");
+        footer[40..48].copy_from_slice(&size.to_be_bytes());
+        footer[48..56].copy_from_slice(&size.to_be_bytes());
+        footer[56..58].copy_from_slice(&self.geometry.cylinder.to_be_bytes());
+        footer[58] = self.geometry.head;
+        footer[59] = self.geometry.sector;
+        footer[60..64].copy_from_slice(&FIXED_DISK.to_be_bytes());
+        let digest = self.digest();
+        for (index, byte) in footer[68..84].iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&digest[index * 2..index * 2 + 2], 16).unwrap();
+        }
+        // The checksum is the one's complement of the sum of all other bytes.
+        let sum = footer.iter().fold(0u32, |sum, byte| sum + u32::from(*byte));
+        footer[64..68].copy_from_slice(&(!sum).to_be_bytes());
+        footer
+    }
+
+    /// Stream the image as a fixed size VHD: the raw image as write_image() produces it,
+    /// followed by the VHD footer.
+    pub fn write_vhd<W: Write>(&self, sink: &mut W) -> Result<()> {
+        self.write_image(sink)?;
+        sink.write_all(&self.vhd_footer())
+    }
+}
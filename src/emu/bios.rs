@@ -0,0 +1,157 @@
+use crate::emu::cpu::{AX, BP, BX, CF, CS, CX, DX, ES, ZF};
+use crate::emu::{BootOutcome, Machine};
+
+/// The only disk this BIOS knows about.
+const HARD_DISK: u8 = 0x80;
+
+impl Machine<'_> {
+    /// Handle BIOS interrupt `vector` and return to the caller like IRET does, except that the
+    /// carry and zero flags are the ones the handler left behind. That's how the BIOS reports
+    /// results.
+    pub(crate) fn bios(&mut self, vector: u8) {
+        match vector {
+            0x00 => self.stopped = Some(BootOutcome::Stuck(String::from("divide error"))),
+            0x10 => self.video(),
+            0x11 => self.regs[AX] = 0x0021,
+            0x12 => self.regs[AX] = 640,
+            0x13 => self.disk(),
+            // No extended services, and no waiting either.
+            0x15 => {
+                self.set_reg8(4, 0x86);
+                self.set_flag(CF, true);
+            }
+            0x16 => self.keyboard(),
+            0x18 | 0x19 => self.stop(),
+            0x1A if self.reg8(4) == 0 => {
+                self.regs[CX] = 0;
+                self.regs[DX] = 0;
+                self.set_reg8(0, 0);
+            }
+            _ => {}
+        }
+        let result = self.flags & (CF | ZF);
+        self.ip = self.pop();
+        self.sregs[CS] = self.pop();
+        let flags = self.pop();
+        self.flags = (flags & !(CF | ZF)) | result;
+    }
+
+    /// The boot code gave up. Whatever it printed last is the reason.
+    fn stop(&mut self) {
+        let outcome = match self.message() {
+            Some(message) => BootOutcome::Failed(message),
+            None => BootOutcome::Failed(String::from("No bootable disk")),
+        };
+        self.stopped = Some(outcome);
+    }
+
+    /// INT 10h. Text goes to the screen buffer, the rest pretends to be an 80 column color
+    /// text mode.
+    fn video(&mut self) {
+        match self.reg8(4) {
+            0x03 => {
+                self.regs[CX] = 0x0607;
+                self.regs[DX] = 0;
+            }
+            0x0E => {
+                let character = self.reg8(0);
+                self.print(character);
+            }
+            0x0F => {
+                self.set_reg8(0, 0x03);
+                self.set_reg8(4, 80);
+                self.set_reg8(7, 0);
+            }
+            0x13 => {
+                let (segment, mut offset) = (self.sregs[ES], self.regs[BP]);
+                for _ in 0..self.regs[CX] {
+                    let character = self.read8(segment, offset);
+                    self.print(character);
+                    // Modes 2 and 3 interleave the text with attributes.
+                    offset = offset.wrapping_add(if self.reg8(0) & 2 != 0 { 2 } else { 1 });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, character: u8) {
+        if character != b'\r' && character != 0x07 {
+            self.screen.push(character);
+        }
+    }
+
+    /// INT 16h. Nobody is at the keyboard, so waiting for a key means waiting forever.
+    fn keyboard(&mut self) {
+        match self.reg8(4) {
+            0x00 | 0x10 => self.stop(),
+            0x01 | 0x11 => self.set_flag(ZF, true),
+            _ => self.regs[AX] = 0,
+        }
+    }
+
+    /// INT 13h for the one hard disk, with just enough functions for the boot code. Sector
+    /// reads use CHS addressing and the disk geometry to find the LBA.
+    fn disk(&mut self) {
+        let function = self.reg8(4);
+        if self.reg8(2) != HARD_DISK {
+            return self.disk_status(0x01);
+        }
+        let (cylinders, heads, sectors) = self.geometry;
+        match function {
+            0x00 => self.disk_status(0x00),
+            0x02 => {
+                let count = self.reg8(0);
+                let sector = self.reg8(1) & 0x3F;
+                let cylinder = (u16::from(self.reg8(1) & 0xC0) << 2) | u16::from(self.reg8(5));
+                let head = self.reg8(6);
+                if count == 0
+                    || sector == 0
+                    || sector > sectors
+                    || head >= heads
+                    || cylinder >= cylinders
+                {
+                    self.set_reg8(0, 0);
+                    return self.disk_status(0x04);
+                }
+                let lba = (u32::from(cylinder) * u32::from(heads) + u32::from(head))
+                    * u32::from(sectors)
+                    + u32::from(sector)
+                    - 1;
+                let (segment, mut offset) = (self.sregs[ES], self.regs[BX]);
+                for index in 0..u32::from(count) {
+                    let data = (self.read_sector)(lba + index);
+                    for byte in data.iter().take(512) {
+                        self.write8(segment, offset, *byte);
+                        offset = offset.wrapping_add(1);
+                    }
+                }
+                self.disk_status(0x00);
+            }
+            0x08 => {
+                let last_cylinder = cylinders.saturating_sub(1).min(1023);
+                self.regs[BX] = 0;
+                self.set_reg8(1, (sectors & 0x3F) | ((last_cylinder >> 2) as u8 & 0xC0));
+                self.set_reg8(5, last_cylinder as u8);
+                self.set_reg8(6, heads.saturating_sub(1));
+                self.set_reg8(2, 1);
+                self.set_reg8(0, 0);
+                self.disk_status(0x00);
+            }
+            0x15 => {
+                let total = u32::from(cylinders) * u32::from(heads) * u32::from(sectors);
+                self.regs[CX] = (total >> 16) as u16;
+                self.regs[DX] = total as u16;
+                self.set_reg8(4, 0x03);
+                self.set_flag(CF, false);
+            }
+            // No LBA extensions, callers fall back to CHS.
+            _ => self.disk_status(0x01),
+        }
+    }
+
+    fn disk_status(&mut self, status: u8) {
+        self.set_reg8(4, status);
+        self.set_flag(CF, status != 0);
+    }
+}
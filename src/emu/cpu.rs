@@ -0,0 +1,962 @@
+use crate::emu::Machine;
+
+pub(crate) const AX: usize = 0;
+pub(crate) const CX: usize = 1;
+pub(crate) const DX: usize = 2;
+pub(crate) const BX: usize = 3;
+pub(crate) const SP: usize = 4;
+pub(crate) const BP: usize = 5;
+pub(crate) const SI: usize = 6;
+pub(crate) const DI: usize = 7;
+
+pub(crate) const ES: usize = 0;
+pub(crate) const CS: usize = 1;
+pub(crate) const SS: usize = 2;
+pub(crate) const DS: usize = 3;
+
+pub(crate) const CF: u16 = 0x0001;
+const PF: u16 = 0x0004;
+const AF: u16 = 0x0010;
+pub(crate) const ZF: u16 = 0x0040;
+const SF: u16 = 0x0080;
+const TF: u16 = 0x0100;
+const IF: u16 = 0x0200;
+const DF: u16 = 0x0400;
+const OF: u16 = 0x0800;
+
+/// Where the ModR/M byte of an instruction points: a register or a memory location.
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(usize),
+    Memory(u16, u16),
+}
+
+/// Prefixes that apply to the instruction being executed.
+#[derive(Default)]
+struct Prefixes {
+    segment: Option<usize>,
+    repeat: Option<u8>,
+}
+
+impl Machine<'_> {
+    pub(crate) fn read8(&self, segment: u16, offset: u16) -> u8 {
+        self.memory[Machine::linear(segment, offset)]
+    }
+
+    pub(crate) fn write8(&mut self, segment: u16, offset: u16, value: u8) {
+        let address = Machine::linear(segment, offset);
+        // The BIOS lives in ROM, everything below it is RAM.
+        if address < 0xF0000 {
+            self.memory[address] = value;
+        }
+    }
+
+    pub(crate) fn read16(&self, segment: u16, offset: u16) -> u16 {
+        u16::from_le_bytes([
+            self.read8(segment, offset),
+            self.read8(segment, offset.wrapping_add(1)),
+        ])
+    }
+
+    pub(crate) fn write16(&mut self, segment: u16, offset: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write8(segment, offset, low);
+        self.write8(segment, offset.wrapping_add(1), high);
+    }
+
+    /// Real mode addresses wrap around at 1MB, like they do with the A20 line off.
+    fn linear(segment: u16, offset: u16) -> usize {
+        ((usize::from(segment) << 4) + usize::from(offset)) & 0xFFFFF
+    }
+
+    fn fetch8(&mut self) -> u8 {
+        let value = self.read8(self.sregs[CS], self.ip);
+        self.ip = self.ip.wrapping_add(1);
+        value
+    }
+
+    fn fetch16(&mut self) -> u16 {
+        let value = self.read16(self.sregs[CS], self.ip);
+        self.ip = self.ip.wrapping_add(2);
+        value
+    }
+
+    pub(crate) fn push(&mut self, value: u16) {
+        self.regs[SP] = self.regs[SP].wrapping_sub(2);
+        self.write16(self.sregs[SS], self.regs[SP], value);
+    }
+
+    pub(crate) fn pop(&mut self) -> u16 {
+        let value = self.read16(self.sregs[SS], self.regs[SP]);
+        self.regs[SP] = self.regs[SP].wrapping_add(2);
+        value
+    }
+
+    pub(crate) fn set_flag(&mut self, flag: u16, value: bool) {
+        if value {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+
+    fn flag(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub(crate) fn reg8(&self, index: usize) -> u8 {
+        let [low, high] = self.regs[index & 3].to_le_bytes();
+        if index < 4 {
+            low
+        } else {
+            high
+        }
+    }
+
+    pub(crate) fn set_reg8(&mut self, index: usize, value: u8) {
+        let register = &mut self.regs[index & 3];
+        *register = if index < 4 {
+            (*register & 0xFF00) | u16::from(value)
+        } else {
+            (*register & 0x00FF) | (u16::from(value) << 8)
+        };
+    }
+
+    /// Run an interrupt the way the INT instruction does.
+    pub(crate) fn interrupt(&mut self, vector: u8) {
+        self.push(self.flags);
+        self.push(self.sregs[CS]);
+        self.push(self.ip);
+        self.set_flag(IF, false);
+        self.set_flag(TF, false);
+        let offset = u16::from(vector) * 4;
+        self.ip = self.read16(0, offset);
+        self.sregs[CS] = self.read16(0, offset + 2);
+    }
+
+    /// Decode a ModR/M byte into the register field and the operand it points at.
+    fn modrm(&mut self, prefixes: &Prefixes) -> (usize, Operand) {
+        let modrm = self.fetch8();
+        let (mode, reg, rm) = (modrm >> 6, usize::from((modrm >> 3) & 7), modrm & 7);
+        if mode == 3 {
+            return (reg, Operand::Register(usize::from(rm)));
+        }
+        let (base, segment) = match rm {
+            0 => (self.regs[BX].wrapping_add(self.regs[SI]), DS),
+            1 => (self.regs[BX].wrapping_add(self.regs[DI]), DS),
+            2 => (self.regs[BP].wrapping_add(self.regs[SI]), SS),
+            3 => (self.regs[BP].wrapping_add(self.regs[DI]), SS),
+            4 => (self.regs[SI], DS),
+            5 => (self.regs[DI], DS),
+            6 if mode == 0 => (0, DS),
+            6 => (self.regs[BP], SS),
+            _ => (self.regs[BX], DS),
+        };
+        let displacement = match (mode, rm) {
+            (0, 6) | (2, _) => self.fetch16(),
+            (1, _) => self.fetch8() as i8 as u16,
+            _ => 0,
+        };
+        let segment = prefixes.segment.unwrap_or(segment);
+        (
+            reg,
+            Operand::Memory(self.sregs[segment], base.wrapping_add(displacement)),
+        )
+    }
+
+    fn get(&self, operand: Operand, word: bool) -> u16 {
+        match (operand, word) {
+            (Operand::Register(index), true) => self.regs[index],
+            (Operand::Register(index), false) => u16::from(self.reg8(index)),
+            (Operand::Memory(segment, offset), true) => self.read16(segment, offset),
+            (Operand::Memory(segment, offset), false) => u16::from(self.read8(segment, offset)),
+        }
+    }
+
+    fn set(&mut self, operand: Operand, word: bool, value: u16) {
+        match (operand, word) {
+            (Operand::Register(index), true) => self.regs[index] = value,
+            (Operand::Register(index), false) => self.set_reg8(index, value as u8),
+            (Operand::Memory(segment, offset), true) => self.write16(segment, offset, value),
+            (Operand::Memory(segment, offset), false) => self.write8(segment, offset, value as u8),
+        }
+    }
+
+    fn get_reg(&self, index: usize, word: bool) -> u16 {
+        self.get(Operand::Register(index), word)
+    }
+
+    fn set_reg(&mut self, index: usize, word: bool, value: u16) {
+        self.set(Operand::Register(index), word, value)
+    }
+
+    /// Zero, sign and parity flags for a result.
+    fn set_szp(&mut self, value: u32, word: bool) {
+        let (mask, sign) = if word { (0xFFFF, 0x8000) } else { (0xFF, 0x80) };
+        self.set_flag(ZF, value & mask == 0);
+        self.set_flag(SF, value & sign != 0);
+        self.set_flag(PF, (value as u8).count_ones().is_multiple_of(2));
+    }
+
+    /// The eight arithmetic and logic operations of opcodes 00-3F and group 1, in opcode order:
+    /// ADD, OR, ADC, SBB, AND, SUB, XOR and CMP. The caller stores the result, except for CMP.
+    fn alu(&mut self, operation: u8, a: u16, b: u16, word: bool) -> u16 {
+        let (mask, sign) = if word {
+            (0xFFFFu32, 0x8000u32)
+        } else {
+            (0xFFu32, 0x80u32)
+        };
+        let (a, b) = (u32::from(a) & mask, u32::from(b) & mask);
+        let carry = u32::from(self.flag(CF));
+        let result = match operation {
+            0 | 2 => {
+                let carry = if operation == 2 { carry } else { 0 };
+                let result = a + b + carry;
+                self.set_flag(CF, result > mask);
+                self.set_flag(OF, (a ^ result) & (b ^ result) & sign != 0);
+                self.set_flag(AF, (a ^ b ^ result) & 0x10 != 0);
+                result
+            }
+            3 | 5 | 7 => {
+                let carry = if operation == 3 { carry } else { 0 };
+                let result = a.wrapping_sub(b).wrapping_sub(carry);
+                self.set_flag(CF, a < b + carry);
+                self.set_flag(OF, (a ^ b) & (a ^ result) & sign != 0);
+                self.set_flag(AF, (a ^ b ^ result) & 0x10 != 0);
+                result
+            }
+            _ => {
+                let result = match operation {
+                    1 => a | b,
+                    4 => a & b,
+                    _ => a ^ b,
+                };
+                self.set_flag(CF, false);
+                self.set_flag(OF, false);
+                self.set_flag(AF, false);
+                result
+            }
+        };
+        self.set_szp(result, word);
+        (result & mask) as u16
+    }
+
+    fn increment(&mut self, value: u16, word: bool, delta: i8) -> u16 {
+        let carry = self.flag(CF);
+        let result = if delta > 0 {
+            self.alu(0, value, 1, word)
+        } else {
+            self.alu(5, value, 1, word)
+        };
+        self.set_flag(CF, carry);
+        result
+    }
+
+    /// The shifts and rotates of group 2, in opcode order: ROL, ROR, RCL, RCR, SHL, SHR, SAL
+    /// and SAR. The count is masked to 5 bits like the 186 and later do.
+    fn shift(&mut self, operation: u8, value: u16, count: u8, word: bool) -> u16 {
+        let count = count & 0x1F;
+        if count == 0 {
+            return value;
+        }
+        let (bits, sign) = if word {
+            (16u32, 0x8000u32)
+        } else {
+            (8u32, 0x80u32)
+        };
+        let mask = (1u32 << bits) - 1;
+        let mut result = u32::from(value) & mask;
+        let mut carry = self.flag(CF);
+        for _ in 0..count {
+            match operation {
+                0 => {
+                    carry = result & sign != 0;
+                    result = ((result << 1) | u32::from(carry)) & mask;
+                }
+                1 => {
+                    carry = result & 1 != 0;
+                    result = (result >> 1) | (u32::from(carry) << (bits - 1));
+                }
+                2 => {
+                    let out = result & sign != 0;
+                    result = ((result << 1) | u32::from(carry)) & mask;
+                    carry = out;
+                }
+                3 => {
+                    let out = result & 1 != 0;
+                    result = (result >> 1) | (u32::from(carry) << (bits - 1));
+                    carry = out;
+                }
+                4 | 6 => {
+                    carry = result & sign != 0;
+                    result = (result << 1) & mask;
+                }
+                5 => {
+                    carry = result & 1 != 0;
+                    result >>= 1;
+                }
+                _ => {
+                    carry = result & 1 != 0;
+                    result = (result >> 1) | (result & sign);
+                }
+            }
+        }
+        self.set_flag(CF, carry);
+        let top = result & sign != 0;
+        let overflow = match operation {
+            0 | 2 | 4 | 6 => top != carry,
+            1 | 3 => top != (result & (sign >> 1) != 0),
+            5 => u32::from(value) & sign != 0,
+            _ => false,
+        };
+        self.set_flag(OF, overflow);
+        if operation >= 4 {
+            self.set_szp(result, word);
+        }
+        result as u16
+    }
+
+    fn condition(&self, code: u8) -> bool {
+        let result = match code >> 1 {
+            0 => self.flag(OF),
+            1 => self.flag(CF),
+            2 => self.flag(ZF),
+            3 => self.flag(CF) || self.flag(ZF),
+            4 => self.flag(SF),
+            5 => self.flag(PF),
+            6 => self.flag(SF) != self.flag(OF),
+            _ => self.flag(ZF) || (self.flag(SF) != self.flag(OF)),
+        };
+        result != (code & 1 == 1)
+    }
+
+    fn jump_relative(&mut self, displacement: u16) {
+        self.ip = self.ip.wrapping_add(displacement);
+    }
+
+    /// Execute a single instruction. Anything the interpreter doesn't know stops the machine
+    /// with a description of where that happened.
+    pub(crate) fn step(&mut self) -> Result<(), String> {
+        let start = (self.sregs[CS], self.ip);
+        let mut prefixes = Prefixes::default();
+        let mut opcode = self.fetch8();
+        loop {
+            match opcode {
+                0x26 | 0x2E | 0x36 | 0x3E => {
+                    prefixes.segment = Some(usize::from((opcode >> 3) & 3));
+                }
+                0xF2 | 0xF3 => prefixes.repeat = Some(opcode),
+                0xF0 => {}
+                _ => break,
+            }
+            opcode = self.fetch8();
+        }
+        let word = opcode & 1 == 1;
+        match opcode {
+            // ADD, OR, ADC, SBB, AND, SUB, XOR and CMP in all their forms.
+            0x00..=0x3F if opcode & 7 < 6 => {
+                let operation = opcode >> 3;
+                match opcode & 7 {
+                    0..=3 => {
+                        let (reg, operand) = self.modrm(&prefixes);
+                        let to_register = opcode & 2 != 0;
+                        let (a, b) = if to_register {
+                            (self.get_reg(reg, word), self.get(operand, word))
+                        } else {
+                            (self.get(operand, word), self.get_reg(reg, word))
+                        };
+                        let result = self.alu(operation, a, b, word);
+                        if operation != 7 {
+                            if to_register {
+                                self.set_reg(reg, word, result);
+                            } else {
+                                self.set(operand, word, result);
+                            }
+                        }
+                    }
+                    _ => {
+                        let b = if word {
+                            self.fetch16()
+                        } else {
+                            u16::from(self.fetch8())
+                        };
+                        let result = self.alu(operation, self.get_reg(AX, word), b, word);
+                        if operation != 7 {
+                            self.set_reg(AX, word, result);
+                        }
+                    }
+                }
+            }
+            0x06 | 0x0E | 0x16 | 0x1E => self.push(self.sregs[usize::from(opcode >> 3)]),
+            0x07 | 0x17 | 0x1F => self.sregs[usize::from(opcode >> 3)] = self.pop(),
+            0x27 | 0x2F => self.decimal_adjust(opcode == 0x2F),
+            0x37 | 0x3F => self.ascii_adjust(opcode == 0x3F),
+            0x40..=0x47 => {
+                let index = usize::from(opcode & 7);
+                self.regs[index] = self.increment(self.regs[index], true, 1);
+            }
+            0x48..=0x4F => {
+                let index = usize::from(opcode & 7);
+                self.regs[index] = self.increment(self.regs[index], true, -1);
+            }
+            0x50..=0x57 => {
+                // Since the 286, PUSH SP pushes the value from before the push.
+                let value = self.regs[usize::from(opcode & 7)];
+                self.push(value);
+            }
+            0x58..=0x5F => {
+                let value = self.pop();
+                self.regs[usize::from(opcode & 7)] = value;
+            }
+            0x60 => {
+                let sp = self.regs[SP];
+                for index in 0..8 {
+                    let value = if index == SP { sp } else { self.regs[index] };
+                    self.push(value);
+                }
+            }
+            0x61 => {
+                for index in (0..8).rev() {
+                    let value = self.pop();
+                    if index != SP {
+                        self.regs[index] = value;
+                    }
+                }
+            }
+            0x68 => {
+                let value = self.fetch16();
+                self.push(value);
+            }
+            0x6A => {
+                let value = self.fetch8() as i8 as u16;
+                self.push(value);
+            }
+            0x69 | 0x6B => {
+                let (reg, operand) = self.modrm(&prefixes);
+                let a = self.get(operand, true) as i16 as i32;
+                let b = if opcode == 0x69 {
+                    self.fetch16() as i16 as i32
+                } else {
+                    self.fetch8() as i8 as i32
+                };
+                let result = a * b;
+                self.regs[reg] = result as u16;
+                let overflow = result != i32::from(result as i16);
+                self.set_flag(CF, overflow);
+                self.set_flag(OF, overflow);
+            }
+            0x70..=0x7F => {
+                let displacement = self.fetch8() as i8 as u16;
+                if self.condition(opcode & 0x0F) {
+                    self.jump_relative(displacement);
+                }
+            }
+            0x80..=0x83 => {
+                let (operation, operand) = self.modrm(&prefixes);
+                let b = match opcode {
+                    0x81 => self.fetch16(),
+                    0x83 => self.fetch8() as i8 as u16,
+                    _ => u16::from(self.fetch8()),
+                };
+                let result = self.alu(operation as u8, self.get(operand, word), b, word);
+                if operation != 7 {
+                    self.set(operand, word, result);
+                }
+            }
+            0x84 | 0x85 => {
+                let (reg, operand) = self.modrm(&prefixes);
+                self.alu(4, self.get(operand, word), self.get_reg(reg, word), word);
+            }
+            0x86 | 0x87 => {
+                let (reg, operand) = self.modrm(&prefixes);
+                let value = self.get(operand, word);
+                self.set(operand, word, self.get_reg(reg, word));
+                self.set_reg(reg, word, value);
+            }
+            0x88..=0x8B => {
+                let (reg, operand) = self.modrm(&prefixes);
+                if opcode & 2 == 0 {
+                    self.set(operand, word, self.get_reg(reg, word));
+                } else {
+                    self.set_reg(reg, word, self.get(operand, word));
+                }
+            }
+            0x8C => {
+                let (reg, operand) = self.modrm(&prefixes);
+                self.set(operand, true, self.sregs[reg & 3]);
+            }
+            0x8D => {
+                let (reg, operand) = self.modrm(&prefixes);
+                match operand {
+                    Operand::Memory(_, offset) => self.regs[reg] = offset,
+                    Operand::Register(_) => return Err(self.invalid(start, opcode)),
+                }
+            }
+            0x8E => {
+                let (reg, operand) = self.modrm(&prefixes);
+                self.sregs[reg & 3] = self.get(operand, true);
+            }
+            0x8F => {
+                let (_, operand) = self.modrm(&prefixes);
+                let value = self.pop();
+                self.set(operand, true, value);
+            }
+            0x90..=0x97 => self.regs.swap(AX, usize::from(opcode & 7)),
+            0x98 => self.regs[AX] = self.reg8(0) as i8 as u16,
+            0x99 => {
+                self.regs[DX] = if self.regs[AX] & 0x8000 != 0 {
+                    0xFFFF
+                } else {
+                    0
+                }
+            }
+            0x9A => {
+                let offset = self.fetch16();
+                let segment = self.fetch16();
+                self.push(self.sregs[CS]);
+                self.push(self.ip);
+                self.sregs[CS] = segment;
+                self.ip = offset;
+            }
+            0x9B => {}
+            0x9C => self.push(self.flags),
+            0x9D => {
+                let value = self.pop();
+                self.set_flags(value);
+            }
+            0x9E => {
+                let value = u16::from(self.reg8(4)) & (SF | ZF | AF | PF | CF);
+                self.flags = (self.flags & !(SF | ZF | AF | PF | CF)) | value;
+            }
+            0x9F => self.set_reg8(4, self.flags as u8),
+            0xA0..=0xA3 => {
+                let offset = self.fetch16();
+                let segment = self.sregs[prefixes.segment.unwrap_or(DS)];
+                let operand = Operand::Memory(segment, offset);
+                if opcode & 2 == 0 {
+                    self.set_reg(AX, word, self.get(operand, word));
+                } else {
+                    self.set(operand, word, self.get_reg(AX, word));
+                }
+            }
+            0xA4..=0xA7 | 0xAA..=0xAF => self.string(opcode, &prefixes),
+            0xA8 | 0xA9 => {
+                let b = if word {
+                    self.fetch16()
+                } else {
+                    u16::from(self.fetch8())
+                };
+                self.alu(4, self.get_reg(AX, word), b, word);
+            }
+            0xB0..=0xB7 => {
+                let value = self.fetch8();
+                self.set_reg8(usize::from(opcode & 7), value);
+            }
+            0xB8..=0xBF => self.regs[usize::from(opcode & 7)] = self.fetch16(),
+            0xC0 | 0xC1 | 0xD0..=0xD3 => {
+                let (operation, operand) = self.modrm(&prefixes);
+                let count = match opcode {
+                    0xC0 | 0xC1 => self.fetch8(),
+                    0xD0 | 0xD1 => 1,
+                    _ => self.reg8(1),
+                };
+                let result = self.shift(operation as u8, self.get(operand, word), count, word);
+                self.set(operand, word, result);
+            }
+            0xC2 | 0xC3 => {
+                let release = if opcode == 0xC2 { self.fetch16() } else { 0 };
+                self.ip = self.pop();
+                self.regs[SP] = self.regs[SP].wrapping_add(release);
+            }
+            0xC4 | 0xC5 => {
+                let (reg, operand) = self.modrm(&prefixes);
+                let Operand::Memory(segment, offset) = operand else {
+                    return Err(self.invalid(start, opcode));
+                };
+                self.regs[reg] = self.read16(segment, offset);
+                let target = if opcode == 0xC4 { ES } else { DS };
+                self.sregs[target] = self.read16(segment, offset.wrapping_add(2));
+            }
+            0xC6 | 0xC7 => {
+                let (_, operand) = self.modrm(&prefixes);
+                let value = if word {
+                    self.fetch16()
+                } else {
+                    u16::from(self.fetch8())
+                };
+                self.set(operand, word, value);
+            }
+            0xC8 => {
+                let size = self.fetch16();
+                let level = self.fetch8() & 0x1F;
+                self.push(self.regs[BP]);
+                let frame = self.regs[SP];
+                for _ in 1..level {
+                    self.regs[BP] = self.regs[BP].wrapping_sub(2);
+                    let value = self.read16(self.sregs[SS], self.regs[BP]);
+                    self.push(value);
+                }
+                if level > 0 {
+                    self.push(frame);
+                }
+                self.regs[BP] = frame;
+                self.regs[SP] = self.regs[SP].wrapping_sub(size);
+            }
+            0xC9 => {
+                self.regs[SP] = self.regs[BP];
+                self.regs[BP] = self.pop();
+            }
+            0xCA | 0xCB => {
+                let release = if opcode == 0xCA { self.fetch16() } else { 0 };
+                self.ip = self.pop();
+                self.sregs[CS] = self.pop();
+                self.regs[SP] = self.regs[SP].wrapping_add(release);
+            }
+            0xCC => self.interrupt(3),
+            0xCD => {
+                let vector = self.fetch8();
+                self.interrupt(vector);
+            }
+            0xCE => {
+                if self.flag(OF) {
+                    self.interrupt(4);
+                }
+            }
+            0xCF => {
+                self.ip = self.pop();
+                self.sregs[CS] = self.pop();
+                let value = self.pop();
+                self.set_flags(value);
+            }
+            0xD4 => {
+                let base = self.fetch8();
+                let al = self.reg8(0);
+                match (al.checked_div(base), al.checked_rem(base)) {
+                    (Some(quotient), Some(remainder)) => {
+                        self.set_reg8(4, quotient);
+                        self.set_reg8(0, remainder);
+                        self.set_szp(u32::from(remainder), false);
+                    }
+                    _ => self.interrupt(0),
+                }
+            }
+            0xD5 => {
+                let base = self.fetch8();
+                let al = self.reg8(0).wrapping_add(self.reg8(4).wrapping_mul(base));
+                self.regs[AX] = u16::from(al);
+                self.set_szp(u32::from(al), false);
+            }
+            0xD6 => self.set_reg8(0, if self.flag(CF) { 0xFF } else { 0 }),
+            0xD7 => {
+                let segment = self.sregs[prefixes.segment.unwrap_or(DS)];
+                let offset = self.regs[BX].wrapping_add(u16::from(self.reg8(0)));
+                let value = self.read8(segment, offset);
+                self.set_reg8(0, value);
+            }
+            // Without a coprocessor, ESC instructions only have an operand to skip.
+            0xD8..=0xDF => {
+                self.modrm(&prefixes);
+            }
+            0xE0..=0xE3 => {
+                let displacement = self.fetch8() as i8 as u16;
+                let taken = if opcode == 0xE3 {
+                    self.regs[CX] == 0
+                } else {
+                    self.regs[CX] = self.regs[CX].wrapping_sub(1);
+                    self.regs[CX] != 0
+                        && match opcode {
+                            0xE0 => !self.flag(ZF),
+                            0xE1 => self.flag(ZF),
+                            _ => true,
+                        }
+                };
+                if taken {
+                    self.jump_relative(displacement);
+                }
+            }
+            // There's no hardware behind the I/O ports, reads float high like on an empty bus.
+            0xE4 | 0xE5 => {
+                self.fetch8();
+                self.set_reg(AX, word, 0xFFFF);
+            }
+            0xE6 | 0xE7 => {
+                self.fetch8();
+            }
+            0xEC | 0xED => self.set_reg(AX, word, 0xFFFF),
+            0xEE | 0xEF => {}
+            0xE8 => {
+                let displacement = self.fetch16();
+                self.push(self.ip);
+                self.jump_relative(displacement);
+            }
+            0xE9 => {
+                let displacement = self.fetch16();
+                self.jump_relative(displacement);
+            }
+            0xEA => {
+                let offset = self.fetch16();
+                self.sregs[CS] = self.fetch16();
+                self.ip = offset;
+            }
+            0xEB => {
+                let displacement = self.fetch8() as i8 as u16;
+                self.jump_relative(displacement);
+            }
+            0xF4 => return Err(format!("halted at {:04X}:{:04X}", start.0, start.1)),
+            0xF5 => self.flags ^= CF,
+            0xF6 | 0xF7 => self.group3(word, &prefixes)?,
+            0xF8 => self.set_flag(CF, false),
+            0xF9 => self.set_flag(CF, true),
+            0xFA => self.set_flag(IF, false),
+            0xFB => self.set_flag(IF, true),
+            0xFC => self.set_flag(DF, false),
+            0xFD => self.set_flag(DF, true),
+            0xFE => {
+                let (operation, operand) = self.modrm(&prefixes);
+                let delta = match operation {
+                    0 => 1,
+                    1 => -1,
+                    _ => return Err(self.invalid(start, opcode)),
+                };
+                let result = self.increment(self.get(operand, false), false, delta);
+                self.set(operand, false, result);
+            }
+            0xFF => {
+                let (operation, operand) = self.modrm(&prefixes);
+                match (operation, operand) {
+                    (0, _) | (1, _) => {
+                        let delta = if operation == 0 { 1 } else { -1 };
+                        let result = self.increment(self.get(operand, true), true, delta);
+                        self.set(operand, true, result);
+                    }
+                    (2, _) => {
+                        let target = self.get(operand, true);
+                        self.push(self.ip);
+                        self.ip = target;
+                    }
+                    (3, Operand::Memory(segment, offset)) => {
+                        self.push(self.sregs[CS]);
+                        self.push(self.ip);
+                        self.ip = self.read16(segment, offset);
+                        self.sregs[CS] = self.read16(segment, offset.wrapping_add(2));
+                    }
+                    (4, _) => self.ip = self.get(operand, true),
+                    (5, Operand::Memory(segment, offset)) => {
+                        self.ip = self.read16(segment, offset);
+                        self.sregs[CS] = self.read16(segment, offset.wrapping_add(2));
+                    }
+                    (6, _) => {
+                        let value = self.get(operand, true);
+                        self.push(value);
+                    }
+                    _ => return Err(self.invalid(start, opcode)),
+                }
+            }
+            _ => return Err(self.invalid(start, opcode)),
+        }
+        Ok(())
+    }
+
+    fn invalid(&self, start: (u16, u16), opcode: u8) -> String {
+        format!(
+            "unsupported instruction {:02X} at {:04X}:{:04X}",
+            opcode, start.0, start.1
+        )
+    }
+
+    /// Flags as a 286 keeps them in real mode: bit 1 always set, bits 12 to 15 always clear.
+    fn set_flags(&mut self, value: u16) {
+        self.flags = (value & 0x0FD5) | 0x0002;
+    }
+
+    /// TEST, NOT, NEG, MUL, IMUL, DIV and IDIV.
+    fn group3(&mut self, word: bool, prefixes: &Prefixes) -> Result<(), String> {
+        let (operation, operand) = self.modrm(prefixes);
+        let value = self.get(operand, word);
+        match operation {
+            0 | 1 => {
+                let b = if word {
+                    self.fetch16()
+                } else {
+                    u16::from(self.fetch8())
+                };
+                self.alu(4, value, b, word);
+            }
+            2 => self.set(operand, word, !value),
+            3 => {
+                let result = self.alu(5, 0, value, word);
+                self.set_flag(CF, value != 0);
+                self.set(operand, word, result);
+            }
+            4 if word => {
+                let result = u32::from(self.regs[AX]) * u32::from(value);
+                self.regs[AX] = result as u16;
+                self.regs[DX] = (result >> 16) as u16;
+                self.set_flag(CF, self.regs[DX] != 0);
+                self.set_flag(OF, self.regs[DX] != 0);
+            }
+            4 => {
+                let result = u16::from(self.reg8(0)) * value;
+                self.regs[AX] = result;
+                self.set_flag(CF, result > 0xFF);
+                self.set_flag(OF, result > 0xFF);
+            }
+            5 if word => {
+                let result = i32::from(self.regs[AX] as i16) * i32::from(value as i16);
+                self.regs[AX] = result as u16;
+                self.regs[DX] = (result >> 16) as u16;
+                let overflow = result != i32::from(result as i16);
+                self.set_flag(CF, overflow);
+                self.set_flag(OF, overflow);
+            }
+            5 => {
+                let result = i16::from(self.reg8(0) as i8) * i16::from(value as u8 as i8);
+                self.regs[AX] = result as u16;
+                let overflow = result != i16::from(result as i8);
+                self.set_flag(CF, overflow);
+                self.set_flag(OF, overflow);
+            }
+            6 if word => {
+                let dividend = (u32::from(self.regs[DX]) << 16) | u32::from(self.regs[AX]);
+                match dividend.checked_div(u32::from(value)) {
+                    Some(quotient) if quotient <= 0xFFFF => {
+                        self.regs[AX] = quotient as u16;
+                        self.regs[DX] = (dividend % u32::from(value)) as u16;
+                    }
+                    _ => self.interrupt(0),
+                }
+            }
+            6 => {
+                let dividend = self.regs[AX];
+                match dividend.checked_div(value) {
+                    Some(quotient) if quotient <= 0xFF => {
+                        self.set_reg8(0, quotient as u8);
+                        self.set_reg8(4, (dividend % value) as u8);
+                    }
+                    _ => self.interrupt(0),
+                }
+            }
+            7 if word => {
+                let dividend = ((u32::from(self.regs[DX]) << 16) | u32::from(self.regs[AX])) as i32;
+                match dividend.checked_div(i32::from(value as i16)) {
+                    Some(quotient) if quotient == i32::from(quotient as i16) => {
+                        self.regs[AX] = quotient as u16;
+                        self.regs[DX] = (dividend % i32::from(value as i16)) as u16;
+                    }
+                    _ => self.interrupt(0),
+                }
+            }
+            _ => {
+                let dividend = self.regs[AX] as i16;
+                match dividend.checked_div(i16::from(value as u8 as i8)) {
+                    Some(quotient) if quotient == i16::from(quotient as i8) => {
+                        self.set_reg8(0, quotient as u8);
+                        self.set_reg8(4, (dividend % i16::from(value as u8 as i8)) as u8);
+                    }
+                    _ => self.interrupt(0),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// MOVS, CMPS, STOS, LODS and SCAS, with or without a REP prefix. A repeated instruction
+    /// runs to completion in one go.
+    fn string(&mut self, opcode: u8, prefixes: &Prefixes) {
+        let word = opcode & 1 == 1;
+        let size: u16 = if word { 2 } else { 1 };
+        let delta = if self.flag(DF) {
+            size.wrapping_neg()
+        } else {
+            size
+        };
+        let source = self.sregs[prefixes.segment.unwrap_or(DS)];
+        loop {
+            if prefixes.repeat.is_some() {
+                if self.regs[CX] == 0 {
+                    break;
+                }
+                self.regs[CX] = self.regs[CX].wrapping_sub(1);
+            }
+            let (si, di, es) = (self.regs[SI], self.regs[DI], self.sregs[ES]);
+            let compares = matches!(opcode, 0xA6 | 0xA7 | 0xAE | 0xAF);
+            match opcode {
+                0xA4 | 0xA5 => {
+                    let value = self.get(Operand::Memory(source, si), word);
+                    self.set(Operand::Memory(es, di), word, value);
+                }
+                0xA6 | 0xA7 => {
+                    let a = self.get(Operand::Memory(source, si), word);
+                    let b = self.get(Operand::Memory(es, di), word);
+                    self.alu(7, a, b, word);
+                }
+                0xAA | 0xAB => self.set(Operand::Memory(es, di), word, self.get_reg(AX, word)),
+                0xAC | 0xAD => {
+                    let value = self.get(Operand::Memory(source, si), word);
+                    self.set_reg(AX, word, value);
+                }
+                _ => {
+                    let b = self.get(Operand::Memory(es, di), word);
+                    self.alu(7, self.get_reg(AX, word), b, word);
+                }
+            }
+            if matches!(opcode, 0xA4..=0xA7 | 0xAC | 0xAD) {
+                self.regs[SI] = si.wrapping_add(delta);
+            }
+            if !matches!(opcode, 0xAC | 0xAD) {
+                self.regs[DI] = di.wrapping_add(delta);
+            }
+            match prefixes.repeat {
+                None => break,
+                Some(0xF3) if compares && !self.flag(ZF) => break,
+                Some(0xF2) if compares && self.flag(ZF) => break,
+                _ => {}
+            }
+        }
+    }
+
+    /// DAA and DAS.
+    fn decimal_adjust(&mut self, subtract: bool) {
+        let mut al = self.reg8(0);
+        let old_al = al;
+        let old_carry = self.flag(CF);
+        if al & 0x0F > 9 || self.flag(AF) {
+            al = if subtract {
+                al.wrapping_sub(6)
+            } else {
+                al.wrapping_add(6)
+            };
+            self.set_flag(AF, true);
+        } else {
+            self.set_flag(AF, false);
+        }
+        if old_al > 0x99 || old_carry {
+            al = if subtract {
+                al.wrapping_sub(0x60)
+            } else {
+                al.wrapping_add(0x60)
+            };
+            self.set_flag(CF, true);
+        } else {
+            self.set_flag(CF, false);
+        }
+        self.set_reg8(0, al);
+        self.set_szp(u32::from(al), false);
+    }
+
+    /// AAA and AAS.
+    fn ascii_adjust(&mut self, subtract: bool) {
+        let adjust = self.reg8(0) & 0x0F > 9 || self.flag(AF);
+        if adjust {
+            if subtract {
+                self.regs[AX] = self.regs[AX].wrapping_sub(6);
+                self.set_reg8(4, self.reg8(4).wrapping_sub(1));
+            } else {
+                self.regs[AX] = self.regs[AX].wrapping_add(0x106);
+            }
+        }
+        self.set_flag(AF, adjust);
+        self.set_flag(CF, adjust);
+        self.set_reg8(0, self.reg8(0) & 0x0F);
+    }
+}
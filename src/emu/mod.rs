@@ -0,0 +1,167 @@
+use crate::disk::chs::CHS;
+use crate::fs::Codepage;
+
+mod bios;
+mod cpu;
+
+#[cfg(test)]
+mod tests;
+
+/// Where the boot sector loads the first sectors of IO.SYS, and where IO.SYS in turn puts the
+/// rest of itself before jumping there for the second time.
+const IO_SYS_SEGMENT: u16 = 0x0070;
+/// Interrupt vectors point at one byte each in this segment, so the BIOS stubs know which
+/// interrupt they're handling no matter how the code got there.
+const BIOS_SEGMENT: u16 = 0xF000;
+const BIOS_VECTORS: u16 = 0xE000;
+/// How many instructions a boot gets before it counts as stuck. MS-DOS 6.22 needs about five
+/// thousand to get to the point where DOS takes over, so this leaves plenty of room.
+const INSTRUCTION_BUDGET: u64 = 1_000_000;
+
+/// How a boot test ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BootOutcome {
+    /// IO.SYS loaded the rest of itself and jumped into it. From here on DOS runs the show.
+    Booted,
+    /// The boot code gave up with a message on screen, like "Non-System disk or disk error".
+    Failed(String),
+    /// The machine stopped doing anything useful without saying why: it halted, went into an
+    /// endless loop, ran into an instruction the interpreter doesn't know or used up its
+    /// instruction budget.
+    Stuck(String),
+}
+
+/// The result of running the boot chain of an image headlessly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BootResult {
+    pub outcome: BootOutcome,
+    /// Everything that was written through the INT 10h teletype functions.
+    pub screen: String,
+    /// Number of instructions executed, BIOS calls not included.
+    pub instructions: u64,
+}
+
+impl BootResult {
+    pub fn is_booted(&self) -> bool {
+        self.outcome == BootOutcome::Booted
+    }
+}
+
+/// A minimal PC with 1MB of memory, one hard disk and a BIOS that only does what the MS-DOS
+/// boot code needs: reading sectors through INT 13h and printing text through INT 10h.
+pub(crate) struct Machine<'a> {
+    pub(crate) regs: [u16; 8],
+    pub(crate) sregs: [u16; 4],
+    pub(crate) ip: u16,
+    pub(crate) flags: u16,
+    pub(crate) memory: Vec<u8>,
+    geometry: (u16, u8, u8),
+    read_sector: Box<dyn Fn(u32) -> Vec<u8> + 'a>,
+    screen: Vec<u8>,
+    /// Set by the BIOS when the boot code gives up, like when it waits for a key.
+    stopped: Option<BootOutcome>,
+    io_sys_entries: u32,
+    executed: u64,
+}
+
+/// Boot a hard disk the way a BIOS does: load sector 0 at 0000:7C00 and jump there with drive
+/// 80h in DL. Sectors are fetched through the closure, by LBA, and the geometry is what INT 13h
+/// reports and uses to turn CHS addresses back into LBA. This runs until IO.SYS takes over,
+/// the boot code fails or the machine gets stuck.
+pub fn boot<F: Fn(u32) -> Vec<u8>>(geometry: &CHS, read_sector: F) -> BootResult {
+    let mut machine = Machine {
+        regs: [0; 8],
+        sregs: [0; 4],
+        ip: 0x7C00,
+        flags: 0x0202,
+        memory: vec![0u8; 0x100000],
+        geometry: (geometry.cylinder, geometry.head, geometry.sector),
+        read_sector: Box::new(read_sector),
+        screen: Vec::new(),
+        stopped: None,
+        io_sys_entries: 0,
+        executed: 0,
+    };
+    machine.power_on();
+    let outcome = machine.run();
+    BootResult {
+        outcome,
+        screen: Codepage::Cp437.decode(&machine.screen),
+        instructions: machine.executed,
+    }
+}
+
+impl Machine<'_> {
+    /// Set up the interrupt vectors and the BIOS data area, then put the MBR in place.
+    fn power_on(&mut self) {
+        for vector in 0..256u16 {
+            let offset = usize::from(vector) * 4;
+            self.memory[offset..offset + 2].copy_from_slice(&(BIOS_VECTORS + vector).to_le_bytes());
+            self.memory[offset + 2..offset + 4].copy_from_slice(&BIOS_SEGMENT.to_le_bytes());
+        }
+        // Conventional memory in KB, and the number of hard disks.
+        self.memory[0x413..0x415].copy_from_slice(&640u16.to_le_bytes());
+        self.memory[0x475] = 1;
+        self.regs[cpu::DX] = 0x0080;
+        self.regs[cpu::SP] = 0x7C00;
+        let mbr = (self.read_sector)(0);
+        self.memory[0x7C00..0x7E00].copy_from_slice(&mbr[..512]);
+    }
+
+    fn run(&mut self) -> BootOutcome {
+        let mbr = &self.memory[0x7C00..0x7E00];
+        if mbr[510..512] != [0x55, 0xAA] {
+            return BootOutcome::Failed(String::from("No bootable disk"));
+        }
+        while self.executed < INSTRUCTION_BUDGET {
+            let (cs, ip, regs) = (self.sregs[cpu::CS], self.ip, self.regs);
+            if cs == BIOS_SEGMENT && (BIOS_VECTORS..BIOS_VECTORS + 256).contains(&ip) {
+                self.bios((ip - BIOS_VECTORS) as u8);
+            } else {
+                if cs == IO_SYS_SEGMENT && ip == 0 {
+                    // The first time it's the boot sector handing over to IO.SYS, the second
+                    // time it's IO.SYS jumping into the part it loaded itself.
+                    self.io_sys_entries += 1;
+                    if self.io_sys_entries == 2 {
+                        return BootOutcome::Booted;
+                    }
+                }
+                if let Err(reason) = self.step() {
+                    return self.give_up(reason);
+                }
+                self.executed += 1;
+                // Jumping to itself is how boot code hangs on purpose after an error. A loop
+                // instruction that counts down CX only looks the same.
+                if self.sregs[cpu::CS] == cs && self.ip == ip && self.regs == regs {
+                    return self.give_up(format!("endless loop at {:04X}:{:04X}", cs, ip));
+                }
+            }
+            if let Some(outcome) = self.stopped.take() {
+                return outcome;
+            }
+        }
+        BootOutcome::Stuck(format!(
+            "still running after {} instructions",
+            INSTRUCTION_BUDGET
+        ))
+    }
+
+    /// A machine that stopped after printing something failed with that message, otherwise
+    /// it's just stuck.
+    fn give_up(&self, reason: String) -> BootOutcome {
+        match self.message() {
+            Some(message) => BootOutcome::Failed(message),
+            None => BootOutcome::Stuck(reason),
+        }
+    }
+
+    /// The first line of text on the screen, which is where the boot code puts its errors.
+    fn message(&self) -> Option<String> {
+        Codepage::Cp437
+            .decode(&self.screen)
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(String::from)
+    }
+}
@@ -0,0 +1,97 @@
+use crate::disk::chs::CHS;
+use crate::emu::{boot, BootOutcome, BootResult};
+
+/// Boot a disk with `program` as its MBR and `second` in the sector after that.
+fn run(program: &[u8], second: &[u8]) -> BootResult {
+    let mut mbr = vec![0u8; 512];
+    mbr[..program.len()].copy_from_slice(program);
+    mbr[510..512].copy_from_slice(&[0x55, 0xAA]);
+    let mut next = vec![0u8; 512];
+    next[..second.len()].copy_from_slice(second);
+    boot(&CHS::new(2, 16, 63), |lba| match lba {
+        0 => mbr.clone(),
+        1 => next.clone(),
+        _ => vec![0u8; 512],
+    })
+}
+
+/// Print a zero terminated string through INT 10h and wait for a key, like boot code does
+/// when it gives up. `origin` is where the code ends up in memory.
+fn greeting(origin: u16, text: &str) -> Vec<u8> {
+    let message = origin + 18;
+    let mut code = vec![0xBE, message as u8, (message >> 8) as u8];
+    code.extend([
+        0xAC, 0x08, 0xC0, 0x74, 0x06, 0xB4, 0x0E, 0xCD, 0x10, 0xEB, 0xF5,
+    ]);
+    code.extend([0x31, 0xC0, 0xCD, 0x16]);
+    code.extend(text.bytes());
+    code.push(0);
+    code
+}
+
+/// A boot sector that prints and waits for a key fails with what it printed.
+#[test]
+fn boot_message() {
+    let result = run(&greeting(0x7C00, "Missing operating system\r\n"), &[]);
+    assert_eq!(
+        result.outcome,
+        BootOutcome::Failed("Missing operating system".to_string())
+    );
+    assert_eq!(result.screen, "Missing operating system\n");
+}
+
+/// Arithmetic, the stack and loops: print 1234 one decimal digit at a time.
+#[test]
+fn boot_arithmetic() {
+    let program = [
+        0xB8, 0xD2, 0x04, 0xBB, 0x0A, 0x00, 0x31, 0xC9, 0x31, 0xD2, 0xF7, 0xF3, 0x52, 0x41, 0x85,
+        0xC0, 0x75, 0xF6, 0x58, 0x04, 0x30, 0xB4, 0x0E, 0xCD, 0x10, 0xE2, 0xF7, 0xCD, 0x19,
+    ];
+    assert_eq!(
+        run(&program, &[]).outcome,
+        BootOutcome::Failed("1234".to_string())
+    );
+}
+
+/// INT 13h reads the second sector to 0000:8000 and the code there runs.
+#[test]
+fn boot_disk_read() {
+    let program = [
+        0xB8, 0x01, 0x02, 0xBB, 0x00, 0x80, 0xB9, 0x02, 0x00, 0xB6, 0x00, 0xCD, 0x13, 0x72, 0x05,
+        0xEA, 0x00, 0x80, 0x00, 0x00, 0xF4,
+    ];
+    let result = run(&program, &greeting(0x8000, "Loaded"));
+    assert_eq!(result.outcome, BootOutcome::Failed("Loaded".to_string()));
+
+    // Sector 64 doesn't exist on a track of 63 sectors.
+    let mut bad = program;
+    bad[7] = 0x40;
+    assert_eq!(
+        run(&bad, &[]).outcome,
+        BootOutcome::Stuck("halted at 0000:7C14".to_string())
+    );
+}
+
+/// Code that stops without saying anything is stuck, with the reason.
+#[test]
+fn boot_stuck() {
+    let stuck = |program: &[u8]| match run(program, &[]).outcome {
+        BootOutcome::Stuck(reason) => reason,
+        outcome => panic!("{:?}", outcome),
+    };
+    assert_eq!(stuck(&[0xF4]), "halted at 0000:7C00");
+    assert_eq!(stuck(&[0x90, 0xEB, 0xFE]), "endless loop at 0000:7C01");
+    assert_eq!(stuck(&[0x31, 0xDB, 0xF7, 0xF3]), "divide error");
+    assert_eq!(stuck(&[0x0F]), "unsupported instruction 0F at 0000:7C00");
+}
+
+/// Without the boot signature the BIOS doesn't even try.
+#[test]
+fn boot_without_signature() {
+    let result = boot(&CHS::new(2, 16, 63), |_| vec![0u8; 512]);
+    assert_eq!(
+        result.outcome,
+        BootOutcome::Failed("No bootable disk".to_string())
+    );
+    assert_eq!(result.instructions, 0);
+}
@@ -0,0 +1,311 @@
+use crate::fs::{DirEntry, File, Volume};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A single inconsistency found on a volume, roughly the things CHKDSK complains about.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    /// One of the extra FAT copies disagrees with the first one on these clusters.
+    FatMismatch { copy: usize, clusters: Vec<u16> },
+    /// Clusters that are marked in use but don't belong to any file or directory.
+    LostClusters { clusters: Vec<u16> },
+    /// A cluster that's part of the chains of more than one file or directory.
+    CrossLinked { cluster: u16, paths: Vec<String> },
+    /// The size in the directory entry doesn't match the length of the cluster chain.
+    SizeMismatch {
+        path: String,
+        size: u32,
+        clusters: usize,
+    },
+    /// The short name in the directory entry isn't a valid 8.3 name.
+    InvalidName { path: String, name: [u8; 11] },
+    /// The size of the file system disagrees with the size of its partition.
+    SectorCountMismatch { partition: u32, boot_record: u32 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::FatMismatch { copy, clusters } => write!(
+                f,
+                "FAT copy {} differs from the first FAT in {} entries",
+                copy + 1,
+                clusters.len()
+            ),
+            Problem::LostClusters { clusters } => {
+                write!(f, "{} lost clusters found", clusters.len())
+            }
+            Problem::CrossLinked { cluster, paths } => {
+                write!(
+                    f,
+                    "{} are cross-linked on cluster {}",
+                    paths.join(" and "),
+                    cluster
+                )
+            }
+            Problem::SizeMismatch {
+                path,
+                size,
+                clusters,
+            } => write!(
+                f,
+                "{} has a size of {} bytes but a chain of {} clusters",
+                path, size, clusters
+            ),
+            Problem::InvalidName { path, name } => {
+                write!(f, "{} has an invalid short name: {:02X?}", path, name)
+            }
+            Problem::SectorCountMismatch {
+                partition,
+                boot_record,
+            } => write!(
+                f,
+                "partition holds {} sectors but the boot record says {}",
+                partition, boot_record
+            ),
+        }
+    }
+}
+
+/// A problem together with whether repair mode fixed it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub problem: Problem,
+    pub repaired: bool,
+}
+
+/// The outcome of checking a volume: everything that was found, plus some statistics
+/// in the spirit of what CHKDSK prints at the end.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CheckReport {
+    pub findings: Vec<Finding>,
+    pub files: usize,
+    pub directories: usize,
+    pub used_clusters: u32,
+    pub free_clusters: u32,
+}
+
+impl CheckReport {
+    /// Whether the volume turned out to be completely consistent.
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Whether there are problems left that repair mode didn't or couldn't fix.
+    pub fn has_unrepaired(&self) -> bool {
+        self.findings.iter().any(|finding| !finding.repaired)
+    }
+
+    pub(crate) fn push(&mut self, problem: Problem, repaired: bool) {
+        self.findings.push(Finding { problem, repaired });
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for finding in &self.findings {
+            let status = if finding.repaired { "fixed" } else { "error" };
+            writeln!(f, "[{}] {}", status, finding.problem)?;
+        }
+        writeln!(
+            f,
+            "{} files in {} directories",
+            self.files, self.directories
+        )?;
+        write!(
+            f,
+            "{} clusters in use, {} clusters free",
+            self.used_clusters, self.free_clusters
+        )
+    }
+}
+
+/// A file or directory that was found while walking the tree, with the slot its short
+/// entry lives in so that repairs can update it.
+struct Visited {
+    path: String,
+    offset: usize,
+    is_dir: bool,
+    size: u32,
+    chain: Vec<u16>,
+}
+
+impl Volume {
+    /// Check the volume for inconsistencies, like CHKDSK does. With repair enabled the problems
+    /// that have a safe fix get fixed on the spot: FAT copies are synchronized with the first,
+    /// lost clusters are freed, and sizes and chains are brought in line with each other.
+    /// Cross-linked chains and invalid names are only ever reported.
+    pub fn check(&mut self, repair: bool) -> CheckReport {
+        let mut report = CheckReport::default();
+        self.check_fat_copies(repair, &mut report);
+
+        let visited = self.walk_tree(&mut report);
+        let mut owners = HashMap::<u16, Vec<String>>::new();
+        for item in &visited {
+            for cluster in &item.chain {
+                owners.entry(*cluster).or_default().push(item.path.clone());
+            }
+            if item.is_dir {
+                report.directories += 1;
+            } else {
+                report.files += 1;
+            }
+        }
+        let mut cross_linked: Vec<(&u16, &Vec<String>)> =
+            owners.iter().filter(|(_, paths)| paths.len() > 1).collect();
+        cross_linked.sort();
+        for (cluster, paths) in cross_linked {
+            let problem = Problem::CrossLinked {
+                cluster: *cluster,
+                paths: paths.clone(),
+            };
+            report.push(problem, false);
+        }
+
+        for item in visited.iter().filter(|item| !item.is_dir) {
+            self.check_size(item, repair, &mut report);
+        }
+        self.check_lost_clusters(repair, &mut report);
+
+        report.free_clusters = self.get_free_cluster_count();
+        report.used_clusters = self.get_cluster_count() - report.free_clusters;
+        report
+    }
+
+    /// Compare every extra copy of the FAT against the first one.
+    fn check_fat_copies(&mut self, repair: bool, report: &mut CheckReport) {
+        let fat_bytes = usize::from(self.get_boot_record().get_sectors_per_fat())
+            * usize::from(self.get_boot_record().get_bytes_per_sector());
+        for copy in 1..usize::from(self.get_boot_record().get_fats_count()) {
+            let clusters: Vec<u16> = (0..self.get_cluster_count() + 2)
+                .map(|cluster| cluster as u16)
+                .filter(|cluster| {
+                    self.get_fat_entry_in(copy, *cluster) != self.get_fat_entry(*cluster)
+                })
+                .collect();
+            if clusters.is_empty() {
+                continue;
+            }
+            if repair {
+                let first = self.fat_offset(0);
+                let target = self.fat_offset(copy);
+                self.data.copy_within(first..first + fat_bytes, target);
+            }
+            report.push(Problem::FatMismatch { copy, clusters }, repair);
+        }
+    }
+
+    /// Walk the whole directory tree and collect every file and directory with its chain.
+    /// Invalid names are reported along the way. Directories are only entered once, so a
+    /// damaged tree that loops back on itself can't send this off forever.
+    fn walk_tree(&self, report: &mut CheckReport) -> Vec<Visited> {
+        let mut visited = Vec::<Visited>::new();
+        let mut pending = vec![(0u16, String::new())];
+        let mut entered = vec![0u16];
+        while let Some((dir_cluster, dir_path)) = pending.pop() {
+            for located in self.locate_dir_entries(dir_cluster) {
+                let entry = &located.entry;
+                if entry.is_dot_entry() || entry.attributes.vol_id {
+                    continue;
+                }
+                let path = format!("{}\\{}", dir_path, entry.get_short_name());
+                if !File::validate_name(entry.short_name) {
+                    let problem = Problem::InvalidName {
+                        path: path.clone(),
+                        name: entry.short_name,
+                    };
+                    report.push(problem, false);
+                }
+                let chain = self.get_chain(entry.first_cluster);
+                if entry.is_dir()
+                    && self.is_valid_cluster(entry.first_cluster)
+                    && !entered.contains(&entry.first_cluster)
+                {
+                    entered.push(entry.first_cluster);
+                    pending.push((entry.first_cluster, path.clone()));
+                }
+                visited.push(Visited {
+                    path,
+                    offset: located.get_offset(),
+                    is_dir: entry.is_dir(),
+                    size: entry.size,
+                    chain,
+                });
+            }
+        }
+        visited
+    }
+
+    /// Compare the size of a file with the length of its chain. Repair frees clusters
+    /// beyond the end of the file, or cuts the size down to what the chain can hold.
+    fn check_size(&mut self, item: &Visited, repair: bool, report: &mut CheckReport) {
+        let cluster_size = self.get_cluster_size();
+        let needed = num::integer::div_ceil(item.size as usize, cluster_size);
+        if needed == item.chain.len() {
+            return;
+        }
+        if repair {
+            let mut entry = DirEntry::from_bytes(&self.data[item.offset..item.offset + 32]);
+            if needed < item.chain.len() {
+                for cluster in &item.chain[needed..] {
+                    self.set_fat_entry(*cluster, 0);
+                }
+                self.link_chain(&item.chain[..needed]);
+                if needed == 0 {
+                    entry.first_cluster = 0;
+                }
+            } else {
+                entry.size = (item.chain.len() * cluster_size) as u32;
+                if item.chain.is_empty() {
+                    entry.first_cluster = 0;
+                }
+            }
+            self.write_entry(item.offset, &entry);
+        }
+        let problem = Problem::SizeMismatch {
+            path: item.path.clone(),
+            size: item.size,
+            clusters: item.chain.len(),
+        };
+        report.push(problem, repair);
+    }
+
+    /// Find clusters that are allocated in the FAT without anything pointing at them.
+    /// This runs after the size repairs, so clusters freed there don't show up as lost.
+    fn check_lost_clusters(&mut self, repair: bool, report: &mut CheckReport) {
+        let mut reachable = vec![false; self.get_cluster_count() as usize + 2];
+        let mut pending = vec![0u16];
+        while let Some(dir_cluster) = pending.pop() {
+            for entry in self.read_dir_at(dir_cluster) {
+                if entry.is_dot_entry() || entry.attributes.vol_id {
+                    continue;
+                }
+                let first = entry.first_cluster;
+                let already = self.is_valid_cluster(first) && reachable[usize::from(first)];
+                for cluster in self.get_chain(first) {
+                    reachable[usize::from(cluster)] = true;
+                }
+                if entry.is_dir() && self.is_valid_cluster(first) && !already {
+                    pending.push(first);
+                }
+            }
+        }
+        let bad_cluster = self.bad_cluster();
+        let lost: Vec<u16> = (2..self.get_cluster_count() + 2)
+            .map(|cluster| cluster as u16)
+            .filter(|cluster| {
+                let value = self.get_fat_entry(*cluster);
+                value != 0 && value != bad_cluster && !reachable[usize::from(*cluster)]
+            })
+            .collect();
+        if lost.is_empty() {
+            return;
+        }
+        if repair {
+            for cluster in &lost {
+                self.set_fat_entry(*cluster, 0);
+            }
+        }
+        report.push(Problem::LostClusters { clusters: lost }, repair);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::sector::Sector;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Cluster {
     value: u16,
     sectors: Vec<Sector>,
@@ -0,0 +1,144 @@
+use crate::fs::{FsError, INVALID_CHARS};
+
+/// The upper half of code page 437, the original IBM PC character set used by US releases.
+const CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+/// The upper half of code page 850, the multilingual Latin-1 set used by western European
+/// releases. It gives up most of the line drawing characters of 437 for accented capitals.
+const CP850: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '®', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À', '©', '╣', '║', '╗', '╝', '¢', '¥', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã', '╚', '╔', '╩', '╦', '╠', '═', '╬', '¤', //
+    'ð', 'Ð', 'Ê', 'Ë', 'È', 'ı', 'Í', 'Î', 'Ï', '┘', '┌', '█', '▄', '¦', 'Ì', '▀', //
+    'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ', 'Þ', 'Ú', 'Û', 'Ù', 'ý', 'Ý', '¯', '´', //
+    '\u{AD}', '±', '‗', '¾', '¶', '§', '÷', '¸', '°', '¨', '·', '¹', '³', '²', '■', '\u{A0}',
+];
+
+/// The upper half of code page 866, the Cyrillic set used by Russian releases.
+const CP866: [char; 128] = [
+    'А', 'Б', 'В', 'Г', 'Д', 'Е', 'Ж', 'З', 'И', 'Й', 'К', 'Л', 'М', 'Н', 'О', 'П', //
+    'Р', 'С', 'Т', 'У', 'Ф', 'Х', 'Ц', 'Ч', 'Ш', 'Щ', 'Ъ', 'Ы', 'Ь', 'Э', 'Ю', 'Я', //
+    'а', 'б', 'в', 'г', 'д', 'е', 'ж', 'з', 'и', 'й', 'к', 'л', 'м', 'н', 'о', 'п', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'р', 'с', 'т', 'у', 'ф', 'х', 'ц', 'ч', 'ш', 'щ', 'ъ', 'ы', 'ь', 'э', 'ю', 'я', //
+    'Ё', 'ё', 'Є', 'є', 'Ї', 'ї', 'Ў', 'ў', '°', '∙', '·', '√', '№', '¤', '■', '\u{A0}',
+];
+
+/// The OEM code page that short names and volume labels are stored in. DOS has no idea
+/// which one a volume was written with, so this has to match the DOS release that's going
+/// to use it. The lower half is plain ASCII in all of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Codepage {
+    /// United States, the default for MS-DOS.
+    #[default]
+    Cp437,
+    /// Multilingual Latin-1, western European releases.
+    Cp850,
+    /// Cyrillic, Russian releases.
+    Cp866,
+}
+
+impl Codepage {
+    /// Look up a code page by its number, like the one in COUNTRY.SYS or CHCP.
+    pub fn from_number(number: u16) -> Option<Codepage> {
+        match number {
+            437 => Some(Codepage::Cp437),
+            850 => Some(Codepage::Cp850),
+            866 => Some(Codepage::Cp866),
+            _ => None,
+        }
+    }
+
+    pub fn get_number(&self) -> u16 {
+        match self {
+            Codepage::Cp437 => 437,
+            Codepage::Cp850 => 850,
+            Codepage::Cp866 => 866,
+        }
+    }
+
+    fn table(&self) -> &'static [char; 128] {
+        match self {
+            Codepage::Cp437 => &CP437,
+            Codepage::Cp850 => &CP850,
+            Codepage::Cp866 => &CP866,
+        }
+    }
+
+    /// Turn bytes as they're stored on disk into a string.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        bytes
+            .iter()
+            .map(|byte| match byte {
+                0x00..=0x7F => char::from(*byte),
+                _ => self.table()[usize::from(byte - 0x80)],
+            })
+            .collect()
+    }
+
+    /// Find the byte for a single character, if this code page has it.
+    pub fn encode_char(&self, character: char) -> Option<u8> {
+        if character.is_ascii() {
+            return Some(character as u8);
+        }
+        self.table()
+            .iter()
+            .position(|candidate| *candidate == character)
+            .map(|index| index as u8 + 0x80)
+    }
+
+    /// Find the byte for the uppercase form of a character, which is how DOS stores every
+    /// letter in a short name. Characters whose uppercase form is missing from this code page,
+    /// like the á in 437, have no place in a short name. Those that only turn into several
+    /// letters, like ß, stay as they are.
+    pub(crate) fn encode_upper(&self, character: char) -> Option<u8> {
+        let mut upper = character.to_uppercase();
+        match (upper.next(), upper.next()) {
+            (Some(upper), None) => self.encode_char(upper),
+            _ => self.encode_char(character),
+        }
+    }
+
+    /// Turn a volume label into the padded 11-byte form used in the boot sector and in the
+    /// root directory. Labels are uppercase and may contain spaces, but no periods or any of
+    /// the other characters that short names can't have either.
+    pub fn encode_label(&self, label: &str) -> Result<[u8; 11], FsError> {
+        let mut bytes = [0x20u8; 11];
+        let mut length = 0;
+        for character in label.trim_end().chars() {
+            let byte = match self.encode_upper(character) {
+                Some(byte) if byte >= 0x20 && byte != 0x7F && !INVALID_CHARS.contains(&byte) => {
+                    byte
+                }
+                _ => return Err(FsError::InvalidName(label.to_string())),
+            };
+            if length == bytes.len() {
+                return Err(FsError::InvalidName(label.to_string()));
+            }
+            bytes[length] = byte;
+            length += 1;
+        }
+        if length == 0 || bytes[0] == 0x20 {
+            return Err(FsError::InvalidName(label.to_string()));
+        }
+        Ok(bytes)
+    }
+
+    /// Turn a padded 11-byte volume label back into a string, without the padding.
+    pub fn decode_label(&self, bytes: &[u8; 11]) -> String {
+        self.decode(bytes).trim_end().to_string()
+    }
+}
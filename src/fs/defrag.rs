@@ -0,0 +1,111 @@
+use crate::fs::{DirEntry, FsError, Volume};
+use std::collections::HashMap;
+
+impl Volume {
+    /// Rewrite every file and directory into a single contiguous chain, packed together at the
+    /// start of the data area. System files in the root directory go first so that IO.SYS and
+    /// friends keep their place at the front, then everything else follows in directory order,
+    /// each directory directly followed by its contents. All free space ends up at the end of
+    /// the volume, zeroed, which is what makes shrinking an image possible. Both FATs and all
+    /// directory entries are updated. The volume has to pass a check first, since moving data
+    /// around on a damaged file system only makes things worse. Returns the number of clusters
+    /// that changed place.
+    pub fn defragment(&mut self) -> Result<u32, FsError> {
+        let report = self.check(false);
+        if !report.is_clean() {
+            return Err(FsError::Inconsistent(report.findings.len()));
+        }
+        let chains: Vec<Vec<u16>> = self
+            .placement_order()
+            .iter()
+            .map(|entry| self.get_chain(entry.first_cluster))
+            .collect();
+        let snapshot = self.data.clone();
+        let cluster_size = self.get_cluster_size();
+        let last_cluster = self.get_cluster_count() + 2;
+        let bad_cluster = self.bad_cluster();
+
+        // Start from an empty FAT, only bad clusters stay where they are.
+        for cluster in 2..last_cluster {
+            if self.get_fat_entry(cluster as u16) != bad_cluster {
+                self.set_fat_entry(cluster as u16, 0);
+            }
+        }
+
+        let mut remap = HashMap::<u16, u16>::new();
+        let mut next = 2u32;
+        let mut moved = 0u32;
+        for chain in &chains {
+            let mut new_chain = Vec::<u16>::with_capacity(chain.len());
+            for old in chain {
+                while self.get_fat_entry(next as u16) == bad_cluster {
+                    next += 1;
+                }
+                let new = next as u16;
+                if *old != new {
+                    let from = self.cluster_offset(*old);
+                    let to = self.cluster_offset(new);
+                    self.data[to..to + cluster_size]
+                        .copy_from_slice(&snapshot[from..from + cluster_size]);
+                    moved += 1;
+                }
+                new_chain.push(new);
+                next += 1;
+            }
+            remap.insert(chain[0], new_chain[0]);
+            self.link_chain(&new_chain);
+        }
+        for cluster in next..last_cluster {
+            if self.get_fat_entry(cluster as u16) != bad_cluster {
+                self.zero_cluster(cluster as u16);
+            }
+        }
+        self.remap_first_clusters(&remap);
+        Ok(moved)
+    }
+
+    /// All files and directories that own clusters, in the order they should end up on disk.
+    pub(crate) fn placement_order(&self) -> Vec<DirEntry> {
+        let is_placed = |entry: &DirEntry| {
+            !entry.is_dot_entry()
+                && !entry.attributes.vol_id
+                && self.is_valid_cluster(entry.first_cluster)
+        };
+        let root: Vec<DirEntry> = self.read_dir_at(0).into_iter().filter(is_placed).collect();
+        let (mut order, rest): (Vec<DirEntry>, Vec<DirEntry>) = root
+            .into_iter()
+            .partition(|entry| entry.attributes.system && !entry.is_dir());
+
+        // A stack of entries still to place, so that a directory is directly followed by
+        // everything inside it before moving on to its next sibling.
+        let mut pending: Vec<DirEntry> = rest.into_iter().rev().collect();
+        while let Some(entry) = pending.pop() {
+            if entry.is_dir() {
+                let children = self.read_dir_at(entry.first_cluster);
+                pending.extend(children.into_iter().filter(is_placed).rev());
+            }
+            order.push(entry);
+        }
+        order
+    }
+
+    /// Point every directory entry, including the dot entries, at the new place of its chain.
+    /// Only the cluster field gets touched so everything else in the entry stays as it was.
+    pub(crate) fn remap_first_clusters(&mut self, remap: &HashMap<u16, u16>) {
+        let mut pending = vec![0u16];
+        while let Some(dir_cluster) = pending.pop() {
+            for located in self.locate_dir_entries(dir_cluster) {
+                let entry = &located.entry;
+                let new = match remap.get(&entry.first_cluster) {
+                    Some(new) => *new,
+                    None => continue,
+                };
+                let offset = located.get_offset();
+                self.data[offset + 26..offset + 28].copy_from_slice(&new.to_le_bytes());
+                if entry.is_dir() && !entry.is_dot_entry() {
+                    pending.push(new);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::fs::Codepage;
+use crate::fs::FileAttributes;
+use crate::fs::Timestamp;
+
+/// Marker in the first byte of a directory entry that was deleted.
+pub(crate) const DELETED_MARKER: u8 = 0xE5;
+
+/// A single file, directory or volume label as found in a directory on a FAT volume.
+/// This is the 32-byte short entry described on page 23 of the Microsoft spec, together
+/// with the long file name that was attached to it, if any.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirEntry {
+    pub(crate) short_name: [u8; 11],
+    pub(crate) long_name: Option<String>,
+    pub(crate) attributes: FileAttributes,
+    pub(crate) first_cluster: u16,
+    pub(crate) size: u32,
+    pub(crate) created: Timestamp,
+    pub(crate) modified: Timestamp,
+    pub(crate) accessed: Timestamp,
+    pub(crate) codepage: Codepage,
+}
+
+impl DirEntry {
+    /// Instantiate a new directory entry from its parts. All timestamps start out at the
+    /// earliest date DOS knows about.
+    pub fn new(
+        short_name: [u8; 11],
+        attributes: FileAttributes,
+        first_cluster: u16,
+        size: u32,
+    ) -> Self {
+        DirEntry {
+            short_name,
+            long_name: None,
+            attributes,
+            first_cluster,
+            size,
+            created: Timestamp::default(),
+            modified: Timestamp::default(),
+            accessed: Timestamp::default(),
+            codepage: Codepage::default(),
+        }
+    }
+
+    /// Parse the 32 bytes of a short directory entry.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut short_name = [0u8; 11];
+        short_name.copy_from_slice(&bytes[0..11]);
+        // A real 0xE5 as the first character is stored as 0x05 so it doesn't look deleted.
+        if short_name[0] == 0x05 {
+            short_name[0] = DELETED_MARKER;
+        }
+        DirEntry {
+            short_name,
+            long_name: None,
+            attributes: FileAttributes::from_byte(bytes[11]),
+            first_cluster: u16::from_le_bytes([bytes[26], bytes[27]]),
+            size: u32::from_le_bytes([bytes[28], bytes[29], bytes[30], bytes[31]]),
+            created: Timestamp::from_dos(
+                u16::from_le_bytes([bytes[16], bytes[17]]),
+                u16::from_le_bytes([bytes[14], bytes[15]]),
+                bytes[13],
+            ),
+            modified: Timestamp::from_dos(
+                u16::from_le_bytes([bytes[24], bytes[25]]),
+                u16::from_le_bytes([bytes[22], bytes[23]]),
+                0,
+            ),
+            accessed: Timestamp::from_dos(u16::from_le_bytes([bytes[18], bytes[19]]), 0, 0),
+            codepage: Codepage::default(),
+        }
+    }
+
+    /// Serialize the short entry into the 32 bytes that go on disk.
+    pub fn as_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        bytes[0..11].copy_from_slice(&self.short_name);
+        if bytes[0] == DELETED_MARKER {
+            bytes[0] = 0x05;
+        }
+        bytes[11] = self.attributes.as_byte();
+        bytes[13] = self.created.as_dos_tenths();
+        bytes[14..16].copy_from_slice(&self.created.as_dos_time().to_le_bytes());
+        bytes[16..18].copy_from_slice(&self.created.as_dos_date().to_le_bytes());
+        bytes[18..20].copy_from_slice(&self.accessed.as_dos_date().to_le_bytes());
+        bytes[22..24].copy_from_slice(&self.modified.as_dos_time().to_le_bytes());
+        bytes[24..26].copy_from_slice(&self.modified.as_dos_date().to_le_bytes());
+        bytes[26..28].copy_from_slice(&self.first_cluster.to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.size.to_le_bytes());
+        bytes
+    }
+
+    /// The name as a user would see it: the long name if there is one, otherwise the short name.
+    pub fn get_name(&self) -> String {
+        match &self.long_name {
+            Some(long_name) => long_name.clone(),
+            None => self.get_short_name(),
+        }
+    }
+
+    /// The short name in its familiar NAME.EXT form, without the padding. Characters
+    /// outside of ASCII are decoded with the code page of the volume the entry came from.
+    pub fn get_short_name(&self) -> String {
+        let base = self.codepage.decode(&self.short_name[0..8]);
+        let extension = self.codepage.decode(&self.short_name[8..11]);
+        let (base, extension) = (base.trim_end(), extension.trim_end());
+        if extension.is_empty() || self.attributes.vol_id {
+            return self
+                .codepage
+                .decode(&self.short_name)
+                .trim_end()
+                .to_string();
+        }
+        format!("{}.{}", base, extension)
+    }
+
+    /// The raw padded 11-byte short name as stored on disk.
+    pub fn get_raw_name(&self) -> [u8; 11] {
+        self.short_name
+    }
+
+    pub fn get_long_name(&self) -> Option<&str> {
+        self.long_name.as_deref()
+    }
+
+    pub fn get_attributes(&self) -> &FileAttributes {
+        &self.attributes
+    }
+
+    pub fn get_first_cluster(&self) -> u16 {
+        self.first_cluster
+    }
+
+    pub fn get_size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn get_created(&self) -> Timestamp {
+        self.created
+    }
+
+    pub fn get_modified(&self) -> Timestamp {
+        self.modified
+    }
+
+    /// The last access date. DOS only keeps the date for this one, so the time is always midnight.
+    pub fn get_accessed(&self) -> Timestamp {
+        self.accessed
+    }
+
+    /// Set all three timestamps at once. The access time is cut down to its date.
+    pub fn set_timestamps(&mut self, created: Timestamp, modified: Timestamp, accessed: Timestamp) {
+        self.created = created;
+        self.modified = modified;
+        self.accessed = Timestamp::new(accessed.year, accessed.month, accessed.day, 0, 0, 0);
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attributes.is_dir
+    }
+
+    /// Whether this entry is one of the "." and ".." entries every subdirectory starts with.
+    pub fn is_dot_entry(&self) -> bool {
+        &self.short_name == b".          " || &self.short_name == b"..         "
+    }
+
+    /// Compare a single path component against this entry, the same way DOS and
+    /// Windows do: case-insensitive, against both the long and the short name.
+    pub fn matches(&self, component: &str) -> bool {
+        let component = component.to_uppercase();
+        if let Some(long_name) = &self.long_name {
+            if long_name.to_uppercase() == component {
+                return true;
+            }
+        }
+        self.get_short_name().to_uppercase() == component
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+use std::io;
+
+/// Errors that can occur while working with names, entries and data on a FAT file system.
+#[derive(Debug, PartialEq)]
+pub enum FsError {
+    /// The name can't be turned into a valid 8.3 name, not even a mangled one.
+    InvalidName(String),
+    /// All numeric tails from ~1 to ~999999 are already taken in the directory.
+    NameCollision(String),
+    /// Nothing by that name exists at the given path.
+    NotFound(String),
+    /// A path component that should be a directory turns out to be a file.
+    NotADirectory(String),
+    /// There already is an entry by that name in the directory.
+    AlreadyExists(String),
+    /// The fixed-size root directory has no free entries left.
+    DirectoryFull(String),
+    /// There are not enough free clusters left on the volume.
+    VolumeFull,
+    /// A file was expected but the path points at a directory.
+    IsADirectory(String),
+    /// A directory has to be empty before it can be removed.
+    DirectoryNotEmpty(String),
+    /// A directory can't be moved into itself or into one of its own subdirectories.
+    InvalidMove(String),
+    /// The file doesn't fit in the 32-bit size field of a directory entry.
+    FileTooLarge(String),
+    /// The boot sector doesn't describe a FAT file system that can be used.
+    InvalidBootRecord(String),
+    /// The volume has problems that need to be fixed by a check before this can be done safely.
+    Inconsistent(usize),
+    /// A FAT16 volume can't be made this small or this large.
+    InvalidSize(u32),
+    /// A line in an attribute rules file can't be parsed.
+    InvalidRule(String),
+    /// The format options don't add up to a FAT12 or FAT16 volume.
+    InvalidFormat(String),
+    /// DOS doesn't delete or overwrite files with the read-only attribute set.
+    ReadOnly(String),
+    /// Symbolic links on the host aren't followed, they could point anywhere or loop.
+    SymbolicLink(String),
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsError::InvalidName(name) => write!(f, "invalid file name: {}", name),
+            FsError::NameCollision(name) => {
+                write!(f, "no free short name left for: {}", name)
+            }
+            FsError::NotFound(path) => write!(f, "not found: {}", path),
+            FsError::NotADirectory(path) => write!(f, "not a directory: {}", path),
+            FsError::AlreadyExists(path) => write!(f, "already exists: {}", path),
+            FsError::DirectoryFull(path) => write!(f, "directory is full: {}", path),
+            FsError::VolumeFull => write!(f, "no free clusters left on the volume"),
+            FsError::IsADirectory(path) => write!(f, "is a directory: {}", path),
+            FsError::DirectoryNotEmpty(path) => write!(f, "directory is not empty: {}", path),
+            FsError::InvalidMove(path) => {
+                write!(f, "can't move a directory into itself: {}", path)
+            }
+            FsError::FileTooLarge(name) => write!(f, "file is larger than 4GB: {}", name),
+            FsError::InvalidBootRecord(reason) => write!(f, "invalid boot record: {}", reason),
+            FsError::Inconsistent(count) => {
+                write!(f, "volume has {} problems, check it first", count)
+            }
+            FsError::InvalidRule(line) => write!(f, "invalid attribute rule: {}", line),
+            FsError::InvalidFormat(reason) => write!(f, "invalid format options: {}", reason),
+            FsError::ReadOnly(path) => write!(f, "file is read-only: {}", path),
+            FsError::SymbolicLink(name) => write!(f, "symbolic links aren't followed: {}", name),
+            FsError::InvalidSize(sectors) => {
+                write!(f, "a FAT16 volume can't be {} sectors in size", sectors)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FsError {}
+
+impl From<FsError> for io::Error {
+    /// Hand file system errors to code that only speaks std::io, such as Read and Write.
+    fn from(error: FsError) -> io::Error {
+        let kind = match error {
+            FsError::NotFound(_) => io::ErrorKind::NotFound,
+            FsError::AlreadyExists(_) => io::ErrorKind::AlreadyExists,
+            FsError::InvalidName(_) | FsError::NameCollision(_) => io::ErrorKind::InvalidInput,
+            FsError::ReadOnly(_) => io::ErrorKind::PermissionDenied,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, error)
+    }
+}
@@ -0,0 +1,152 @@
+use crate::fs::{AttributeRules, FsError, Volume};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+/// What happened while exporting a directory of the volume to the host: the attributes of
+/// everything that was written, and what was left out and why.
+#[derive(Debug, Default, PartialEq)]
+pub struct ExportReport {
+    /// The attributes of the exported files and directories, as they went into the sidecar.
+    pub rules: AttributeRules,
+    /// DOS paths that weren't written to the host.
+    pub errors: Vec<(String, FsError)>,
+}
+
+impl ExportReport {
+    /// Whether everything made it onto the host.
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for ExportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (dos, error) in &self.errors {
+            writeln!(f, "error: {}: {}", dos, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Volume {
+    /// Copy a directory of the volume and everything below it into a directory on the host,
+    /// which gets created if needed. Files and directories take their long names when they
+    /// have one, their short names otherwise, and keep their modification times. The host
+    /// doesn't know about DOS attributes, so those get written as rules to a sidecar file,
+    /// and import_dir with the same file puts them back. Only entries that have any
+    /// attributes set get a rule. Keep the sidecar outside the exported directory, or it gets
+    /// imported along with the rest.
+    ///
+    /// Nothing here depends on how the volume was made, so images straight from MS-DOS work
+    /// the same. That also means the names can't be trusted: a name that isn't a single
+    /// plain file name on the host, like "..\EVIL" in a damaged or crafted entry, is reported
+    /// as an error instead of written. Files that already exist on the host are left alone
+    /// and reported too, so an export never overwrites anything. Volume labels are skipped,
+    /// and a damaged tree that loops back on itself is only followed once.
+    pub fn export_dir(
+        &self,
+        directory: &str,
+        host: &Path,
+        sidecar: &Path,
+    ) -> std::io::Result<ExportReport> {
+        let dir_cluster = self.resolve_dir(directory)?;
+        let mut report = ExportReport::default();
+        let mut visited = vec![dir_cluster];
+        std::fs::create_dir_all(host)?;
+        self.export_tree(dir_cluster, host, "", directory, &mut report, &mut visited)?;
+        report.rules.save(sidecar)?;
+        Ok(report)
+    }
+
+    fn export_tree(
+        &self,
+        dir_cluster: u16,
+        host: &Path,
+        relative: &str,
+        directory: &str,
+        report: &mut ExportReport,
+        visited: &mut Vec<u16>,
+    ) -> std::io::Result<()> {
+        for entry in self.read_dir_at(dir_cluster) {
+            if entry.is_dot_entry() || entry.attributes.vol_id {
+                continue;
+            }
+            let name = entry.get_name();
+            let dos_path = Volume::join_path(directory, &name);
+            let path = match Volume::host_path(host, &name) {
+                Some(path) => path,
+                None => {
+                    report.errors.push((dos_path, FsError::InvalidName(name)));
+                    continue;
+                }
+            };
+            let relative = if relative.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", relative, name)
+            };
+
+            if entry.is_dir() {
+                if visited.contains(&entry.first_cluster) {
+                    continue;
+                }
+                visited.push(entry.first_cluster);
+                if path.exists() && !path.is_dir() {
+                    report.errors.push((dos_path, FsError::AlreadyExists(name)));
+                    continue;
+                }
+                std::fs::create_dir_all(&path)?;
+                self.export_tree(
+                    entry.first_cluster,
+                    &path,
+                    &relative,
+                    &dos_path,
+                    report,
+                    visited,
+                )?;
+                // Filling the directory touched its time on the host, so set it afterwards.
+                // Not every host lets directories be opened like this, which is fine to skip.
+                if let Ok(handle) = std::fs::File::open(&path) {
+                    handle
+                        .set_modified(entry.get_modified().as_system_time())
+                        .ok();
+                }
+            } else {
+                let mut handle = match std::fs::File::options()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                {
+                    Ok(handle) => handle,
+                    Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                        report.errors.push((dos_path, FsError::AlreadyExists(name)));
+                        continue;
+                    }
+                    Err(error) => return Err(error),
+                };
+                std::io::Write::write_all(&mut handle, &self.read_contents(&entry))?;
+                handle.set_modified(entry.get_modified().as_system_time())?;
+            }
+            let flags = entry.attributes.as_flags();
+            if flags != "-" {
+                report.rules.add(&relative, &flags)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Where a name from the volume goes below a host directory, or None if it would end up
+    /// anywhere else. Only a single plain name without separators, NULs or a drive will do.
+    fn host_path(host: &Path, name: &str) -> Option<PathBuf> {
+        if name.is_empty() || name.contains(['/', '\\', ':', '\0']) {
+            return None;
+        }
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => {}
+            _ => return None,
+        }
+        let path = host.join(name);
+        (path.parent() == Some(host)).then_some(path)
+    }
+}
@@ -2,7 +2,7 @@ use crate::fs::Cluster;
 use crate::fs::File;
 use crate::fs::VBR;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct FAT {
     files: Vec<File>,
     sector_count: u32,
@@ -29,6 +29,21 @@ impl FAT {
         }
     }
 
+    /// Instantiate a FAT struct that matches the layout in a Volume Boot Record, for boot
+    /// records that don't use the default layout for their size.
+    pub(crate) fn from_boot_record(boot_record: &VBR) -> Self {
+        let cluster_count = boot_record.get_cluster_count();
+        FAT {
+            files: Vec::<File>::new(),
+            sector_count: u32::from(boot_record.get_sectors_per_fat()),
+            clusters: FAT::initialize_fat(cluster_count as usize),
+            cluster_count,
+            cluster_size: usize::from(boot_record.get_sectors_per_cluster())
+                * usize::from(boot_record.get_bytes_per_sector()),
+            sectors_per_fat: u32::from(boot_record.get_sectors_per_fat()),
+        }
+    }
+
     /// No idea why this is there yet. Cluster 0 contains this when formatted
     /// using MS-DOS so I'm replicating it here.
     fn initialize_fat(cluster_count: usize) -> Vec<Cluster> {
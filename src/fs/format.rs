@@ -0,0 +1,136 @@
+use crate::fs::resize::{MAX_SECTORS, MIN_SECTORS};
+use crate::fs::vbr::VBR;
+use crate::fs::volume::FAT12_MAX_CLUSTERS;
+use crate::fs::FsError;
+
+/// The most clusters a FAT16 volume can have, higher cluster numbers are reserved.
+const FAT16_MAX_CLUSTERS: u32 = 65524;
+
+/// Parameters for laying down a new file system, for when the defaults from the Microsoft
+/// tables don't fit the job. Like 32K clusters on a 1GB volume, or a bigger root directory
+/// for games that want all their files in one place. Anything that isn't set keeps the same
+/// value FORMAT itself would pick.
+///
+/// ```
+/// use doscontainer::fs::{FormatOptions, Volume};
+///
+/// let options = FormatOptions::new().cluster_size(16384).root_entries(1024);
+/// let volume = Volume::with_options(131072, &options).unwrap();
+/// assert!(!volume.is_fat12());
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormatOptions {
+    pub(crate) cluster_size: Option<u32>,
+    pub(crate) root_entries: u16,
+    pub(crate) fats: u8,
+    pub(crate) reserved_sectors: u16,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            cluster_size: None,
+            root_entries: 512,
+            fats: 2,
+            reserved_sectors: 1,
+        }
+    }
+}
+
+impl FormatOptions {
+    pub fn new() -> Self {
+        FormatOptions::default()
+    }
+
+    /// Cluster size in bytes, a power of two from 512 up to 32768. Larger clusters mean a
+    /// smaller FAT and less fragmentation, at the cost of more slack at the end of every file.
+    pub fn cluster_size(mut self, bytes: u32) -> Self {
+        self.cluster_size = Some(bytes);
+        self
+    }
+
+    /// Number of entries in the root directory, a multiple of 16 so it fills whole sectors.
+    pub fn root_entries(mut self, entries: u16) -> Self {
+        self.root_entries = entries;
+        self
+    }
+
+    /// Number of copies of the FAT, either 1 or 2.
+    pub fn fats(mut self, count: u8) -> Self {
+        self.fats = count;
+        self
+    }
+
+    /// Number of sectors before the first FAT, the boot sector included.
+    pub fn reserved_sectors(mut self, count: u16) -> Self {
+        self.reserved_sectors = count;
+        self
+    }
+
+    /// Work out sectors per cluster and sectors per FAT for a volume of this size, or explain
+    /// why these options don't make a usable volume out of it.
+    pub(crate) fn layout(&self, sector_count: u32) -> Result<(u8, u16), FsError> {
+        let invalid = |reason: String| Err(FsError::InvalidFormat(reason));
+        let sectors_per_cluster = match self.cluster_size {
+            None if !(MIN_SECTORS..=MAX_SECTORS).contains(&sector_count) => {
+                return Err(FsError::InvalidSize(sector_count))
+            }
+            None => VBR::set_sectors_per_cluster(sector_count),
+            Some(bytes) if bytes.is_power_of_two() && (512..=32768).contains(&bytes) => {
+                (bytes / 512) as u8
+            }
+            Some(bytes) => return invalid(format!("unsupported cluster size {}", bytes)),
+        };
+        if self.root_entries == 0 || !self.root_entries.is_multiple_of(16) {
+            return invalid(format!(
+                "{} root entries is not a multiple of 16",
+                self.root_entries
+            ));
+        }
+        if !(1..=2).contains(&self.fats) {
+            return invalid(format!("{} copies of the FAT", self.fats));
+        }
+        if self.reserved_sectors == 0 {
+            return invalid(String::from("no room for the boot sector"));
+        }
+
+        // The same calculation as VBR::set_sectors_per_fat, which is the one from the FAT32
+        // spec, only with the parameters filled in instead of the defaults.
+        let root_sectors = u32::from(self.root_entries) * 32 / 512;
+        let overhead = u32::from(self.reserved_sectors) + root_sectors;
+        if overhead >= sector_count {
+            return invalid(format!("{} sectors is too small", sector_count));
+        }
+        let divisor = 256 * u32::from(sectors_per_cluster) + u32::from(self.fats);
+        let mut sectors_per_fat = num::integer::div_ceil(sector_count - overhead, divisor);
+        let clusters = |sectors_per_fat: u32| {
+            sector_count.saturating_sub(overhead + u32::from(self.fats) * sectors_per_fat)
+                / u32::from(sectors_per_cluster)
+        };
+
+        // That formula assumes two bytes per entry. A FAT12 volume only needs one and a half,
+        // so shrink its FAT to what the clusters that are left actually need. Unless the space
+        // that frees up would push it over into FAT16 territory, then it keeps the bigger FAT.
+        if clusters(sectors_per_fat) < FAT12_MAX_CLUSTERS {
+            let mut fat12_sectors = 1;
+            while num::integer::div_ceil((clusters(fat12_sectors) + 2) * 3, 2 * 512) > fat12_sectors
+            {
+                fat12_sectors += 1;
+            }
+            if clusters(fat12_sectors) < FAT12_MAX_CLUSTERS {
+                sectors_per_fat = fat12_sectors;
+            }
+        }
+        let cluster_count = clusters(sectors_per_fat);
+        if cluster_count == 0 || cluster_count > FAT16_MAX_CLUSTERS {
+            return invalid(format!(
+                "{} clusters is outside what FAT12 and FAT16 allow",
+                cluster_count
+            ));
+        }
+        match u16::try_from(sectors_per_fat) {
+            Ok(sectors_per_fat) => Ok((sectors_per_cluster, sectors_per_fat)),
+            Err(_) => invalid(format!("a FAT of {} sectors is too large", sectors_per_fat)),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use crate::fs::direntry::DirEntry;
+use crate::fs::{FsError, Volume};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// An open file on a Volume. It implements Read, Write and Seek so that everything that
+/// works with ordinary Rust I/O, like io::copy or an archive extractor, can stream straight
+/// into an image. Writing past the end grows the cluster chain as needed and the directory
+/// entry is kept up to date after every change, so there is nothing to close or flush.
+pub struct FileHandle<'a> {
+    volume: &'a mut Volume,
+    offset: usize,
+    entry: DirEntry,
+    chain: Vec<u16>,
+    position: u64,
+}
+
+impl<'a> FileHandle<'a> {
+    /// Instantiate a handle for the directory entry that lives at a byte offset in the volume.
+    pub(crate) fn new(volume: &'a mut Volume, offset: usize, entry: DirEntry) -> Self {
+        let chain = volume.get_chain(entry.first_cluster);
+        FileHandle {
+            volume,
+            offset,
+            entry,
+            chain,
+            position: 0,
+        }
+    }
+
+    /// The directory entry as it currently stands, with the up to date size.
+    pub fn get_entry(&self) -> &DirEntry {
+        &self.entry
+    }
+
+    /// Size of the file in bytes.
+    pub fn len(&self) -> u64 {
+        u64::from(self.entry.size)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entry.size == 0
+    }
+
+    /// Truncate or extend the file to a new size. Clusters that are no longer needed go back
+    /// to the free pool, and extended space reads back as zeroes.
+    pub fn set_len(&mut self, size: u64) -> io::Result<()> {
+        match u32::try_from(size) {
+            Ok(size) => Ok(self.resize(size)?),
+            Err(_) => Err(FsError::FileTooLarge(self.entry.get_name()).into()),
+        }
+    }
+
+    pub(crate) fn resize(&mut self, size: u32) -> Result<(), FsError> {
+        let cluster_size = self.volume.get_cluster_size();
+        let needed = num::integer::div_ceil(size as usize, cluster_size);
+        if needed > self.chain.len() {
+            let extra = self.volume.allocate_chain(needed - self.chain.len())?;
+            if let Some(last) = self.chain.last() {
+                self.volume.set_fat_entry(*last, extra[0]);
+            }
+            self.chain.extend(extra);
+        } else if needed < self.chain.len() {
+            for cluster in self.chain.split_off(needed) {
+                self.volume.set_fat_entry(cluster, 0);
+            }
+            let chain = self.chain.clone();
+            self.volume.link_chain(&chain);
+        }
+
+        // Whatever was left behind in the slack of the last cluster must not show up again.
+        if size > self.entry.size {
+            let gap = vec![0u8; (size - self.entry.size) as usize];
+            self.copy_in(self.entry.size as usize, &gap);
+        }
+        self.entry.size = size;
+        self.entry.first_cluster = self.chain.first().copied().unwrap_or(0);
+        self.touch();
+        Ok(())
+    }
+
+    /// Copy bytes into the file's clusters at a position. The chain has to be long enough.
+    fn copy_in(&mut self, position: usize, bytes: &[u8]) {
+        let cluster_size = self.volume.get_cluster_size();
+        let mut done = 0;
+        while done < bytes.len() {
+            let index = (position + done) / cluster_size;
+            let within = (position + done) % cluster_size;
+            let span = (cluster_size - within).min(bytes.len() - done);
+            let offset = self.volume.cluster_offset(self.chain[index]) + within;
+            self.volume.data[offset..offset + span].copy_from_slice(&bytes[done..done + span]);
+            done += span;
+        }
+    }
+
+    /// Update the modification time and write the entry back into its directory.
+    fn touch(&mut self) {
+        let timestamp = self.volume.default_timestamp(None);
+        let created = self.entry.created;
+        self.entry.set_timestamps(created, timestamp, timestamp);
+        self.volume.write_entry(self.offset, &self.entry);
+    }
+}
+
+impl Read for FileHandle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = u64::from(self.entry.size);
+        if self.position >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let cluster_size = self.volume.get_cluster_size() as u64;
+        let index = (self.position / cluster_size) as usize;
+        let within = (self.position % cluster_size) as usize;
+        if index >= self.chain.len() {
+            return Ok(0);
+        }
+        let span = (buf.len() as u64)
+            .min(size - self.position)
+            .min(cluster_size - within as u64) as usize;
+        let offset = self.volume.cluster_offset(self.chain[index]) + within;
+        buf[..span].copy_from_slice(&self.volume.data[offset..offset + span]);
+        self.position += span as u64;
+        Ok(span)
+    }
+}
+
+impl Write for FileHandle<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let end = self.position + buf.len() as u64;
+        if end > u64::from(self.entry.size) {
+            self.set_len(end)?;
+        }
+        self.copy_in(self.position as usize, buf);
+        self.touch();
+        self.position = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for FileHandle<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => u64::from(self.entry.size).checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        match target {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::fs::{AttributeRules, File, FsError, Timestamp, Volume};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// What happened while importing a directory tree from the host: where every file and
+/// directory ended up on the volume, and what couldn't be imported and why.
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportReport {
+    /// Host path and the DOS path with 8.3 names it was stored under, in import order.
+    pub mapping: Vec<(PathBuf, String)>,
+    /// Host paths that couldn't be stored on the volume.
+    pub errors: Vec<(PathBuf, FsError)>,
+}
+
+impl ImportReport {
+    /// Whether everything made it onto the volume.
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (host, dos) in &self.mapping {
+            writeln!(f, "{} -> {}", host.display(), dos)?;
+        }
+        for (host, error) in &self.errors {
+            writeln!(f, "error: {}: {}", host.display(), error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Volume {
+    /// Copy a directory tree from the host into a directory on the volume, which has to exist
+    /// already. Subdirectories are created as needed, names are converted to 8.3 and the host
+    /// modification times are kept unless a source date epoch is set. Attributes come from the
+    /// rules, relative to the host directory. Entries are imported sorted by name so the same
+    /// tree always gives the same volume.
+    ///
+    /// Files and directories that can't be stored, like files over 4GB or anything that doesn't
+    /// fit in the root directory anymore, end up in the errors of the report while the rest of
+    /// the import carries on. So do symbolic links, which aren't followed since they can point
+    /// outside the tree or back up into it. Only failures to read from the host abort the
+    /// import.
+    pub fn import_dir(
+        &mut self,
+        host: &Path,
+        directory: &str,
+        rules: &AttributeRules,
+    ) -> std::io::Result<ImportReport> {
+        let mut report = ImportReport::default();
+        self.resolve_dir(directory)?;
+        self.import_tree(host, "", directory, rules, &mut report)?;
+        Ok(report)
+    }
+
+    fn import_tree(
+        &mut self,
+        host: &Path,
+        relative: &str,
+        directory: &str,
+        rules: &AttributeRules,
+        report: &mut ImportReport,
+    ) -> std::io::Result<()> {
+        let mut children = std::fs::read_dir(host)?.collect::<std::io::Result<Vec<_>>>()?;
+        children.sort_by_key(|child| child.file_name());
+        for child in children {
+            let path = child.path();
+            let name = child.file_name().to_string_lossy().to_string();
+            let relative = if relative.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", relative, name)
+            };
+            let target = Volume::join_path(directory, &name);
+            let metadata = std::fs::symlink_metadata(&path)?;
+            let host_modified = metadata.modified().ok().map(Timestamp::from_system_time);
+
+            if metadata.is_symlink() {
+                report.errors.push((path, FsError::SymbolicLink(name)));
+            } else if metadata.is_dir() {
+                let dos_path = match self.import_subdir(&target, &relative, host_modified, rules) {
+                    Ok(dos_path) => dos_path,
+                    Err(error) => {
+                        report.errors.push((path, error));
+                        continue;
+                    }
+                };
+                report.mapping.push((path.clone(), dos_path.clone()));
+                self.import_tree(&path, &relative, &dos_path, rules, report)?;
+            } else if metadata.len() > u64::from(u32::MAX) {
+                report.errors.push((path, FsError::FileTooLarge(name)));
+            } else {
+                let mut file = File::from_path(&path)?;
+                if let Some(attributes) = rules.lookup(&relative) {
+                    file.attributes = attributes;
+                }
+                match self.add_file(directory, file) {
+                    Ok(entry) => {
+                        let dos_path = Volume::join_path(directory, &entry.get_short_name());
+                        report.mapping.push((path, dos_path));
+                    }
+                    Err(error) => report.errors.push((path, error)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the contents of a ZIP archive into a directory on the volume, which has to exist
+    /// already. This works like import_dir, with paths inside the archive taking the place of
+    /// host paths in the rules and the report. Directories that only show up as part of a
+    /// file's path get created too. Entries whose path would end up outside the target
+    /// directory, like ones starting with "../", are refused and reported as errors.
+    pub fn import_archive<R: Read + Seek>(
+        &mut self,
+        archive: R,
+        directory: &str,
+        rules: &AttributeRules,
+    ) -> std::io::Result<ImportReport> {
+        let mut report = ImportReport::default();
+        self.resolve_dir(directory)?;
+        let mut archive = ZipArchive::new(archive)?;
+        let mut entries = Vec::new();
+        for index in 0..archive.len() {
+            let entry = archive.by_index(index)?;
+            let name = entry.name().trim_end_matches('/').to_string();
+            match entry.enclosed_name() {
+                Some(_) if !name.is_empty() => entries.push((name, index)),
+                _ => {
+                    let error = FsError::InvalidName(entry.name().to_string());
+                    report.errors.push((PathBuf::from(entry.name()), error));
+                }
+            }
+        }
+        entries.sort();
+
+        // Where every directory of the archive ended up on the volume, by archive path.
+        let mut directories = HashMap::from([(String::new(), directory.to_string())]);
+        for (relative, index) in entries {
+            let mut entry = archive.by_index(index)?;
+            let host_modified = entry.last_modified();
+            let host_modified = Timestamp::new(
+                host_modified.year(),
+                host_modified.month(),
+                host_modified.day(),
+                host_modified.hour(),
+                host_modified.minute(),
+                host_modified.second(),
+            );
+            let (parent, name) = relative.rsplit_once('/').unwrap_or(("", &relative));
+            let parent = match self.archive_dir(parent, &mut directories, rules, &mut report) {
+                Some(parent) => parent,
+                None => continue,
+            };
+            let path = PathBuf::from(&relative);
+            if entry.is_dir() {
+                if let Entry::Vacant(slot) = directories.entry(relative.clone()) {
+                    let target = Volume::join_path(&parent, name);
+                    match self.import_subdir(&target, &relative, Some(host_modified), rules) {
+                        Ok(dos_path) => {
+                            report.mapping.push((path, dos_path.clone()));
+                            slot.insert(dos_path);
+                        }
+                        Err(error) => report.errors.push((path, error)),
+                    }
+                }
+            } else if entry.size() > u64::from(u32::MAX) {
+                report
+                    .errors
+                    .push((path, FsError::FileTooLarge(name.to_string())));
+            } else {
+                let mut data = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut data)?;
+                let mut file = File::new(name.to_string(), data);
+                file.host_modified = Some(host_modified);
+                if let Some(attributes) = rules.lookup(&relative) {
+                    file.attributes = attributes;
+                }
+                match self.add_file(&parent, file) {
+                    Ok(entry) => {
+                        let dos_path = Volume::join_path(&parent, &entry.get_short_name());
+                        report.mapping.push((path, dos_path));
+                    }
+                    Err(error) => report.errors.push((path, error)),
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// The DOS path of a directory from an archive, creating it and its parents when the
+    /// archive doesn't have entries of their own for them. None when that fails, the reason
+    /// is in the report by then.
+    fn archive_dir(
+        &mut self,
+        relative: &str,
+        directories: &mut HashMap<String, String>,
+        rules: &AttributeRules,
+        report: &mut ImportReport,
+    ) -> Option<String> {
+        if let Some(dos_path) = directories.get(relative) {
+            return Some(dos_path.clone());
+        }
+        let (parent, name) = relative.rsplit_once('/').unwrap_or(("", relative));
+        let parent = self.archive_dir(parent, directories, rules, report)?;
+        let target = Volume::join_path(&parent, name);
+        match self.import_subdir(&target, relative, None, rules) {
+            Ok(dos_path) => {
+                report
+                    .mapping
+                    .push((PathBuf::from(relative), dos_path.clone()));
+                directories.insert(relative.to_string(), dos_path.clone());
+                Some(dos_path)
+            }
+            Err(error) => {
+                report.errors.push((PathBuf::from(relative), error));
+                None
+            }
+        }
+    }
+
+    /// Create a directory for an import and give it its timestamps and the attributes from the
+    /// rules. Returns the DOS path it ended up under.
+    fn import_subdir(
+        &mut self,
+        target: &str,
+        relative: &str,
+        host_modified: Option<Timestamp>,
+        rules: &AttributeRules,
+    ) -> Result<String, FsError> {
+        let entry = self.create_dir(target)?;
+        let (directory, _) = target.rsplit_once('\\').unwrap_or(("", target));
+        let dos_path = Volume::join_path(directory, &entry.get_short_name());
+        let located = self.locate(&dos_path)?;
+        let offset = located.get_offset();
+        let mut entry = located.entry;
+        let timestamp = self.default_timestamp(host_modified);
+        entry.set_timestamps(timestamp, timestamp, timestamp);
+        if let Some(attributes) = rules.lookup(relative) {
+            entry.attributes = attributes;
+            entry.attributes.is_dir = true;
+        }
+        self.write_entry(offset, &entry);
+        Ok(dos_path)
+    }
+
+    /// Put a name below a DOS directory path, where an empty path is the root directory.
+    pub(crate) fn join_path(directory: &str, name: &str) -> String {
+        match directory.trim_end_matches(['\\', '/']) {
+            "" => name.to_string(),
+            directory => format!("{}\\{}", directory, name),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::fs::direntry::DELETED_MARKER;
+use crate::fs::{DirEntry, FileAttributes, FsError, Timestamp, Volume};
+
+/// What the boot sector holds when a volume has no label, same as FORMAT writes.
+pub(crate) const NO_LABEL: &[u8; 11] = b"NO NAME    ";
+
+impl Volume {
+    /// The volume label, as DIR and LABEL show it. DOS takes it from the root directory and
+    /// not from the boot sector, so this does too.
+    pub fn get_label(&self) -> Option<String> {
+        self.find_label_entry()
+            .map(|offset| DirEntry::from_bytes(&self.data[offset..offset + 32]))
+            .map(|entry| self.get_codepage().decode_label(&entry.short_name))
+    }
+
+    /// Set or change the volume label, both in the root directory and in the boot sector.
+    /// The label is stored uppercase in the code page of the volume. This works the same on
+    /// images that were loaded from disk, as long as the root directory has a free slot.
+    pub fn set_label(&mut self, label: &str) -> Result<(), FsError> {
+        let label = self.get_codepage().encode_label(label)?;
+        let timestamp = self.default_timestamp(None);
+        self.write_label_entry(label, timestamp)
+    }
+
+    /// Remove the volume label from the root directory and reset the one in the boot
+    /// sector.
+    pub fn remove_label(&mut self) {
+        if let Some(offset) = self.find_label_entry() {
+            self.data[offset] = DELETED_MARKER;
+        }
+        self.write_boot_label(*NO_LABEL);
+    }
+
+    /// Put a label entry in the root directory, overwriting the existing one if there is
+    /// any.
+    pub(crate) fn write_label_entry(
+        &mut self,
+        label: [u8; 11],
+        timestamp: Timestamp,
+    ) -> Result<(), FsError> {
+        let offset = match self.find_label_entry() {
+            Some(offset) => offset,
+            None => self.find_free_slots(0, 1)?[0],
+        };
+        let attributes = FileAttributes {
+            vol_id: true,
+            ..FileAttributes::default()
+        };
+        let mut entry = DirEntry::new(label, attributes, 0, 0);
+        entry.set_timestamps(timestamp, timestamp, timestamp);
+        self.write_entry(offset, &entry);
+        self.write_boot_label(label);
+        Ok(())
+    }
+
+    /// The offset of the label entry in the root directory. Long name entries carry the
+    /// same attribute bit, so those don't count.
+    fn find_label_entry(&self) -> Option<usize> {
+        self.locate_dir_entries(0)
+            .into_iter()
+            .find(|located| located.entry.attributes.vol_id)
+            .map(|located| located.get_offset())
+    }
+}
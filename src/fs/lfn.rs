@@ -0,0 +1,123 @@
+use crate::fs::FsError;
+
+/// Attribute byte that marks a directory entry as a long file name fragment.
+pub(crate) const LFN_ATTRIBUTE: u8 = 0x0F;
+
+/// Flag in the ordinal byte that marks the fragment holding the end of the name.
+const LAST_LONG_ENTRY: u8 = 0x40;
+
+/// Number of UCS-2 characters that fit in a single long file name entry.
+const CHARS_PER_ENTRY: usize = 13;
+
+/// Byte offsets of the 13 UCS-2 characters inside a long file name entry.
+const CHAR_OFFSETS: [usize; CHARS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// Calculate the checksum over a short name that ties the long name entries to it.
+/// This is the rotate-and-add algorithm from page 31 of the Microsoft spec.
+pub fn checksum(short_name: &[u8; 11]) -> u8 {
+    let mut sum = 0u8;
+    for byte in short_name {
+        let carry = if sum & 1 == 1 { 0x80 } else { 0 };
+        sum = carry + (sum >> 1);
+        sum = sum.wrapping_add(*byte);
+    }
+    sum
+}
+
+/// Build the sequence of long file name entries for a name, in the order in which they
+/// go on disk. That means the fragment holding the end of the name comes first and the
+/// short name entry follows right after the last one returned here.
+pub fn build_entries(long_name: &str, short_name: &[u8; 11]) -> Result<Vec<[u8; 32]>, FsError> {
+    let mut units: Vec<u16> = long_name.encode_utf16().collect();
+    if units.is_empty() || units.len() > 255 {
+        return Err(FsError::InvalidName(long_name.to_string()));
+    }
+    let count = num::integer::div_ceil(units.len(), CHARS_PER_ENTRY);
+    // The name is terminated by a NUL unless it fills the last fragment exactly, the rest is padding.
+    if units.len() < count * CHARS_PER_ENTRY {
+        units.push(0x0000);
+    }
+    units.resize(count * CHARS_PER_ENTRY, 0xFFFF);
+
+    let sum = checksum(short_name);
+    let mut entries = Vec::<[u8; 32]>::with_capacity(count);
+    for ordinal in (1..=count).rev() {
+        let mut entry = [0u8; 32];
+        entry[0] = ordinal as u8;
+        if ordinal == count {
+            entry[0] |= LAST_LONG_ENTRY;
+        }
+        entry[11] = LFN_ATTRIBUTE;
+        entry[13] = sum;
+        let fragment = &units[(ordinal - 1) * CHARS_PER_ENTRY..ordinal * CHARS_PER_ENTRY];
+        for (unit, offset) in fragment.iter().zip(CHAR_OFFSETS) {
+            entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Whether a raw 32-byte directory entry is a long file name fragment.
+pub fn is_lfn_entry(entry: &[u8]) -> bool {
+    entry[11] & 0x3F == LFN_ATTRIBUTE
+}
+
+/// Collects long file name fragments while walking a directory and hands out the
+/// complete name once the short name entry it belongs to comes along. Sequences that
+/// are incomplete, out of order or carry the wrong checksum are silently dropped, just
+/// like Windows 9x treats them as orphans.
+#[derive(Debug, Default)]
+pub(crate) struct LongNameReader {
+    fragments: Vec<[u16; CHARS_PER_ENTRY]>,
+    next_ordinal: u8,
+    checksum: u8,
+    valid: bool,
+}
+
+impl LongNameReader {
+    /// Feed a long file name fragment into the reader.
+    pub(crate) fn push(&mut self, entry: &[u8]) {
+        let ordinal = entry[0] & !LAST_LONG_ENTRY;
+        if entry[0] & LAST_LONG_ENTRY != 0 {
+            self.fragments.clear();
+            self.checksum = entry[13];
+            self.valid = ordinal > 0;
+        } else if !self.valid || ordinal != self.next_ordinal || entry[13] != self.checksum {
+            self.reset();
+            return;
+        }
+        let mut fragment = [0u16; CHARS_PER_ENTRY];
+        for (unit, offset) in fragment.iter_mut().zip(CHAR_OFFSETS) {
+            *unit = u16::from_le_bytes([entry[offset], entry[offset + 1]]);
+        }
+        self.fragments.push(fragment);
+        self.next_ordinal = ordinal.wrapping_sub(1);
+    }
+
+    /// Finish the current sequence for the short name entry that follows it.
+    /// Returns the long name if a complete and matching sequence was collected.
+    pub(crate) fn finish(&mut self, short_name: &[u8; 11]) -> Option<String> {
+        let complete =
+            self.valid && self.next_ordinal == 0 && self.checksum == checksum(short_name);
+        let mut units = Vec::<u16>::with_capacity(self.fragments.len() * CHARS_PER_ENTRY);
+        for fragment in self.fragments.iter().rev() {
+            units.extend_from_slice(fragment);
+        }
+        self.reset();
+        if !complete {
+            return None;
+        }
+        if let Some(end) = units.iter().position(|unit| *unit == 0x0000) {
+            units.truncate(end);
+        }
+        Some(String::from_utf16_lossy(&units))
+    }
+
+    /// Throw away whatever was collected so far.
+    pub(crate) fn reset(&mut self) {
+        self.fragments.clear();
+        self.next_ordinal = 0;
+        self.valid = false;
+    }
+}
@@ -2,14 +2,49 @@
 use crate::fs::vbr::VBR;
 use crate::fs::cluster::Cluster;
 use bitvec::prelude::*;
+use std::path::Path;
+
+pub use crate::fs::check::{CheckReport, Finding, Problem};
+pub use crate::fs::codepage::Codepage;
+pub use crate::fs::direntry::DirEntry;
+pub use crate::fs::error::FsError;
+pub use crate::fs::export::ExportReport;
+pub use crate::fs::format::FormatOptions;
+pub use crate::fs::handle::FileHandle;
+pub use crate::fs::import::ImportReport;
+pub(crate) use crate::fs::resize::MIN_SECTORS;
+pub use crate::fs::rules::AttributeRules;
+pub use crate::fs::timestamp::Timestamp;
+pub use crate::fs::vbr::BootRecordProblem;
+pub use crate::fs::volume::Volume;
 
 #[cfg(test)]
 mod tests;
 pub mod vbr;
+mod check;
 mod cluster;
+mod codepage;
+mod defrag;
+mod direntry;
+mod error;
+mod export;
+mod format;
+mod handle;
+mod import;
+mod label;
 pub mod fat;
+pub mod lfn;
+mod resize;
+mod rules;
+mod timestamp;
+pub mod volume;
+
+/// Bytes that are never allowed in a short file name, see page 24 of the spec.
+const INVALID_CHARS: [u8; 16] = [
+    0x22, 0x2A, 0x2B, 0x2C, 0x2E, 0x2F, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F, 0x5B, 0x5C, 0x5D, 0x7C,
+];
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 /// Attributes that can be set on a file or directory
 /// in a FAT file system.
 pub struct FileAttributes {
@@ -48,14 +83,68 @@ impl FileAttributes {
         bits.set(5, self.archive);
         data
     }
+
+    /// Build the attributes from the on-disk byte.
+    pub fn from_byte(byte: u8) -> FileAttributes {
+        let bits = byte.view_bits::<Lsb0>();
+        FileAttributes {
+            read_only: bits[0],
+            hidden: bits[1],
+            system: bits[2],
+            vol_id: bits[3],
+            is_dir: bits[4],
+            archive: bits[5],
+        }
+    }
+
+    /// Build the attributes from a string of flags like "RHS", the same letters ATTRIB uses:
+    /// R for read-only, H for hidden, S for system and A for archive. A single "-" means none.
+    pub fn from_flags(flags: &str) -> Option<FileAttributes> {
+        let mut attributes = FileAttributes::default();
+        if flags == "-" {
+            return Some(attributes);
+        }
+        for flag in flags.chars() {
+            match flag.to_ascii_uppercase() {
+                'R' => attributes.read_only = true,
+                'H' => attributes.hidden = true,
+                'S' => attributes.system = true,
+                'A' => attributes.archive = true,
+                _ => return None,
+            }
+        }
+        Some(attributes)
+    }
+
+    /// The attributes as a string of flags like "RHS", the inverse of from_flags.
+    pub fn as_flags(&self) -> String {
+        let flags: String = [
+            (self.read_only, 'R'),
+            (self.hidden, 'H'),
+            (self.system, 'S'),
+            (self.archive, 'A'),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, flag)| *flag)
+        .collect();
+        if flags.is_empty() {
+            return String::from("-");
+        }
+        flags
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct File {
     name: String,
     data: Vec<u8>,
     clusters: Vec<Cluster>,
     attributes: FileAttributes,
+    created: Option<Timestamp>,
+    modified: Option<Timestamp>,
+    accessed: Option<Timestamp>,
+    host_modified: Option<Timestamp>,
 }
 
 impl File {
@@ -65,8 +154,41 @@ impl File {
             data: data,
             clusters: Vec::<Cluster>::new(),
             attributes: FileAttributes::default(),
+            created: None,
+            modified: None,
+            accessed: None,
+            host_modified: None,
         }
     }
+
+    /// Read a file from the host, keeping its name and modification time.
+    pub fn from_path(path: &Path) -> std::io::Result<Self> {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput)),
+        };
+        let mut file = File::new(name, std::fs::read(path)?);
+        file.host_modified = std::fs::metadata(path)?
+            .modified()
+            .ok()
+            .map(Timestamp::from_system_time);
+        Ok(file)
+    }
+
+    /// Explicitly set the creation time. This wins over any volume-wide default.
+    pub fn set_created(&mut self, created: Timestamp) {
+        self.created = Some(created);
+    }
+
+    /// Explicitly set the modification time. This wins over any volume-wide default.
+    pub fn set_modified(&mut self, modified: Timestamp) {
+        self.modified = Some(modified);
+    }
+
+    /// Explicitly set the last access date. This wins over any volume-wide default.
+    pub fn set_accessed(&mut self, accessed: Timestamp) {
+        self.accessed = Some(accessed);
+    }
     pub fn set_readonly(&mut self, readonly: bool) {
         self.attributes.read_only = readonly;
     }
@@ -91,14 +213,16 @@ impl File {
     /// Validate the requested file name according to the
     /// rules as determined by Microsoft in their spec document
     /// on page 24.
-    /// [TODO] Use a proper Result<E, T> and expand so this function
-    /// doesn't just validate but can serve to normalize a filename.
-    fn validate_name(name: &str) -> bool {
+    /// [TODO] Use a proper Result<E, T>. Use normalize_name to turn
+    /// a host file name into something that passes this check.
+    /// Takes the raw bytes from a directory entry, already in the
+    /// code page of the volume.
+    pub(crate) fn validate_name<T: AsRef<[u8]>>(name: T) -> bool {
+        let bytes = name.as_ref();
         // Name must not be longer than 11 characters
-        if name.len() > 11 {
+        if bytes.len() > 11 {
             return false;
         }
-        let bytes = name.as_bytes();
 
         // Special validation required for the first character
         if bytes[0] == 0x00 {
@@ -109,15 +233,90 @@ impl File {
         }
 
         // Check for disallowed characters
-        let invalid_chars: [u8; 16] = [
-            0x22, 0x2A, 0x2B, 0x2C, 0x2E, 0x2F, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F, 0x5B, 0x5C,
-            0x5D, 0x7C,
-        ];
         for value in bytes {
-            if invalid_chars.contains(&value) {
+            if INVALID_CHARS.contains(value) {
                 return false;
             }
         }
         return true;
     }
+
+    /// Turn a host file name like "Setup Program.exe" into the padded 11-byte form
+    /// that goes into a directory entry, like "SETUPP~1EXE". The name is uppercased
+    /// and stripped of spaces and invalid characters. If that loses information, or
+    /// the result is already present in `siblings`, a numeric tail is added the same
+    /// way Windows 95 does it. Pass in the short names already present in the target
+    /// directory as `siblings` so that collisions get resolved. Anything outside of
+    /// ASCII is encoded in code page 437, use normalize_name_in for other code pages.
+    pub fn normalize_name(name: &str, siblings: &[[u8; 11]]) -> Result<[u8; 11], FsError> {
+        File::normalize_name_in(name, siblings, Codepage::default())
+    }
+
+    /// Same as normalize_name, but for a volume that uses a specific code page. Characters
+    /// that exist in the code page are kept, everything else counts as lost information.
+    pub fn normalize_name_in(
+        name: &str,
+        siblings: &[[u8; 11]],
+        codepage: Codepage,
+    ) -> Result<[u8; 11], FsError> {
+        // Leading periods can't be represented, the last remaining period splits off the extension.
+        let trimmed = name.trim_start_matches('.');
+        let mut lossy = trimmed.len() != name.len();
+        let (base, extension) = match trimmed.rfind('.') {
+            Some(position) => (&trimmed[..position], &trimmed[position + 1..]),
+            None => (trimmed, ""),
+        };
+        let (base, base_lossy) = File::strip_name_part(base, codepage);
+        let (extension, extension_lossy) = File::strip_name_part(extension, codepage);
+        if base.is_empty() {
+            return Err(FsError::InvalidName(name.to_string()));
+        }
+        lossy = lossy || base_lossy || extension_lossy || base.len() > 8 || extension.len() > 3;
+
+        let mut short_name = [0x20u8; 11];
+        let extension = &extension[..extension.len().min(3)];
+        short_name[8..8 + extension.len()].copy_from_slice(extension);
+        if !lossy {
+            short_name[..base.len()].copy_from_slice(&base);
+            if !siblings.contains(&short_name) {
+                return Ok(short_name);
+            }
+        }
+
+        // Either the name didn't fit or it's taken, so go looking for a free numeric tail.
+        for tail_number in 1..=999999u32 {
+            let tail = format!("~{}", tail_number);
+            let kept = base.len().min(8 - tail.len());
+            let mut candidate = short_name;
+            candidate[..8].copy_from_slice(&[0x20u8; 8]);
+            candidate[..kept].copy_from_slice(&base[..kept]);
+            candidate[kept..kept + tail.len()].copy_from_slice(tail.as_bytes());
+            if !siblings.contains(&candidate) {
+                return Ok(candidate);
+            }
+        }
+        Err(FsError::NameCollision(name.to_string()))
+    }
+
+    /// Uppercase one part of a file name and drop everything that isn't allowed
+    /// in a short name. Returns the remaining bytes and whether anything was dropped.
+    fn strip_name_part(part: &str, codepage: Codepage) -> (Vec<u8>, bool) {
+        let mut bytes = Vec::<u8>::with_capacity(part.len());
+        let mut lossy = false;
+        for character in part.chars() {
+            let byte = match codepage.encode_upper(character) {
+                Some(byte) => byte,
+                None => {
+                    lossy = true;
+                    continue;
+                }
+            };
+            if byte <= 0x20 || byte == 0x7F || INVALID_CHARS.contains(&byte) {
+                lossy = true;
+                continue;
+            }
+            bytes.push(byte);
+        }
+        (bytes, lossy)
+    }
 }
@@ -0,0 +1,114 @@
+use crate::fs::{FsError, Volume};
+use std::collections::HashMap;
+
+/// The smallest volume the cluster size table in the VBR has an entry for.
+pub(crate) const MIN_SECTORS: u32 = 8400;
+
+/// The largest volume the cluster size table in the VBR has an entry for.
+pub(crate) const MAX_SECTORS: u32 = 4194303;
+
+impl Volume {
+    /// Change the size of the volume to a number of sectors. The FAT and the cluster size are
+    /// recalculated for the new size, so the data area usually moves. That's why everything gets
+    /// laid out again from scratch: files and directories are copied over in the same order the
+    /// defragmenter uses, and come out contiguous. Names, attributes, timestamps, the boot code
+    /// and the serial number are all kept, and so are the number of FATs, root entries and
+    /// reserved sectors, and a cluster size other than the default. Fails when the layout
+    /// doesn't work for the new size or the content doesn't fit in it.
+    pub fn resize(&mut self, sector_count: u32) -> Result<(), FsError> {
+        let boot_record = self.get_boot_record().resized(sector_count)?;
+        let report = self.check(false);
+        if !report.is_clean() {
+            return Err(FsError::Inconsistent(report.findings.len()));
+        }
+        if !self.fits(sector_count) {
+            return Err(FsError::VolumeFull);
+        }
+
+        let mut resized = Volume::format(boot_record);
+        resized.set_long_names(self.get_long_names());
+        resized.set_source_date_epoch(self.get_source_date_epoch());
+        resized.set_codepage(self.get_codepage());
+
+        // Anything in the reserved sectors after the boot sector stays, as does the root
+        // directory since it has the same number of entries on both volumes.
+        let sector_size = self.bytes_per_sector();
+        let reserved =
+            usize::from(self.get_boot_record().get_reserved_sectors_count()) * sector_size;
+        resized.data[sector_size..reserved].copy_from_slice(&self.data[sector_size..reserved]);
+        let (from, to, size) = (
+            self.root_dir_offset(),
+            resized.root_dir_offset(),
+            self.root_dir_size(),
+        );
+        resized.data[to..to + size].copy_from_slice(&self.data[from..from + size]);
+
+        let mut remap = HashMap::<u16, u16>::new();
+        for entry in self.placement_order() {
+            let contents = self.read_contents(&entry);
+            let count = num::integer::div_ceil(contents.len(), resized.get_cluster_size());
+            let chain = resized.allocate_chain(count.max(1))?;
+            resized.write_chain(&chain, &contents);
+            remap.insert(entry.first_cluster, chain[0]);
+        }
+        resized.remap_first_clusters(&remap);
+        *self = resized;
+        Ok(())
+    }
+
+    /// The smallest size in sectors this volume can be resized to while keeping all of its
+    /// content. There's no headroom at all, so the volume is full after shrinking to this.
+    /// Volumes with a cluster size of their own can go below the FAT16 minimum as FAT12.
+    pub fn get_minimum_sector_count(&self) -> Result<u32, FsError> {
+        let current = self.get_boot_record().get_volume_sectors_count();
+        let high = if self.fits(current) {
+            current
+        } else {
+            MAX_SECTORS
+        };
+        if !self.fits(high) {
+            return Err(FsError::VolumeFull);
+        }
+        let (mut low, mut high) = (1, high);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.fits(middle) {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        Ok(low)
+    }
+
+    /// Resize the volume to the smallest size that still holds all of its content.
+    /// Returns the new size in sectors.
+    pub fn shrink_to_fit(&mut self) -> Result<u32, FsError> {
+        let sector_count = self.get_minimum_sector_count()?;
+        self.resize(sector_count)?;
+        Ok(sector_count)
+    }
+
+    /// Whether everything on this volume would fit on a volume of a certain size.
+    fn fits(&self, sector_count: u32) -> bool {
+        let boot_record = match self.get_boot_record().resized(sector_count) {
+            Ok(boot_record) => boot_record,
+            Err(_) => return false,
+        };
+        let cluster_size = usize::from(boot_record.get_bytes_per_sector())
+            * usize::from(boot_record.get_sectors_per_cluster());
+        let needed: usize = self
+            .placement_order()
+            .iter()
+            .map(|entry| {
+                let bytes = if entry.is_dir() {
+                    self.get_chain(entry.first_cluster).len() * self.get_cluster_size()
+                } else {
+                    entry.size as usize
+                };
+                num::integer::div_ceil(bytes, cluster_size).max(1)
+            })
+            .sum();
+        needed <= boot_record.get_cluster_count() as usize
+    }
+}
@@ -0,0 +1,112 @@
+use crate::fs::{FileAttributes, FsError};
+use std::fmt;
+use std::path::Path;
+
+/// Rules that decide which attributes files and directories get when they're imported from
+/// the host. A rules file has one rule per line: a path relative to the directory that gets
+/// imported, followed by the attribute flags. Paths use forward slashes, match without regard
+/// to case and may contain `*` and `?` wildcards, which never match a slash. When several
+/// rules match a path, the last one wins. Empty lines and lines starting with `#` are ignored.
+/// A leading `./` is dropped, which is how a path that starts with `#` gets written.
+///
+/// ```text
+/// # Keep the system files out of sight
+/// IO.SYS         RHS
+/// MSDOS.SYS      RHS
+/// GAMES/*/*.SAV  A
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AttributeRules {
+    rules: Vec<(String, FileAttributes)>,
+}
+
+impl AttributeRules {
+    pub fn new() -> Self {
+        AttributeRules::default()
+    }
+
+    /// Parse rules from the contents of a rules file.
+    pub fn parse(text: &str) -> Result<Self, FsError> {
+        let mut rules = AttributeRules::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // The flags come last, so paths with spaces in them work without quoting.
+            let (pattern, flags) = match line.rsplit_once(char::is_whitespace) {
+                Some((pattern, flags)) => (pattern.trim_end(), flags),
+                None => return Err(FsError::InvalidRule(line.to_string())),
+            };
+            let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+            if rules.add(pattern, flags).is_err() {
+                return Err(FsError::InvalidRule(line.to_string()));
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Read and parse a rules file from the host.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Ok(AttributeRules::parse(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Write the rules to a file on the host, in the format parse() reads.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+
+    /// Add a single rule, with the attributes as flags like "RHS".
+    pub fn add(&mut self, pattern: &str, flags: &str) -> Result<(), FsError> {
+        let attributes = match FileAttributes::from_flags(flags) {
+            Some(attributes) => attributes,
+            None => return Err(FsError::InvalidRule(format!("{} {}", pattern, flags))),
+        };
+        self.rules.push((pattern.to_string(), attributes));
+        Ok(())
+    }
+
+    /// The attributes for a relative host path, if any rule matches it.
+    pub fn lookup(&self, path: &str) -> Option<FileAttributes> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| AttributeRules::matches(pattern.as_bytes(), path.as_bytes()))
+            .map(|(_, attributes)| attributes.clone())
+    }
+
+    /// Match a path against a pattern with `*` and `?` wildcards, ignoring case.
+    pub(crate) fn matches(pattern: &[u8], path: &[u8]) -> bool {
+        match (pattern.first(), path.first()) {
+            (None, None) => true,
+            (Some(b'*'), _) => {
+                AttributeRules::matches(&pattern[1..], path)
+                    || (matches!(path.first(), Some(byte) if *byte != b'/')
+                        && AttributeRules::matches(pattern, &path[1..]))
+            }
+            (Some(b'?'), Some(byte)) if *byte != b'/' => {
+                AttributeRules::matches(&pattern[1..], &path[1..])
+            }
+            (Some(expected), Some(byte)) if expected.eq_ignore_ascii_case(byte) => {
+                AttributeRules::matches(&pattern[1..], &path[1..])
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for AttributeRules {
+    /// One rule per line, exactly as a rules file holds them. Patterns that start with `#`
+    /// or `./` get a `./` in front, so parse() doesn't take them for a comment.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (pattern, attributes) in &self.rules {
+            let prefix = if pattern.starts_with('#') || pattern.starts_with("./") {
+                "./"
+            } else {
+                ""
+            };
+            writeln!(f, "{}{} {}", prefix, pattern, attributes.as_flags())?;
+        }
+        Ok(())
+    }
+}
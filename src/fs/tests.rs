@@ -1,8 +1,22 @@
 use crate::fs::File;
 use crate::fs::FileAttributes;
+use crate::fs::FsError;
 use crate::fs::fat::FAT;
 use crate::fs::VBR;
 use crate::fs::Cluster;
+use crate::fs::Problem;
+use crate::fs::lfn;
+use crate::fs::lfn::LongNameReader;
+use crate::fs::AttributeRules;
+use crate::fs::Codepage;
+use crate::fs::Timestamp;
+use crate::fs::Volume;
+use crate::fs::BootRecordProblem;
+use crate::fs::FormatOptions;
+use crate::fs::MIN_SECTORS;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
 
 #[test]
 pub fn attributes_empty() {
@@ -109,8 +123,8 @@ pub fn vbr_as_bytes() {
     let vbr = VBR::new(94532);
     let reference: Vec<u8> = vec![
         235, 60, 144, 77, 83, 68, 79, 83, 53, 46, 48, 0, 2, 4, 1, 0, 2, 0, 2, 0, 0, 248, 93, 0, 63,
-        0, 16, 0, 63, 0, 0, 0, 68, 113, 1, 0, 128, 0, 41, 241, 202, 53, 99, 68, 79, 83, 67, 78, 84,
-        78, 82, 32, 32, 32, 70, 65, 84, 49, 54, 32, 32, 32, 250, 51, 192, 142, 208, 188, 0, 124,
+        0, 16, 0, 63, 0, 0, 0, 68, 113, 1, 0, 128, 0, 41, 241, 202, 53, 99, 78, 79, 32, 78, 65, 77,
+        69, 32, 32, 32, 32, 70, 65, 84, 49, 54, 32, 32, 32, 250, 51, 192, 142, 208, 188, 0, 124,
         22, 7, 187, 120, 0, 54, 197, 55, 30, 86, 22, 83, 191, 62, 124, 185, 11, 0, 252, 243, 164,
         6, 31, 198, 69, 254, 15, 139, 14, 24, 124, 136, 77, 249, 137, 71, 2, 199, 7, 62, 124, 251,
         205, 19, 114, 121, 51, 192, 57, 6, 19, 124, 116, 8, 139, 14, 19, 124, 137, 14, 32, 124,
@@ -169,3 +183,1048 @@ pub fn msdossys_to_clusters() {
     let clusters = fat.allocate_clusters(&msdos_sys);
     // assert_eq!(clusters, reference_clusters);
 }
+
+#[test]
+pub fn normalize_plain_name() {
+    let name = File::normalize_name("readme.txt", &[]).unwrap();
+    assert_eq!(&name, b"README  TXT");
+}
+
+#[test]
+pub fn normalize_mangles_long_name() {
+    let name = File::normalize_name("Setup Program.exe", &[]).unwrap();
+    assert_eq!(&name, b"SETUPP~1EXE");
+}
+
+#[test]
+pub fn normalize_resolves_collisions() {
+    let siblings = [*b"SETUPP~1EXE", *b"SETUPP~2EXE"];
+    let name = File::normalize_name("Setup Program.exe", &siblings).unwrap();
+    assert_eq!(&name, b"SETUPP~3EXE");
+    let siblings = [*b"README  TXT"];
+    let name = File::normalize_name("README.TXT", &siblings).unwrap();
+    assert_eq!(&name, b"README~1TXT");
+}
+
+#[test]
+pub fn normalize_strips_extra_dots() {
+    let name = File::normalize_name("archive.tar.gz", &[]).unwrap();
+    assert_eq!(&name, b"ARCHIV~1GZ ");
+}
+
+#[test]
+pub fn normalize_rejects_empty_name() {
+    assert!(File::normalize_name("???", &[]).is_err());
+}
+
+#[test]
+pub fn lfn_checksum() {
+    assert_eq!(lfn::checksum(b"SETUPP~1EXE"), 192);
+}
+
+#[test]
+pub fn lfn_entries_for_name() {
+    let entries = lfn::build_entries("Setup Program.exe", b"SETUPP~1EXE").unwrap();
+    assert_eq!(entries.len(), 2);
+    // Last fragment goes first on disk and carries the 0x40 flag.
+    assert_eq!(entries[0][0], 0x42);
+    assert_eq!(entries[1][0], 0x01);
+    assert_eq!(entries[0][11], 0x0F);
+    assert_eq!(entries[0][13], 192);
+    // Fragment 2 holds ".exe", the NUL terminator and then 0xFFFF padding.
+    assert_eq!(
+        &entries[0][1..11],
+        &[b'.', 0, b'e', 0, b'x', 0, b'e', 0, 0, 0]
+    );
+    assert_eq!(&entries[0][14..16], &[0xFF, 0xFF]);
+}
+
+#[test]
+pub fn lfn_roundtrip() {
+    let mut reader = LongNameReader::default();
+    for entry in lfn::build_entries("Setup Program.exe", b"SETUPP~1EXE").unwrap() {
+        reader.push(&entry);
+    }
+    assert_eq!(
+        reader.finish(b"SETUPP~1EXE"),
+        Some("Setup Program.exe".to_string())
+    );
+}
+
+#[test]
+pub fn lfn_orphan_is_dropped() {
+    let mut reader = LongNameReader::default();
+    for entry in lfn::build_entries("Setup Program.exe", b"SETUPP~1EXE").unwrap() {
+        reader.push(&entry);
+    }
+    assert_eq!(reader.finish(b"SETUPP~2EXE"), None);
+}
+
+#[test]
+pub fn volume_writes_long_names() {
+    let mut volume = Volume::new(94532);
+    volume.set_long_names(true);
+    volume.create_dir("Program Files").unwrap();
+    let file = File::new("Setup Program.exe".to_string(), vec![0x90; 5000]);
+    volume.add_file("\\PROGRA~1", file).unwrap();
+    let entries = volume.read_dir("Program Files").unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[2].get_name(), "Setup Program.exe");
+    assert_eq!(entries[2].get_short_name(), "SETUPP~1.EXE");
+    assert_eq!(entries[2].get_size(), 5000);
+}
+
+#[test]
+pub fn volume_without_long_names() {
+    let mut volume = Volume::new(94532);
+    let file = File::new("Setup Program.exe".to_string(), vec![0x90; 5000]);
+    let entry = volume.add_file("\\", file).unwrap();
+    assert_eq!(entry.get_long_name(), None);
+    let entries = volume.read_dir("").unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].get_name(), "SETUPP~1.EXE");
+}
+
+#[test]
+pub fn timestamp_from_unix() {
+    let timestamp = Timestamp::from_unix(809222400 + 13 * 3600 + 37 * 60 + 21);
+    assert_eq!(timestamp, Timestamp::new(1995, 8, 24, 13, 37, 21));
+}
+
+#[test]
+pub fn timestamp_dos_roundtrip() {
+    let timestamp = Timestamp::new(1995, 8, 24, 13, 37, 21);
+    assert_eq!(timestamp.as_dos_date(), 7960);
+    assert_eq!(timestamp.as_dos_time(), (13 << 11) | (37 << 5) | 10);
+    assert_eq!(timestamp.as_dos_tenths(), 100);
+    let restored = Timestamp::from_dos(
+        timestamp.as_dos_date(),
+        timestamp.as_dos_time(),
+        timestamp.as_dos_tenths(),
+    );
+    assert_eq!(restored, timestamp);
+}
+
+#[test]
+pub fn volume_source_date_epoch() {
+    let epoch = Timestamp::new(1994, 5, 31, 6, 22, 0);
+    let mut volume = Volume::new(94532);
+    volume.set_source_date_epoch(Some(epoch));
+    let explicit = Timestamp::new(1993, 12, 10, 12, 0, 0);
+    let mut doom = File::new("DOOM.EXE".to_string(), vec![0; 100]);
+    doom.set_modified(explicit);
+    volume.add_file("", doom).unwrap();
+    volume
+        .add_file("", File::new("DOOM.WAD".to_string(), vec![0; 100]))
+        .unwrap();
+    let entries = volume.read_dir("").unwrap();
+    assert_eq!(entries[0].get_modified(), explicit);
+    assert_eq!(entries[0].get_created(), explicit);
+    assert_eq!(entries[1].get_modified(), epoch);
+    assert_eq!(
+        entries[1].get_accessed(),
+        Timestamp::new(1994, 5, 31, 0, 0, 0)
+    );
+}
+
+#[test]
+pub fn volume_serial_from_seed() {
+    let mut first = Volume::new(94532);
+    let mut second = Volume::new(94532);
+    first.set_serial_from_seed("doom");
+    second.set_serial_from_seed("doom");
+    assert_eq!(first.get_serial(), second.get_serial());
+    assert_ne!(first.get_serial(), 1664469745);
+    assert_eq!(&first.as_bytes()[39..43], &first.get_serial().to_le_bytes());
+}
+
+#[test]
+pub fn volume_serial_from_content() {
+    let mut volume = Volume::new(20000);
+    volume.set_serial_from_content();
+    let serial = volume.get_serial();
+    volume.set_serial_from_content();
+    assert_eq!(volume.get_serial(), serial);
+}
+
+#[test]
+pub fn volume_read_file() {
+    let mut volume = Volume::new(20000);
+    volume.create_dir("SAVES").unwrap();
+    let data: Vec<u8> = (0..10000u32).map(|value| value as u8).collect();
+    volume
+        .add_file("SAVES", File::new("DOOMSAV0.DSG".to_string(), data.clone()))
+        .unwrap();
+    assert_eq!(volume.read_file("\\SAVES\\DOOMSAV0.DSG").unwrap(), data);
+    assert_eq!(volume.read_file("saves/doomsav0.dsg").unwrap(), data);
+    assert_eq!(
+        volume.read_file("SAVES"),
+        Err(FsError::IsADirectory("SAVES".to_string()))
+    );
+    assert_eq!(
+        volume.read_file("SAVES\\NOPE.DSG"),
+        Err(FsError::NotFound("SAVES\\NOPE.DSG".to_string()))
+    );
+}
+
+#[test]
+pub fn volume_from_bytes_roundtrip() {
+    let mut volume = Volume::new(20000);
+    volume.set_long_names(true);
+    volume
+        .add_file("", File::new("Read me.txt".to_string(), b"Hi".to_vec()))
+        .unwrap();
+    let loaded = Volume::from_bytes(volume.as_bytes().to_vec()).unwrap();
+    assert!(loaded.get_long_names());
+    assert_eq!(loaded.read_file("README~1.TXT").unwrap(), b"Hi");
+    assert_eq!(loaded.read_dir("").unwrap(), volume.read_dir("").unwrap());
+}
+
+#[test]
+pub fn volume_from_garbage() {
+    assert!(Volume::from_bytes(vec![0u8; 4096]).is_err());
+}
+
+#[test]
+pub fn vbr_from_bytes_roundtrip() {
+    let vbr = VBR::new(94532);
+    assert_eq!(VBR::from_bytes(&Volume::new(94532).as_bytes()[0..512]), vbr);
+}
+
+#[test]
+pub fn handle_write_and_read() {
+    let mut volume = Volume::new(20000);
+    let data: Vec<u8> = (0..10000u32).map(|value| (value % 251) as u8).collect();
+    let mut handle = volume.create("GAME.DAT").unwrap();
+    std::io::copy(&mut data.as_slice(), &mut handle).unwrap();
+    assert_eq!(handle.len(), 10000);
+    handle.seek(SeekFrom::Start(100)).unwrap();
+    let mut buffer = [0u8; 3000];
+    handle.read_exact(&mut buffer).unwrap();
+    assert_eq!(&buffer[..], &data[100..3100]);
+    assert_eq!(volume.read_file("GAME.DAT").unwrap(), data);
+}
+
+#[test]
+pub fn handle_write_past_end_fills_zeroes() {
+    let mut volume = Volume::new(20000);
+    let mut handle = volume.create("SPARSE.DAT").unwrap();
+    handle.write_all(b"START").unwrap();
+    handle.seek(SeekFrom::Start(5000)).unwrap();
+    handle.write_all(b"END").unwrap();
+    let data = volume.read_file("SPARSE.DAT").unwrap();
+    assert_eq!(data.len(), 5003);
+    assert_eq!(&data[0..5], b"START");
+    assert!(data[5..5000].iter().all(|byte| *byte == 0));
+    assert_eq!(&data[5000..], b"END");
+}
+
+#[test]
+pub fn handle_set_len_frees_clusters() {
+    let mut volume = Volume::new(20000);
+    let free = volume.get_free_cluster_count();
+    let mut handle = volume.create("BIG.DAT").unwrap();
+    handle.write_all(&vec![0xAA; 20000]).unwrap();
+    handle.set_len(10).unwrap();
+    assert_eq!(volume.get_free_cluster_count(), free - 1);
+    let mut handle = volume.open("BIG.DAT").unwrap();
+    handle.set_len(0).unwrap();
+    assert_eq!(handle.get_entry().get_first_cluster(), 0);
+    assert_eq!(volume.get_free_cluster_count(), free);
+    // Extending again must not bring back the old contents.
+    let mut handle = volume.open("BIG.DAT").unwrap();
+    handle.set_len(100).unwrap();
+    assert_eq!(volume.read_file("BIG.DAT").unwrap(), vec![0u8; 100]);
+}
+
+#[test]
+pub fn create_truncates_existing_file() {
+    let mut volume = Volume::new(20000);
+    volume
+        .add_file("", File::new("AUTOEXEC.BAT".to_string(), vec![b'X'; 3000]))
+        .unwrap();
+    let mut handle = volume.create("AUTOEXEC.BAT").unwrap();
+    handle.write_all(b"@ECHO OFF\r\n").unwrap();
+    assert_eq!(volume.read_file("AUTOEXEC.BAT").unwrap(), b"@ECHO OFF\r\n");
+    assert_eq!(volume.read_dir("").unwrap().len(), 1);
+    assert!(volume.open("NOPE.BAT").is_err());
+}
+
+#[test]
+pub fn create_keeps_read_only_file() {
+    let mut volume = Volume::new(20000);
+    let mut config = File::new("CONFIG.SYS".to_string(), b"FILES=30\r\n".to_vec());
+    config.set_readonly(true);
+    volume.add_file("", config).unwrap();
+    assert_eq!(
+        volume.create("CONFIG.SYS").map(|_| ()),
+        Err(FsError::ReadOnly("CONFIG.SYS".to_string()))
+    );
+    assert_eq!(volume.read_file("CONFIG.SYS").unwrap(), b"FILES=30\r\n");
+}
+
+#[test]
+pub fn remove_file_frees_everything() {
+    let mut volume = Volume::new(20000);
+    volume.set_long_names(true);
+    let free = volume.get_free_cluster_count();
+    volume
+        .add_file("", File::new("Install Log.txt".to_string(), vec![1; 5000]))
+        .unwrap();
+    volume.remove_file("install log.txt").unwrap();
+    assert_eq!(volume.get_free_cluster_count(), free);
+    assert!(volume.read_dir("").unwrap().is_empty());
+    // Both the long name fragment and the short entry are marked deleted. The root directory
+    // comes after the boot sector and two FATs of 39 sectors each.
+    let root = &volume.as_bytes()[(1 + 2 * 39) * 512..];
+    assert_eq!(root[0], 0xE5);
+    assert_eq!(root[32], 0xE5);
+}
+
+#[test]
+pub fn broken_directory_chain_is_an_error() {
+    let mut volume = Volume::new(20000);
+    volume.create_dir("GAMES").unwrap();
+    // Point the directory entry, the first one in the root directory after the boot sector
+    // and two FATs of 39 sectors each, at a cluster past the end of the volume.
+    let mut data = volume.as_bytes().to_vec();
+    let root = (1 + 2 * 39) * 512;
+    data[root + 26..root + 28].copy_from_slice(&0xFFF0u16.to_le_bytes());
+    let mut volume = Volume::from_bytes(data).unwrap();
+    let broken = Err(FsError::Inconsistent(1));
+    let file = File::new("SAVE.DAT".to_string(), vec![1; 100]);
+    assert_eq!(volume.add_file("GAMES", file).map(|_| ()), broken);
+    assert_eq!(volume.create_dir("GAMES\\DOOM").map(|_| ()), broken);
+    assert_eq!(volume.create("GAMES\\SAVE.DAT").map(|_| ()), broken);
+}
+
+#[test]
+pub fn remove_dir_needs_empty_dir() {
+    let mut volume = Volume::new(20000);
+    let free = volume.get_free_cluster_count();
+    volume.create_dir("INSTALL").unwrap();
+    volume.create_dir("INSTALL\\DISK1").unwrap();
+    volume
+        .add_file(
+            "INSTALL\\DISK1",
+            File::new("SETUP.EXE".to_string(), vec![1; 5000]),
+        )
+        .unwrap();
+    assert_eq!(
+        volume.remove_dir("INSTALL"),
+        Err(FsError::DirectoryNotEmpty("INSTALL".to_string()))
+    );
+    volume.remove_dir_all("INSTALL").unwrap();
+    assert!(volume.read_dir("").unwrap().is_empty());
+    assert_eq!(volume.get_free_cluster_count(), free);
+}
+
+#[test]
+pub fn dot_entries_and_read_only_files_stay() {
+    let mut volume = Volume::new(20000);
+    volume.create_dir("GAMES").unwrap();
+    let mut save = File::new("SAVE.DAT".to_string(), vec![1; 100]);
+    save.set_readonly(true);
+    volume.add_file("GAMES", save).unwrap();
+    for path in ["GAMES\\.", "GAMES\\.."] {
+        let invalid = Err(FsError::InvalidName(path.to_string()));
+        assert_eq!(volume.rename(path, "X").map(|_| ()), invalid);
+        assert_eq!(volume.remove_file(path), invalid);
+        assert_eq!(volume.remove_dir(path), invalid);
+    }
+    assert_eq!(volume.read_dir("GAMES").unwrap().len(), 3);
+    assert_eq!(
+        volume.remove_file("GAMES\\SAVE.DAT"),
+        Err(FsError::ReadOnly("GAMES\\SAVE.DAT".to_string()))
+    );
+    assert_eq!(volume.read_file("GAMES\\SAVE.DAT").unwrap(), vec![1; 100]);
+    volume.remove_dir_all("GAMES").unwrap();
+    assert!(volume.read_dir("").unwrap().is_empty());
+    assert!(volume.check(false).is_clean());
+}
+
+#[test]
+pub fn rename_file_in_place() {
+    let mut volume = Volume::new(20000);
+    volume
+        .add_file("", File::new("SETUP.EXE".to_string(), b"MZ".to_vec()))
+        .unwrap();
+    let entry = volume.rename("SETUP.EXE", "Install Me.exe").unwrap();
+    assert_eq!(entry.get_short_name(), "INSTAL~1.EXE");
+    assert_eq!(volume.read_file("INSTAL~1.EXE").unwrap(), b"MZ");
+    assert!(volume.get_entry("SETUP.EXE").is_err());
+    volume.rename("INSTAL~1.EXE", "install.exe").unwrap();
+    assert_eq!(volume.read_dir("").unwrap()[0].get_name(), "INSTALL.EXE");
+}
+
+#[test]
+pub fn move_directory_fixes_dot_dot() {
+    let mut volume = Volume::new(20000);
+    volume.create_dir("GAMES").unwrap();
+    volume.create_dir("DOOM").unwrap();
+    volume
+        .add_file("DOOM", File::new("DOOM.EXE".to_string(), b"MZ".to_vec()))
+        .unwrap();
+    let games = volume.get_entry("GAMES").unwrap().get_first_cluster();
+    volume.rename("DOOM", "GAMES\\DOOM").unwrap();
+    assert_eq!(volume.read_file("GAMES\\DOOM\\DOOM.EXE").unwrap(), b"MZ");
+    let dot_dot = &volume.read_dir("GAMES\\DOOM").unwrap()[1];
+    assert_eq!(dot_dot.get_first_cluster(), games);
+    assert_eq!(volume.read_dir("").unwrap().len(), 1);
+    assert_eq!(
+        volume.rename("GAMES", "GAMES\\DOOM\\GAMES"),
+        Err(FsError::InvalidMove("GAMES".to_string()))
+    );
+}
+
+#[test]
+pub fn check_clean_volume() {
+    let mut volume = Volume::new(20000);
+    volume.create_dir("DOS").unwrap();
+    volume
+        .add_file("DOS", File::new("EDIT.COM".to_string(), vec![1; 5000]))
+        .unwrap();
+    let report = volume.check(false);
+    assert!(report.is_clean());
+    assert_eq!(report.files, 1);
+    assert_eq!(report.directories, 1);
+    assert_eq!(report.used_clusters, 6);
+}
+
+#[test]
+pub fn check_repairs_lost_clusters_and_fat_copies() {
+    let mut volume = Volume::new(20000);
+    volume
+        .add_file("", File::new("EDIT.COM".to_string(), vec![1; 5000]))
+        .unwrap();
+    // Allocate a chain nobody points at, then damage the second FAT by hand.
+    let lost = volume.allocate_chain(2).unwrap();
+    let second_fat = volume.fat_offset(1);
+    volume.data[second_fat + 3] = 0xAB;
+    let report = volume.check(true);
+    assert_eq!(report.findings.len(), 2);
+    assert!(matches!(
+        report.findings[0].problem,
+        Problem::FatMismatch { copy: 1, .. }
+    ));
+    assert_eq!(
+        report.findings[1].problem,
+        Problem::LostClusters { clusters: lost }
+    );
+    assert!(!report.has_unrepaired());
+    assert!(volume.check(false).is_clean());
+}
+
+#[test]
+pub fn check_repairs_size_mismatch() {
+    let mut volume = Volume::new(20000);
+    let entry = volume
+        .add_file("", File::new("EDIT.COM".to_string(), vec![1; 5000]))
+        .unwrap();
+    let offset = volume.locate("EDIT.COM").unwrap().get_offset();
+    let mut damaged = entry.clone();
+    damaged.size = 100;
+    volume.write_entry(offset, &damaged);
+    let report = volume.check(true);
+    assert_eq!(
+        report.findings[0].problem,
+        Problem::SizeMismatch {
+            path: "\\EDIT.COM".to_string(),
+            size: 100,
+            clusters: 5
+        }
+    );
+    assert_eq!(volume.read_file("EDIT.COM").unwrap().len(), 100);
+    assert!(volume.check(false).is_clean());
+}
+
+#[test]
+pub fn check_reports_cross_links() {
+    let mut volume = Volume::new(20000);
+    volume
+        .add_file("", File::new("ONE.TXT".to_string(), vec![1; 100]))
+        .unwrap();
+    let two = volume
+        .add_file("", File::new("TWO.TXT".to_string(), vec![2; 100]))
+        .unwrap();
+    let offset = volume.locate("TWO.TXT").unwrap().get_offset();
+    let mut damaged = two.clone();
+    damaged.first_cluster = volume.get_entry("ONE.TXT").unwrap().get_first_cluster();
+    volume.write_entry(offset, &damaged);
+    let report = volume.check(true);
+    assert!(report.has_unrepaired());
+    assert!(report
+        .findings
+        .iter()
+        .any(|finding| matches!(finding.problem, Problem::CrossLinked { .. })));
+}
+
+#[test]
+pub fn defragment_makes_chains_contiguous() {
+    let mut volume = Volume::new(20000);
+    volume
+        .add_file("", File::new("ONE.TXT".to_string(), vec![1; 3000]))
+        .unwrap();
+    volume
+        .add_file("", File::new("TWO.TXT".to_string(), vec![2; 1000]))
+        .unwrap();
+    volume.create_dir("GAMES").unwrap();
+    volume
+        .add_file("GAMES", File::new("DOOM.EXE".to_string(), vec![3; 3000]))
+        .unwrap();
+    volume.remove_file("TWO.TXT").unwrap();
+    let mut handle = volume.open("ONE.TXT").unwrap();
+    handle.seek(SeekFrom::End(0)).unwrap();
+    handle.write_all(&[4; 3000]).unwrap();
+    let mut io_sys = File::new("IO.SYS".to_string(), vec![5; 2000]);
+    io_sys.set_system(true);
+    volume.add_file("", io_sys).unwrap();
+    let fragmented = volume.get_chain(2);
+    assert!(!fragmented.windows(2).all(|pair| pair[1] == pair[0] + 1));
+
+    let moved = volume.defragment().unwrap();
+    assert!(moved > 0);
+    assert!(volume.check(false).is_clean());
+    assert_eq!(volume.get_entry("IO.SYS").unwrap().get_first_cluster(), 2);
+    let mut used = 0;
+    for path in ["IO.SYS", "ONE.TXT", "GAMES", "GAMES\\DOOM.EXE"] {
+        let chain = volume.get_chain(volume.get_entry(path).unwrap().get_first_cluster());
+        assert!(chain.windows(2).all(|pair| pair[1] == pair[0] + 1));
+        used += chain.len();
+    }
+    // Everything after the used clusters is free.
+    let last = volume.get_cluster_count() as u16 + 2;
+    assert!((2 + used as u16..last).all(|cluster| volume.get_fat_entry(cluster) == 0));
+    let mut expected = vec![1; 3000];
+    expected.extend_from_slice(&[4; 3000]);
+    assert_eq!(volume.read_file("ONE.TXT").unwrap(), expected);
+    assert_eq!(volume.read_file("GAMES\\DOOM.EXE").unwrap(), vec![3; 3000]);
+    assert_eq!(
+        volume.read_dir("GAMES").unwrap()[0].get_first_cluster(),
+        volume.get_entry("GAMES").unwrap().get_first_cluster()
+    );
+}
+
+#[test]
+pub fn defragment_refuses_damaged_volume() {
+    let mut volume = Volume::new(20000);
+    volume.allocate_chain(1).unwrap();
+    assert_eq!(volume.defragment(), Err(FsError::Inconsistent(1)));
+}
+
+#[test]
+pub fn resize_keeps_content() {
+    let mut volume = Volume::new(20000);
+    volume.set_serial(0x1234_5678);
+    volume.create_dir("GAMES").unwrap();
+    volume
+        .add_file("GAMES", File::new("DOOM.WAD".to_string(), vec![7; 100000]))
+        .unwrap();
+    let created = volume.get_entry("GAMES\\DOOM.WAD").unwrap().get_created();
+    volume.resize(100000).unwrap();
+    assert_eq!(volume.as_bytes().len(), 100000 * 512);
+    assert_eq!(volume.get_cluster_size(), 2048);
+    assert_eq!(volume.get_serial(), 0x1234_5678);
+    assert_eq!(
+        volume.read_file("GAMES\\DOOM.WAD").unwrap(),
+        vec![7; 100000]
+    );
+    assert_eq!(
+        volume.get_entry("GAMES\\DOOM.WAD").unwrap().get_created(),
+        created
+    );
+    assert!(volume.check(false).is_clean());
+    volume.resize(9000).unwrap();
+    assert_eq!(
+        volume.read_file("GAMES\\DOOM.WAD").unwrap(),
+        vec![7; 100000]
+    );
+    assert_eq!(volume.resize(100), Err(FsError::InvalidSize(100)));
+}
+
+#[test]
+pub fn shrink_to_fit_leaves_no_room() {
+    let mut volume = Volume::new(94532);
+    volume
+        .add_file("", File::new("BIG.DAT".to_string(), vec![1; 5000000]))
+        .unwrap();
+    let sectors = volume.shrink_to_fit().unwrap();
+    assert!(sectors < 94532);
+    assert_eq!(volume.read_file("BIG.DAT").unwrap().len(), 5000000);
+    assert_eq!(volume.resize(sectors - 1), Err(FsError::VolumeFull));
+}
+
+#[test]
+pub fn resize_keeps_the_layout() {
+    let options = FormatOptions::new().cluster_size(16384).root_entries(1024);
+    let mut volume = Volume::with_options(131072, &options).unwrap();
+    volume
+        .add_file("", File::new("DOOM.WAD".to_string(), vec![3; 100000]))
+        .unwrap();
+    volume.resize(140000).unwrap();
+    assert_eq!(volume.get_cluster_size(), 16384);
+    assert_eq!(volume.get_boot_record().get_root_dir_entries_count(), 1024);
+    assert_eq!(volume.read_file("DOOM.WAD").unwrap(), vec![3; 100000]);
+
+    let mut small = Volume::with_options(4000, &FormatOptions::new().cluster_size(512)).unwrap();
+    small
+        .add_file("", File::new("KEEN.EXE".to_string(), vec![4; 20000]))
+        .unwrap();
+    let sectors = small.shrink_to_fit().unwrap();
+    assert!(sectors < 4000);
+    assert!(small.is_fat12());
+    assert_eq!(small.get_cluster_size(), 512);
+    assert_eq!(small.read_file("KEEN.EXE").unwrap(), vec![4; 20000]);
+
+    // Enough 512 byte clusters for FAT16, and the boot sector says so now.
+    small.resize(20000).unwrap();
+    assert!(!small.is_fat12());
+    assert_eq!(&small.as_bytes()[54..62], b"FAT16   ");
+    assert!(small.check(false).is_clean());
+}
+
+/// A fresh, empty scratch directory on the host for tests that import or export trees.
+fn scratch_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("doscontainer-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+#[test]
+pub fn attribute_rules_match_wildcards() {
+    let rules = AttributeRules::parse(
+        "# System files\nIO.SYS RHS\ngames/*/*.sav  A\nGAMES/DOOM/DOOM?.SAV -\n",
+    )
+    .unwrap();
+    assert!(rules.lookup("io.sys").unwrap().system);
+    assert!(rules.lookup("GAMES/KEEN/KEEN.SAV").unwrap().archive);
+    assert!(!rules.lookup("GAMES/DOOM/DOOM1.SAV").unwrap().archive);
+    assert_eq!(rules.lookup("GAMES/KEEN.SAV"), None);
+    assert_eq!(
+        AttributeRules::parse("IO.SYS RHX"),
+        Err(FsError::InvalidRule("IO.SYS RHX".to_string()))
+    );
+}
+
+#[test]
+pub fn import_host_tree() {
+    let host = scratch_dir("import");
+    std::fs::create_dir_all(host.join("Games/Commander Keen")).unwrap();
+    std::fs::write(host.join("autoexec.bat"), b"@ECHO OFF").unwrap();
+    std::fs::write(host.join("Games/Commander Keen/keen4e.exe"), b"MZ").unwrap();
+    let keen = std::fs::File::options()
+        .write(true)
+        .open(host.join("Games/Commander Keen/keen4e.exe"))
+        .unwrap();
+    keen.set_modified(UNIX_EPOCH + Duration::from_secs(770_000_000))
+        .unwrap();
+    let rules = AttributeRules::parse("GAMES/* H\n*.BAT RA").unwrap();
+
+    let mut volume = Volume::new(20000);
+    let report = volume.import_dir(&host, "", &rules).unwrap();
+    std::fs::remove_dir_all(&host).unwrap();
+    assert!(report.is_complete());
+    let mapped: Vec<&str> = report.mapping.iter().map(|(_, dos)| dos.as_str()).collect();
+    assert_eq!(
+        mapped,
+        [
+            "GAMES",
+            "GAMES\\COMMAN~1",
+            "GAMES\\COMMAN~1\\KEEN4E.EXE",
+            "AUTOEXEC.BAT"
+        ]
+    );
+    assert_eq!(
+        volume.read_file("GAMES\\COMMAN~1\\KEEN4E.EXE").unwrap(),
+        b"MZ"
+    );
+    let entry = volume.get_entry("GAMES\\COMMAN~1").unwrap();
+    assert!(entry.is_dir());
+    assert!(entry.get_attributes().hidden);
+    assert!(
+        volume
+            .get_entry("AUTOEXEC.BAT")
+            .unwrap()
+            .get_attributes()
+            .read_only
+    );
+    assert_eq!(
+        volume
+            .get_entry("GAMES\\COMMAN~1\\KEEN4E.EXE")
+            .unwrap()
+            .get_modified(),
+        Timestamp::from_unix(770_000_000)
+    );
+}
+
+#[test]
+pub fn import_reports_full_root_directory() {
+    let host = scratch_dir("import-root");
+    for index in 0..515 {
+        std::fs::write(host.join(format!("FILE{:03}.TXT", index)), b"x").unwrap();
+    }
+    let mut volume = Volume::new(20000);
+    let report = volume
+        .import_dir(&host, "", &AttributeRules::new())
+        .unwrap();
+    std::fs::remove_dir_all(&host).unwrap();
+    assert_eq!(report.mapping.len(), 512);
+    assert_eq!(report.errors.len(), 3);
+    assert!(matches!(report.errors[0].1, FsError::DirectoryFull(_)));
+}
+
+#[test]
+pub fn import_zip_archive() {
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::FileOptions::default()
+        .last_modified_time(zip::DateTime::from_date_and_time(1993, 12, 10, 12, 0, 0).unwrap());
+    archive.start_file("Keen/keen4e.exe", options).unwrap();
+    archive.write_all(b"MZ").unwrap();
+    archive.add_directory("Keen/Saves/", options).unwrap();
+    archive.start_file("readme.txt", options).unwrap();
+    archive.write_all(b"Have fun").unwrap();
+    archive.start_file("../escape.txt", options).unwrap();
+    let archive = archive.finish().unwrap();
+    let rules = AttributeRules::parse("KEEN/SAVES H").unwrap();
+
+    let mut volume = Volume::new(20000);
+    let report = volume.import_archive(archive, "", &rules).unwrap();
+    let mapped: Vec<&str> = report.mapping.iter().map(|(_, dos)| dos.as_str()).collect();
+    assert_eq!(
+        mapped,
+        ["KEEN", "KEEN\\SAVES", "KEEN\\KEEN4E.EXE", "README.TXT"]
+    );
+    assert_eq!(report.errors.len(), 1);
+    assert_eq!(report.errors[0].0, PathBuf::from("../escape.txt"));
+    assert_eq!(volume.read_file("KEEN\\KEEN4E.EXE").unwrap(), b"MZ");
+    assert!(
+        volume
+            .get_entry("KEEN\\SAVES")
+            .unwrap()
+            .get_attributes()
+            .hidden
+    );
+    assert_eq!(
+        volume.get_entry("README.TXT").unwrap().get_modified(),
+        Timestamp::new(1993, 12, 10, 12, 0, 0)
+    );
+}
+
+#[test]
+pub fn timestamp_unix_roundtrip() {
+    let timestamp = Timestamp::new(1994, 5, 31, 6, 22, 18);
+    assert_eq!(Timestamp::from_unix(timestamp.as_unix()), timestamp);
+    assert_eq!(Timestamp::default().as_unix(), 315532800);
+}
+
+#[test]
+pub fn export_and_import_roundtrip() {
+    let mut volume = Volume::new(20000);
+    volume.set_long_names(true);
+    volume.set_source_date_epoch(Some(Timestamp::new(1993, 12, 10, 12, 0, 0)));
+    // A volume label in the first root slot, the way FORMAT leaves it.
+    let root = (1 + 2 * 39) * 512;
+    volume.data[root..root + 11].copy_from_slice(b"DOOM       ");
+    volume.data[root + 11] = 0x08;
+    let mut io_sys = File::new("IO.SYS".to_string(), vec![1; 3000]);
+    io_sys.set_hidden(true);
+    io_sys.set_system(true);
+    volume.add_file("", io_sys).unwrap();
+    volume.create_dir("GAMES").unwrap();
+    volume
+        .add_file(
+            "GAMES",
+            File::new("Save Game.dat".to_string(), vec![2; 100]),
+        )
+        .unwrap();
+
+    let scratch = scratch_dir("export");
+    let host = scratch.join("tree");
+    let sidecar = scratch.join("attributes.txt");
+    let report = volume.export_dir("", &host, &sidecar).unwrap();
+    assert!(report.is_complete());
+    assert_eq!(report.rules.to_string(), "IO.SYS HS\n");
+    let rules = AttributeRules::load(&sidecar).unwrap();
+    assert_eq!(rules, report.rules);
+    let saved = std::fs::read(host.join("GAMES/Save Game.dat")).unwrap();
+    assert_eq!(saved, vec![2; 100]);
+    let modified = std::fs::metadata(host.join("IO.SYS"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    assert_eq!(
+        Timestamp::from_system_time(modified),
+        Timestamp::new(1993, 12, 10, 12, 0, 0)
+    );
+    assert!(!host.join("DOOM").exists());
+
+    let mut copy = Volume::new(20000);
+    copy.set_long_names(true);
+    let report = copy.import_dir(&host, "", &rules).unwrap();
+    std::fs::remove_dir_all(&scratch).unwrap();
+    assert!(report.is_complete());
+    assert_eq!(
+        copy.get_entry("IO.SYS")
+            .unwrap()
+            .get_attributes()
+            .as_flags(),
+        "HS"
+    );
+    assert_eq!(
+        copy.read_file("GAMES\\Save Game.dat").unwrap(),
+        vec![2; 100]
+    );
+    assert_eq!(
+        copy.get_entry("GAMES").unwrap().get_modified(),
+        Timestamp::new(1993, 12, 10, 12, 0, 0)
+    );
+}
+
+/// `#` is fine in a DOS name but starts a comment in a rules file.
+#[test]
+pub fn export_and_import_comment_like_names() {
+    let mut volume = Volume::new(20000);
+    let mut readme = File::new("#README.TXT".to_string(), b"Read me".to_vec());
+    readme.set_readonly(true);
+    volume.add_file("", readme).unwrap();
+
+    let scratch = scratch_dir("export-comment");
+    let host = scratch.join("tree");
+    let sidecar = scratch.join("attributes.txt");
+    volume.export_dir("", &host, &sidecar).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&sidecar).unwrap(),
+        "./#README.TXT R\n"
+    );
+    let rules = AttributeRules::load(&sidecar).unwrap();
+    let mut copy = Volume::new(20000);
+    let report = copy.import_dir(&host, "", &rules).unwrap();
+    std::fs::remove_dir_all(&scratch).unwrap();
+    assert!(report.is_complete());
+    let entry = copy.get_entry("#README.TXT").unwrap();
+    assert_eq!(entry.get_attributes().as_flags(), "R");
+}
+
+#[test]
+pub fn export_refuses_unsafe_names() {
+    let mut volume = Volume::new(20000);
+    volume.set_long_names(true);
+    volume
+        .add_file("", File::new("Escape Me.txt".to_string(), vec![1; 10]))
+        .unwrap();
+    volume
+        .add_file("", File::new("KEEP.TXT".to_string(), vec![2; 10]))
+        .unwrap();
+    // Swap the long name for one that climbs out of the target, like a crafted image would.
+    let root = (1 + 2 * 39) * 512;
+    let evil = lfn::build_entries("../../pwn.txt", b"ESCAPE~1TXT").unwrap();
+    volume.data[root..root + 32].copy_from_slice(&evil[0]);
+    assert_eq!(volume.read_dir("").unwrap()[0].get_name(), "../../pwn.txt");
+
+    let scratch = scratch_dir("export-unsafe");
+    let host = scratch.join("a/b");
+    std::fs::create_dir_all(&host).unwrap();
+    std::fs::write(host.join("KEEP.TXT"), b"mine").unwrap();
+    let report = volume
+        .export_dir("", &host, &scratch.join("attributes.txt"))
+        .unwrap();
+    assert_eq!(
+        report.errors,
+        [
+            (
+                "../../pwn.txt".to_string(),
+                FsError::InvalidName("../../pwn.txt".to_string())
+            ),
+            (
+                "KEEP.TXT".to_string(),
+                FsError::AlreadyExists("KEEP.TXT".to_string())
+            ),
+        ]
+    );
+    assert!(!scratch.join("pwn.txt").exists());
+    assert_eq!(std::fs::read(host.join("KEEP.TXT")).unwrap(), b"mine");
+    std::fs::remove_dir_all(&scratch).unwrap();
+}
+
+#[test]
+pub fn codepage_tables_roundtrip() {
+    for codepage in [Codepage::Cp437, Codepage::Cp850, Codepage::Cp866] {
+        for byte in 0..=255u8 {
+            let decoded = codepage.decode(&[byte]);
+            let character = decoded.chars().next().unwrap();
+            assert_eq!(codepage.encode_char(character), Some(byte));
+        }
+        assert_eq!(Codepage::from_number(codepage.get_number()), Some(codepage));
+    }
+}
+
+#[test]
+pub fn normalize_name_codepages() {
+    let name = File::normalize_name("Café.txt", &[]).unwrap();
+    assert_eq!(&name, b"CAF\x90    TXT");
+    let name = File::normalize_name_in("Café.txt", &[], Codepage::Cp866).unwrap();
+    assert_eq!(&name, b"CAF~1   TXT");
+    let name = File::normalize_name_in("игра.exe", &[], Codepage::Cp866).unwrap();
+    assert_eq!(&name, b"\x88\x83\x90\x80    EXE");
+    // There's no uppercase á in 437, but 850 has one.
+    assert_eq!(
+        &File::normalize_name("ádám.txt", &[]).unwrap(),
+        b"DM~1    TXT"
+    );
+    let name = File::normalize_name_in("ádám.txt", &[], Codepage::Cp850).unwrap();
+    assert_eq!(&name, b"\xB5D\xB5M    TXT");
+}
+
+#[test]
+pub fn volume_codepage_names() {
+    let mut volume = Volume::new(20000);
+    volume.set_codepage(Codepage::Cp866);
+    volume
+        .add_file("", File::new("игра.exe".to_string(), b"MZ".to_vec()))
+        .unwrap();
+    assert_eq!(volume.read_dir("").unwrap()[0].get_short_name(), "ИГРА.EXE");
+    assert_eq!(volume.read_file("игра.exe").unwrap(), b"MZ");
+    volume.set_codepage(Codepage::Cp437);
+    assert_eq!(volume.read_dir("").unwrap()[0].get_short_name(), "êâÉÇ.EXE");
+}
+
+#[test]
+pub fn codepage_labels() {
+    let label = Codepage::Cp850.encode_label("Jeux à moi").unwrap();
+    assert_eq!(&label, b"JEUX \xB7 MOI ");
+    assert_eq!(Codepage::Cp850.decode_label(&label), "JEUX À MOI");
+    assert!(Codepage::Cp437.encode_label("TOO LONG LABEL").is_err());
+    assert!(Codepage::Cp437.encode_label("DOS.622").is_err());
+}
+
+#[test]
+pub fn volume_label_in_root_and_boot_sector() {
+    let mut volume = Volume::new(20000);
+    volume.set_source_date_epoch(Some(Timestamp::default()));
+    assert_eq!(volume.get_label(), None);
+    volume
+        .add_file("", File::new("GAMES".to_string(), b"not a label".to_vec()))
+        .unwrap();
+    volume.set_label("games").unwrap();
+    assert_eq!(volume.get_label(), Some("GAMES".to_string()));
+    assert_eq!(&volume.as_bytes()[43..54], b"GAMES      ");
+    assert_eq!(volume.read_file("GAMES").unwrap(), b"not a label");
+
+    volume.set_label("Work Disk").unwrap();
+    let loaded = Volume::from_bytes(volume.as_bytes().to_vec()).unwrap();
+    assert_eq!(loaded.get_label(), Some("WORK DISK".to_string()));
+    let labels = loaded.read_dir("").unwrap();
+    assert_eq!(labels.iter().filter(|e| e.attributes.vol_id).count(), 1);
+
+    volume.remove_label();
+    assert_eq!(volume.get_label(), None);
+    assert_eq!(&volume.as_bytes()[43..54], b"NO NAME    ");
+    assert!(volume.set_label("NO.DOTS").is_err());
+}
+
+#[test]
+pub fn format_options_default_layout() {
+    for sector_count in [MIN_SECTORS, 20000, 94532, 1048576, 4000000] {
+        let vbr = VBR::from_options(sector_count, &FormatOptions::new()).unwrap();
+        assert_eq!(vbr, VBR::new(sector_count));
+    }
+}
+
+#[test]
+pub fn format_options_custom_layout() {
+    let options = FormatOptions::new()
+        .cluster_size(8192)
+        .root_entries(1024)
+        .fats(1)
+        .reserved_sectors(4);
+    let mut volume = Volume::with_options(131072, &options).unwrap();
+    let boot_record = volume.get_boot_record();
+    assert_eq!(boot_record.get_sectors_per_cluster(), 16);
+    assert_eq!(boot_record.get_root_dir_entries_count(), 1024);
+    assert_eq!(boot_record.get_fats_count(), 1);
+    assert_eq!(boot_record.get_reserved_sectors_count(), 4);
+    for index in 0..600 {
+        let name = format!("FILE{}.DAT", index);
+        volume
+            .add_file("", File::new(name, vec![0x42; 100]))
+            .unwrap();
+    }
+    assert_eq!(volume.read_file("FILE599.DAT").unwrap(), vec![0x42; 100]);
+    assert!(volume.check(false).is_clean());
+
+    let mut small = Volume::with_options(4000, &FormatOptions::new().cluster_size(1024)).unwrap();
+    assert!(small.is_fat12());
+    assert_eq!(&small.as_bytes()[54..62], b"FAT12   ");
+    small
+        .add_file("", File::new("BIG.BIN".to_string(), vec![7; 100000]))
+        .unwrap();
+    assert_eq!(small.read_file("BIG.BIN").unwrap(), vec![7; 100000]);
+    assert!(small.check(false).is_clean());
+}
+
+#[test]
+pub fn format_options_validation() {
+    let invalid = |sector_count, options: FormatOptions| {
+        matches!(
+            Volume::with_options(sector_count, &options),
+            Err(FsError::InvalidFormat(_))
+        )
+    };
+    assert!(invalid(20000, FormatOptions::new().cluster_size(3072)));
+    assert!(invalid(20000, FormatOptions::new().cluster_size(65536)));
+    assert!(invalid(20000, FormatOptions::new().root_entries(100)));
+    assert!(invalid(20000, FormatOptions::new().fats(3)));
+    assert!(invalid(20000, FormatOptions::new().reserved_sectors(0)));
+    assert!(invalid(2097152, FormatOptions::new().cluster_size(512)));
+    assert_eq!(
+        Volume::with_options(100, &FormatOptions::new()).unwrap_err(),
+        FsError::InvalidSize(100)
+    );
+}
+
+#[test]
+pub fn diagnose_boot_record() {
+    let volume = Volume::new(20000);
+    assert!(Volume::diagnose_boot_record(volume.as_bytes()).is_empty());
+    assert_eq!(
+        Volume::diagnose_boot_record(&[0u8; 100]),
+        vec![BootRecordProblem::TooShort(100)]
+    );
+
+    let mut sector = volume.as_bytes()[0..512].to_vec();
+    sector[0] = 0x00;
+    sector[11..13].copy_from_slice(&1000u16.to_le_bytes());
+    sector[13] = 3;
+    sector[510] = 0x00;
+    let problems = Volume::diagnose_boot_record(&sector);
+    assert_eq!(
+        problems,
+        vec![
+            BootRecordProblem::MissingSignature([0x00, 0xAA]),
+            BootRecordProblem::InvalidJump([0x00, 0x3C, 0x90]),
+            BootRecordProblem::BytesPerSector(1000),
+            BootRecordProblem::SectorsPerCluster(3),
+        ]
+    );
+
+    let mut sector = volume.as_bytes()[0..512].to_vec();
+    sector[22..24].copy_from_slice(&10u16.to_le_bytes());
+    sector[21] = 0x12;
+    let problems = Volume::diagnose_boot_record(&sector);
+    assert_eq!(problems[0], BootRecordProblem::MediaDescriptor(0x12));
+    assert!(!problems[0].is_fatal());
+    assert!(matches!(
+        problems[1],
+        BootRecordProblem::FatTooSmall {
+            sectors_per_fat: 10,
+            ..
+        }
+    ));
+    let mut data = volume.as_bytes().to_vec();
+    data[22..24].copy_from_slice(&10u16.to_le_bytes());
+    assert!(matches!(
+        Volume::from_bytes(data),
+        Err(FsError::InvalidBootRecord(reason)) if reason.starts_with("FAT has 10 sectors")
+    ));
+}
@@ -0,0 +1,131 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A date and time as stored in FAT directory entries. DOS keeps local time without any
+/// notion of time zones, we treat everything as UTC so that results don't depend on the
+/// machine an image gets built on. The range is 1980 up to and including 2107.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    pub(crate) year: u16,
+    pub(crate) month: u8,
+    pub(crate) day: u8,
+    pub(crate) hour: u8,
+    pub(crate) minute: u8,
+    pub(crate) second: u8,
+}
+
+impl Default for Timestamp {
+    /// The earliest moment a FAT file system can record: January 1st, 1980 at midnight.
+    fn default() -> Timestamp {
+        Timestamp::new(1980, 1, 1, 0, 0, 0)
+    }
+}
+
+impl Timestamp {
+    /// Instantiate a new Timestamp. Values outside of what DOS can store get clamped.
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Timestamp {
+        Timestamp {
+            year: year.clamp(1980, 2107),
+            month: month.clamp(1, 12),
+            day: day.clamp(1, 31),
+            hour: hour.min(23),
+            minute: minute.min(59),
+            second: second.min(59),
+        }
+    }
+
+    /// Convert a number of seconds since the Unix epoch, the same value SOURCE_DATE_EPOCH holds.
+    pub fn from_unix(seconds: u64) -> Timestamp {
+        let days = seconds / 86400;
+        let remainder = seconds % 86400;
+
+        // Civil-from-days algorithm by Howard Hinnant, shifted so that years start in March.
+        let shifted = days + 719468;
+        let era = shifted / 146097;
+        let day_of_era = shifted % 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+        Timestamp::new(
+            u16::try_from(year).unwrap_or(u16::MAX),
+            month as u8,
+            day as u8,
+            (remainder / 3600) as u8,
+            (remainder % 3600 / 60) as u8,
+            (remainder % 60) as u8,
+        )
+    }
+
+    /// Convert a timestamp from the host, such as the modification time of a file.
+    pub fn from_system_time(time: SystemTime) -> Timestamp {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => Timestamp::from_unix(duration.as_secs()),
+            Err(_) => Timestamp::default(),
+        }
+    }
+
+    /// The number of seconds since the Unix epoch, the inverse of from_unix.
+    pub fn as_unix(&self) -> u64 {
+        // Days-from-civil, the counterpart of the algorithm in from_unix.
+        let (month, day) = (u64::from(self.month), u64::from(self.day));
+        let year = u64::from(self.year) - u64::from(month <= 2);
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+        days * 86400
+            + u64::from(self.hour) * 3600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second)
+    }
+
+    /// Convert to a timestamp on the host, such as for setting the modification time of a file.
+    pub fn as_system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.as_unix())
+    }
+
+    /// The current time on the host.
+    pub fn now() -> Timestamp {
+        Timestamp::from_system_time(SystemTime::now())
+    }
+
+    /// Rebuild a Timestamp from the packed on-disk values. The tenths byte is only present
+    /// for creation times and carries the odd second that doesn't fit in the time field.
+    pub fn from_dos(date: u16, time: u16, tenths: u8) -> Timestamp {
+        let second = ((time & 0x1F) * 2) as u8 + (tenths / 100).min(1);
+        Timestamp::new(
+            1980 + (date >> 9),
+            ((date >> 5) & 0x0F) as u8,
+            (date & 0x1F) as u8,
+            (time >> 11) as u8,
+            ((time >> 5) & 0x3F) as u8,
+            second,
+        )
+    }
+
+    /// The packed date: bits 15-9 hold the year since 1980, bits 8-5 the month and 4-0 the day.
+    pub fn as_dos_date(&self) -> u16 {
+        ((self.year - 1980) << 9) | (u16::from(self.month) << 5) | u16::from(self.day)
+    }
+
+    /// The packed time: bits 15-11 hold the hour, bits 10-5 the minutes and 4-0 the seconds
+    /// divided by two, so odd seconds get rounded down.
+    pub fn as_dos_time(&self) -> u16 {
+        (u16::from(self.hour) << 11) | (u16::from(self.minute) << 5) | u16::from(self.second / 2)
+    }
+
+    /// The creation time fine resolution in units of 10 milliseconds, this is where the odd
+    /// second goes that the packed time can't hold.
+    pub fn as_dos_tenths(&self) -> u8 {
+        (self.second % 2) * 100
+    }
+}
@@ -1,4 +1,105 @@
-#[derive(Debug, PartialEq)]
+use crate::fs::resize::{MAX_SECTORS, MIN_SECTORS};
+use crate::fs::volume::FAT12_MAX_CLUSTERS;
+use crate::fs::{FormatOptions, FsError};
+use std::fmt;
+
+/// Something wrong with a boot sector. Some of these make the volume unusable, others are only
+/// odd and DOS lives with them, see is_fatal().
+#[derive(Clone, Debug, PartialEq)]
+pub enum BootRecordProblem {
+    /// There's less than a full sector to look at.
+    TooShort(usize),
+    /// The sector doesn't end in 0x55 0xAA.
+    MissingSignature([u8; 2]),
+    /// The first bytes aren't a short or near jump into the boot code.
+    InvalidJump([u8; 3]),
+    /// Bytes per sector is not a power of two from 512 up to 4096.
+    BytesPerSector(u16),
+    /// Sectors per cluster is not a power of two.
+    SectorsPerCluster(u8),
+    /// There are no reserved sectors, so not even room for the boot sector itself.
+    ReservedSectors(u16),
+    /// There are no copies of the FAT.
+    FatCount(u8),
+    /// The root directory has no entries, or doesn't fill whole sectors.
+    RootEntries(u16),
+    /// Neither of the two sector count fields holds the size of the volume.
+    SectorCount,
+    /// The media descriptor isn't one DOS knows about.
+    MediaDescriptor(u8),
+    /// The FAT and root directory take up the whole volume, there's no data area left.
+    NoDataArea,
+    /// The FAT has fewer sectors than it needs to hold an entry for every cluster.
+    FatTooSmall { sectors_per_fat: u16, needed: u32 },
+}
+
+impl BootRecordProblem {
+    /// Whether the volume can't be used at all with this problem. The others are worth a
+    /// warning, but the layout still makes sense.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            BootRecordProblem::MissingSignature(_)
+            | BootRecordProblem::InvalidJump(_)
+            | BootRecordProblem::MediaDescriptor(_) => false,
+            BootRecordProblem::RootEntries(entries) => *entries == 0,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for BootRecordProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootRecordProblem::TooShort(length) => {
+                write!(f, "boot sector is only {} bytes long", length)
+            }
+            BootRecordProblem::MissingSignature(signature) => {
+                write!(f, "boot sector ends in {:02X?} instead of 55 AA", signature)
+            }
+            BootRecordProblem::InvalidJump(jump) => {
+                write!(f, "boot sector starts with {:02X?} instead of a jump", jump)
+            }
+            BootRecordProblem::BytesPerSector(bytes) => {
+                write!(f, "{} bytes per sector is not a power of two", bytes)
+            }
+            BootRecordProblem::SectorsPerCluster(sectors) => {
+                write!(f, "{} sectors per cluster is not a power of two", sectors)
+            }
+            BootRecordProblem::ReservedSectors(sectors) => {
+                write!(
+                    f,
+                    "{} reserved sectors leaves no room for the boot sector",
+                    sectors
+                )
+            }
+            BootRecordProblem::FatCount(count) => write!(f, "{} copies of the FAT", count),
+            BootRecordProblem::RootEntries(entries) => {
+                write!(
+                    f,
+                    "{} root directory entries don't fill whole sectors",
+                    entries
+                )
+            }
+            BootRecordProblem::SectorCount => write!(f, "volume has no sector count"),
+            BootRecordProblem::MediaDescriptor(media) => {
+                write!(f, "unknown media descriptor {:02X}", media)
+            }
+            BootRecordProblem::NoDataArea => {
+                write!(f, "FAT and root directory leave no room for any data")
+            }
+            BootRecordProblem::FatTooSmall {
+                sectors_per_fat,
+                needed,
+            } => write!(
+                f,
+                "FAT has {} sectors but needs {} for all clusters",
+                sectors_per_fat, needed
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) struct VBR {
     jump_bytes: [u8; 3],
     oem_name: [u8; 8],
@@ -44,11 +145,182 @@ impl VBR {
             drive_number: 0x80,
             extended_boot_signature: 0x29,
             volume_serial: 1664469745,
-            volume_label: *b"DOSCNTNR   ",
+            // No label until one is set. This used to be "DOSCNTNR", but format() now puts any
+            // label in the root directory as well, and that entry would take the slot IO.SYS
+            // needs to be in for the disk to boot.
+            volume_label: *b"NO NAME    ",
             filesystem_type: *b"FAT16  ",
         }
     }
 
+    /// Instantiate a Volume Boot Record with the layout from a set of format options. Those
+    /// get validated first, so unlike new() this doesn't panic on sizes FAT16 can't do.
+    pub(crate) fn from_options(
+        volume_sector_count: u32,
+        options: &FormatOptions,
+    ) -> Result<Self, FsError> {
+        let (sectors_per_cluster, sectors_per_fat) = options.layout(volume_sector_count)?;
+        let mut vbr = VBR {
+            sectors_per_cluster,
+            reserved_sectors_count: options.reserved_sectors,
+            fats_count: options.fats,
+            root_dir_entries_count: options.root_entries,
+            sectors_per_fat,
+            volume_sectors_count: VBR::set_sectors_count16(volume_sector_count),
+            volume_sectors_count32: VBR::set_sectors_count32(volume_sector_count),
+            ..VBR::new(MIN_SECTORS)
+        };
+        if vbr.get_cluster_count() < FAT12_MAX_CLUSTERS {
+            vbr.filesystem_type = *b"FAT12  ";
+        }
+        Ok(vbr)
+    }
+
+    /// The boot record FORMAT would pick for a volume of this size, without the panics of
+    /// new(). Volumes too small for the FAT16 table get FAT12 with 4K clusters, like FORMAT
+    /// gives small hard disk partitions. Sizes no FAT can do at all keep the layout of the
+    /// smallest FAT16 volume, which format_with() or a volume loaded from disk replaces.
+    pub(crate) fn for_size(volume_sector_count: u32) -> Self {
+        let small = FormatOptions::new().cluster_size(4096);
+        VBR::from_options(volume_sector_count, &FormatOptions::new())
+            .or_else(|_| VBR::from_options(volume_sector_count, &small))
+            .unwrap_or_else(|_| VBR {
+                volume_sectors_count: VBR::set_sectors_count16(volume_sector_count),
+                volume_sectors_count32: VBR::set_sectors_count32(volume_sector_count),
+                ..VBR::new(MIN_SECTORS)
+            })
+    }
+
+    /// Parse a Volume Boot Record from the first sector of a volume. This is the inverse
+    /// of as_bytes(), the boot code is everything from the file system type up to the
+    /// signature. The caller has to make sure there are at least 512 bytes.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        let mut vbr = VBR {
+            jump_bytes: [0; 3],
+            oem_name: [0; 8],
+            bytes_per_sector: u16_at(11),
+            sectors_per_cluster: bytes[13],
+            reserved_sectors_count: u16_at(14),
+            fats_count: bytes[16],
+            root_dir_entries_count: u16_at(17),
+            sectors_per_fat: u16_at(22),
+            media_descriptor: bytes[21],
+            sectors_per_track: u16_at(24),
+            heads_count: u16_at(26),
+            hidden_sectors_count: u32_at(28),
+            volume_boot_code: bytes[61..509].to_vec(),
+            volume_sectors_count: u16_at(19),
+            volume_sectors_count32: u32_at(32),
+            drive_number: bytes[36],
+            extended_boot_signature: bytes[38],
+            volume_serial: u32_at(39),
+            volume_label: [0; 11],
+            filesystem_type: [0; 7],
+        };
+        vbr.jump_bytes.copy_from_slice(&bytes[0..3]);
+        vbr.oem_name.copy_from_slice(&bytes[3..11]);
+        vbr.volume_label.copy_from_slice(&bytes[43..54]);
+        vbr.filesystem_type.copy_from_slice(&bytes[54..61]);
+        vbr
+    }
+
+    /// Look for anything in a boot sector that doesn't add up, from the signature to whether
+    /// the FAT is large enough for the clusters it has to track. An empty list means from_bytes()
+    /// gives a boot record that can be used as-is.
+    pub(crate) fn diagnose(sector: &[u8]) -> Vec<BootRecordProblem> {
+        if sector.len() < 512 {
+            return vec![BootRecordProblem::TooShort(sector.len())];
+        }
+        let mut problems = Vec::<BootRecordProblem>::new();
+        if sector[510..512] != [0x55, 0xAA] {
+            problems.push(BootRecordProblem::MissingSignature([
+                sector[510],
+                sector[511],
+            ]));
+        }
+        let vbr = VBR::from_bytes(sector);
+        match vbr.jump_bytes {
+            [0xEB, _, 0x90] | [0xE9, _, _] => {}
+            jump => problems.push(BootRecordProblem::InvalidJump(jump)),
+        }
+        let bytes_per_sector = vbr.bytes_per_sector;
+        let sector_size_valid =
+            bytes_per_sector.is_power_of_two() && (512..=4096).contains(&bytes_per_sector);
+        if !sector_size_valid {
+            problems.push(BootRecordProblem::BytesPerSector(bytes_per_sector));
+        }
+        if !vbr.sectors_per_cluster.is_power_of_two() {
+            problems.push(BootRecordProblem::SectorsPerCluster(
+                vbr.sectors_per_cluster,
+            ));
+        }
+        if vbr.reserved_sectors_count == 0 {
+            problems.push(BootRecordProblem::ReservedSectors(0));
+        }
+        if vbr.fats_count == 0 {
+            problems.push(BootRecordProblem::FatCount(0));
+        }
+        // Whole sectors only make sense to count in with a sector size that does.
+        let entries_per_sector = if sector_size_valid {
+            bytes_per_sector / 32
+        } else {
+            1
+        };
+        if vbr.root_dir_entries_count == 0
+            || !vbr
+                .root_dir_entries_count
+                .is_multiple_of(entries_per_sector)
+        {
+            problems.push(BootRecordProblem::RootEntries(vbr.root_dir_entries_count));
+        }
+        if vbr.get_volume_sectors_count() == 0 {
+            problems.push(BootRecordProblem::SectorCount);
+        }
+        if vbr.media_descriptor != 0xF0 && vbr.media_descriptor < 0xF8 {
+            problems.push(BootRecordProblem::MediaDescriptor(vbr.media_descriptor));
+        }
+
+        // The rest is arithmetic on the fields above, which only means something when those
+        // are sane to begin with.
+        if problems.iter().any(BootRecordProblem::is_fatal) {
+            return problems;
+        }
+        let root_dir_sectors = num::integer::div_ceil(
+            u32::from(vbr.root_dir_entries_count) * 32,
+            u32::from(bytes_per_sector),
+        );
+        let metadata_sectors = u32::from(vbr.reserved_sectors_count)
+            + u32::from(vbr.fats_count) * u32::from(vbr.sectors_per_fat)
+            + root_dir_sectors;
+        if metadata_sectors >= vbr.get_volume_sectors_count() {
+            problems.push(BootRecordProblem::NoDataArea);
+        } else {
+            let entries = vbr.get_cluster_count() + 2;
+            let fat_bytes = if vbr.get_cluster_count() < FAT12_MAX_CLUSTERS {
+                num::integer::div_ceil(entries * 3, 2)
+            } else {
+                entries * 2
+            };
+            let needed = num::integer::div_ceil(fat_bytes, u32::from(bytes_per_sector));
+            if u32::from(vbr.sectors_per_fat) < needed {
+                problems.push(BootRecordProblem::FatTooSmall {
+                    sectors_per_fat: vbr.sectors_per_fat,
+                    needed,
+                });
+            }
+        }
+        problems
+    }
+
     fn set_sectors_count16(volume_sector_count: u32) -> u16 {
         if volume_sector_count < 65536 {
             return u16::try_from(volume_sector_count).unwrap();
@@ -127,6 +399,149 @@ impl VBR {
         return &self.volume_boot_code;
     }
 
+    pub(crate) fn get_bytes_per_sector(&self) -> u16 {
+        self.bytes_per_sector
+    }
+
+    pub(crate) fn get_sectors_per_cluster(&self) -> u8 {
+        self.sectors_per_cluster
+    }
+
+    pub(crate) fn get_reserved_sectors_count(&self) -> u16 {
+        self.reserved_sectors_count
+    }
+
+    pub(crate) fn get_fats_count(&self) -> u8 {
+        self.fats_count
+    }
+
+    pub(crate) fn get_root_dir_entries_count(&self) -> u16 {
+        self.root_dir_entries_count
+    }
+
+    pub(crate) fn get_sectors_per_fat(&self) -> u16 {
+        self.sectors_per_fat
+    }
+
+    pub(crate) fn get_media_descriptor(&self) -> u8 {
+        self.media_descriptor
+    }
+
+    pub(crate) fn get_sectors_per_track(&self) -> u16 {
+        self.sectors_per_track
+    }
+
+    pub(crate) fn get_heads_count(&self) -> u16 {
+        self.heads_count
+    }
+
+    pub(crate) fn get_hidden_sectors_count(&self) -> u32 {
+        self.hidden_sectors_count
+    }
+
+    pub(crate) fn get_volume_serial(&self) -> u32 {
+        self.volume_serial
+    }
+
+    /// The geometry INT 13h reports for the disk, which the boot code needs to turn sector
+    /// numbers into cylinders, heads and sectors.
+    pub(crate) fn set_geometry(&mut self, sectors_per_track: u16, heads: u16) {
+        self.sectors_per_track = sectors_per_track;
+        self.heads_count = heads;
+    }
+
+    pub(crate) fn set_volume_serial(&mut self, serial: u32) {
+        self.volume_serial = serial;
+    }
+
+    /// The label in the extended BPB, "NO NAME" padded with spaces when there is none.
+    pub(crate) fn get_volume_label(&self) -> [u8; 11] {
+        self.volume_label
+    }
+
+    pub(crate) fn set_volume_label(&mut self, label: [u8; 11]) {
+        self.volume_label = label;
+    }
+
+    /// Whether the boot sector has the extended BPB that DOS 4.0 introduced, which is where the
+    /// serial number and the label live. Older boot sectors have boot code in that spot.
+    pub(crate) fn has_extended_bpb(&self) -> bool {
+        self.extended_boot_signature == 0x29
+    }
+
+    /// The size of the volume in sectors, from whichever of the two BPB fields is in use.
+    pub(crate) fn get_volume_sectors_count(&self) -> u32 {
+        if self.volume_sectors_count != 0 {
+            return u32::from(self.volume_sectors_count);
+        }
+        self.volume_sectors_count32
+    }
+
+    /// The format options that describe the layout of this boot record. The cluster size is
+    /// only pinned when it isn't the one FORMAT would have picked anyway, so a volume with the
+    /// default layout keeps following the table as it grows or shrinks.
+    pub(crate) fn format_options(&self) -> FormatOptions {
+        let sector_count = self.get_volume_sectors_count();
+        let options = FormatOptions::new()
+            .root_entries(self.root_dir_entries_count)
+            .fats(self.fats_count)
+            .reserved_sectors(self.reserved_sectors_count);
+        if (MIN_SECTORS..=MAX_SECTORS).contains(&sector_count)
+            && VBR::set_sectors_per_cluster(sector_count) == self.sectors_per_cluster
+        {
+            return options;
+        }
+        options.cluster_size(u32::from(self.sectors_per_cluster) * 512)
+    }
+
+    /// A copy of this boot record for a volume of a different size. Cluster size and FAT size
+    /// get recalculated from the format options of this record, so the layout it was made
+    /// with carries over. Everything else is kept as-is, apart from the file system type,
+    /// which follows the number of clusters that's left.
+    pub(crate) fn resized(&self, volume_sector_count: u32) -> Result<VBR, FsError> {
+        let (sectors_per_cluster, sectors_per_fat) =
+            self.format_options().layout(volume_sector_count)?;
+        let mut vbr = self.clone();
+        vbr.sectors_per_cluster = sectors_per_cluster;
+        vbr.sectors_per_fat = sectors_per_fat;
+        vbr.volume_sectors_count = VBR::set_sectors_count16(volume_sector_count);
+        vbr.volume_sectors_count32 = VBR::set_sectors_count32(volume_sector_count);
+        vbr.filesystem_type = if vbr.get_cluster_count() < FAT12_MAX_CLUSTERS {
+            *b"FAT12  "
+        } else {
+            *b"FAT16  "
+        };
+        Ok(vbr)
+    }
+
+    /// The partition type the MBR needs for this file system, so that DOS mounts it the
+    /// way it is laid out: 0x01 for FAT12, 0x04 for FAT16 under 32MB and 0x06 for larger
+    /// FAT16 volumes.
+    pub(crate) fn get_partition_type(&self) -> u8 {
+        if self.get_cluster_count() < FAT12_MAX_CLUSTERS {
+            0x01
+        } else if self.get_volume_sectors_count() < 65536 {
+            0x04
+        } else {
+            0x06
+        }
+    }
+
+    /// Number of data clusters on a volume described by this boot record.
+    pub(crate) fn get_cluster_count(&self) -> u32 {
+        let bytes_per_sector = u32::from(self.bytes_per_sector);
+        let root_dir_sectors = num::integer::div_ceil(
+            u32::from(self.root_dir_entries_count) * 32,
+            bytes_per_sector,
+        );
+        let metadata_sectors = u32::from(self.reserved_sectors_count)
+            + u32::from(self.fats_count) * u32::from(self.sectors_per_fat)
+            + root_dir_sectors;
+        self.get_volume_sectors_count()
+            .saturating_sub(metadata_sectors)
+            / u32::from(self.sectors_per_cluster)
+    }
+
     /// Jump to the bootstrap routine. These are three
     /// x86 machine language instructions that constitute
     /// a jump into the machine language routine that's
@@ -0,0 +1,923 @@
+use crate::fs::direntry::{DirEntry, DELETED_MARKER};
+use crate::fs::label::NO_LABEL;
+use crate::fs::lfn;
+use crate::fs::lfn::LongNameReader;
+use crate::fs::vbr::{BootRecordProblem, VBR};
+use crate::fs::{Codepage, File, FileAttributes, FileHandle, FormatOptions, FsError, Timestamp};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Directory entries are always 32 bytes, regardless of FAT type.
+const ENTRY_SIZE: usize = 32;
+
+/// Byte offset of the volume serial number inside the boot sector of a FAT12/16 volume.
+const SERIAL_OFFSET: usize = 39;
+
+/// Byte offset of the volume label inside the boot sector of a FAT12/16 volume.
+const LABEL_OFFSET: usize = 43;
+
+/// Volumes with fewer clusters than this are FAT12, see page 15 of the Microsoft spec.
+pub(crate) const FAT12_MAX_CLUSTERS: u32 = 4085;
+
+/// A directory entry together with the byte offsets of every slot it occupies in its
+/// directory: the long file name fragments first, the short entry last.
+#[derive(Clone, Debug)]
+pub(crate) struct LocatedEntry {
+    pub(crate) slots: Vec<usize>,
+    pub(crate) entry: DirEntry,
+}
+
+impl LocatedEntry {
+    /// Byte offset of the short entry, which is where the actual metadata lives.
+    pub(crate) fn get_offset(&self) -> usize {
+        *self.slots.last().unwrap()
+    }
+}
+
+/// A formatted FAT12/16 volume that lives entirely in memory. All structures are kept in
+/// `data` exactly as they end up on disk, so the bytes can be copied into a partition as-is.
+/// Directories are addressed by the cluster they start at, with 0 meaning the root directory,
+/// which is the same convention the ".." entries use on disk.
+///
+/// Building a volume is deterministic: entries go into a directory in the order they are
+/// added, clusters are always handed out lowest number first, and nothing depends on the
+/// clock or the host once a source date epoch and a serial number have been set.
+#[derive(Clone, PartialEq)]
+pub struct Volume {
+    boot_record: VBR,
+    pub(crate) data: Vec<u8>,
+    long_names: bool,
+    source_date_epoch: Option<Timestamp>,
+    codepage: Codepage,
+}
+
+impl fmt::Debug for Volume {
+    /// Leave out the raw data, nobody wants to scroll past a hundred megabytes of zeroes.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Volume")
+            .field("boot_record", &self.boot_record)
+            .field("size", &self.data.len())
+            .field("long_names", &self.long_names)
+            .field("source_date_epoch", &self.source_date_epoch)
+            .field("codepage", &self.codepage)
+            .finish()
+    }
+}
+
+impl Volume {
+    /// Instantiate a new, freshly formatted volume of a certain size in sectors.
+    pub fn new(sector_count: u32) -> Self {
+        Volume::format(VBR::new(sector_count))
+    }
+
+    /// Instantiate a new, freshly formatted volume with the layout picked by the caller.
+    pub fn with_options(sector_count: u32, options: &FormatOptions) -> Result<Self, FsError> {
+        Ok(Volume::format(VBR::from_options(sector_count, options)?))
+    }
+
+    /// Lay down an empty file system as described by a Volume Boot Record: the boot sector
+    /// itself, all copies of the FAT and an empty root directory.
+    pub(crate) fn format(boot_record: VBR) -> Self {
+        let size = boot_record.get_volume_sectors_count() as usize
+            * usize::from(boot_record.get_bytes_per_sector());
+        let mut volume = Volume {
+            boot_record,
+            data: vec![0u8; size],
+            long_names: false,
+            source_date_epoch: None,
+            codepage: Codepage::default(),
+        };
+        let boot_sector = volume.boot_record.as_bytes();
+        volume.data[..boot_sector.len()].copy_from_slice(&boot_sector);
+        volume.data[0x1FE] = 0x55;
+        volume.data[0x1FF] = 0xAA;
+
+        // The first two FAT entries are reserved. The first holds the media descriptor, the
+        // second an end-of-chain marker with the "clean shutdown" bits set.
+        let media = u16::from(volume.boot_record.get_media_descriptor());
+        let end_of_chain = volume.end_of_chain();
+        volume.set_fat_entry(0, (end_of_chain & 0xFF00) | media);
+        volume.set_fat_entry(1, end_of_chain);
+
+        // A label in the boot record also goes into the root directory, where DIR and LABEL
+        // look for it. It gets the earliest DOS date so that formatting stays deterministic.
+        let label = volume.boot_record.get_volume_label();
+        if &label != NO_LABEL {
+            volume
+                .write_label_entry(label, Timestamp::default())
+                .expect("a freshly formatted root directory has room for a label");
+        }
+        volume
+    }
+
+    /// Load an existing volume from its raw bytes, starting at its boot sector. Volumes made
+    /// by MS-DOS itself work just as well as the ones built here. Long file names are enabled
+    /// on the loaded volume when any of its directories turn out to contain them.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, FsError> {
+        if let Some(problem) = VBR::diagnose(&data)
+            .into_iter()
+            .find(BootRecordProblem::is_fatal)
+        {
+            return Err(FsError::InvalidBootRecord(problem.to_string()));
+        }
+        let boot_record = VBR::from_bytes(&data);
+        let size = boot_record.get_volume_sectors_count() as usize
+            * usize::from(boot_record.get_bytes_per_sector());
+        if size > data.len() {
+            return Err(FsError::InvalidBootRecord(String::from(
+                "volume is larger than the data it was loaded from",
+            )));
+        }
+        let mut volume = Volume {
+            boot_record,
+            data,
+            long_names: false,
+            source_date_epoch: None,
+            codepage: Codepage::default(),
+        };
+        volume.data.truncate(size);
+        if volume.data_offset() > volume.data.len() {
+            return Err(FsError::InvalidBootRecord(String::from(
+                "FAT and root directory don't fit on the volume",
+            )));
+        }
+        volume.long_names = volume.contains_long_names(0);
+        Ok(volume)
+    }
+
+    /// Everything that's wrong or odd about the boot sector at the start of some raw volume
+    /// data. This works on data that from_bytes() refuses, so it can tell why.
+    pub fn diagnose_boot_record(data: &[u8]) -> Vec<BootRecordProblem> {
+        VBR::diagnose(data)
+    }
+
+    /// Whether a directory or any directory below it contains long file name entries. Every
+    /// directory gets visited only once, so loops in a damaged tree can't trip this up.
+    fn contains_long_names(&self, dir_cluster: u16) -> bool {
+        let mut pending = vec![dir_cluster];
+        let mut visited = Vec::<u16>::new();
+        while let Some(dir_cluster) = pending.pop() {
+            visited.push(dir_cluster);
+            for entry in self.read_dir_at(dir_cluster) {
+                if entry.long_name.is_some() {
+                    return true;
+                }
+                if entry.is_dir()
+                    && !entry.is_dot_entry()
+                    && !visited.contains(&entry.first_cluster)
+                {
+                    pending.push(entry.first_cluster);
+                }
+            }
+        }
+        false
+    }
+
+    /// Enable or disable VFAT long file names on this volume. Leave this off for volumes
+    /// that will only ever see plain DOS, so that only short entries get written.
+    pub fn set_long_names(&mut self, enabled: bool) {
+        self.long_names = enabled;
+    }
+
+    /// Whether this volume gets VFAT long file name entries.
+    pub fn get_long_names(&self) -> bool {
+        self.long_names
+    }
+
+    /// Choose the OEM code page that short names and volume labels are stored in. This has to
+    /// match the DOS release the volume is meant for, code page 437 is the default. Changing it
+    /// doesn't touch anything already on the volume, it only changes how names are read and
+    /// written from here on.
+    pub fn set_codepage(&mut self, codepage: Codepage) {
+        self.codepage = codepage;
+    }
+
+    pub fn get_codepage(&self) -> Codepage {
+        self.codepage
+    }
+
+    /// Use a fixed moment in time for every file and directory that doesn't have an explicit
+    /// timestamp set through the API. Host modification times and the clock get ignored, so
+    /// two builds from the same input end up with byte-identical directory entries.
+    /// Pass None to go back to host modification times, or the current time if there's none.
+    pub fn set_source_date_epoch(&mut self, epoch: Option<Timestamp>) {
+        self.source_date_epoch = epoch;
+    }
+
+    pub fn get_source_date_epoch(&self) -> Option<Timestamp> {
+        self.source_date_epoch
+    }
+
+    /// The timestamp to use for anything that wasn't given one explicitly.
+    pub(crate) fn default_timestamp(&self, host_modified: Option<Timestamp>) -> Timestamp {
+        match (self.source_date_epoch, host_modified) {
+            (Some(epoch), _) => epoch,
+            (None, Some(host_modified)) => host_modified,
+            (None, None) => Timestamp::now(),
+        }
+    }
+
+    /// Set the volume serial number, both in the boot record and in the on-disk boot sector.
+    pub fn set_serial(&mut self, serial: u32) {
+        self.boot_record.set_volume_serial(serial);
+        self.data[SERIAL_OFFSET..SERIAL_OFFSET + 4].copy_from_slice(&serial.to_le_bytes());
+    }
+
+    /// Keep the label in the boot sector in line, if it has an extended BPB to hold one.
+    pub(crate) fn write_boot_label(&mut self, label: [u8; 11]) {
+        if !self.boot_record.has_extended_bpb() {
+            return;
+        }
+        self.boot_record.set_volume_label(label);
+        self.data[LABEL_OFFSET..LABEL_OFFSET + 11].copy_from_slice(&label);
+    }
+
+    pub fn get_serial(&self) -> u32 {
+        self.boot_record.get_volume_serial()
+    }
+
+    /// Derive the serial number from a seed chosen by the user, such as the name of the
+    /// container. The same seed always gives the same serial.
+    pub fn set_serial_from_seed(&mut self, seed: &str) {
+        self.set_serial(Volume::serial_from_hash(
+            Sha256::digest(seed.as_bytes()).as_slice(),
+        ));
+    }
+
+    /// Derive the serial number from everything else on the volume. Call this after all
+    /// files are in place, so that identical content always ends up with the same serial.
+    pub fn set_serial_from_content(&mut self) {
+        self.set_serial(0);
+        self.set_serial(Volume::serial_from_hash(
+            Sha256::digest(&self.data).as_slice(),
+        ));
+    }
+
+    fn serial_from_hash(hash: &[u8]) -> u32 {
+        u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]])
+    }
+
+    /// The raw bytes of the whole volume, ready to be copied into a partition.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Number of data clusters on this volume.
+    pub fn get_cluster_count(&self) -> u32 {
+        let data_bytes = self.data.len().saturating_sub(self.data_offset());
+        (data_bytes / self.get_cluster_size()) as u32
+    }
+
+    /// Size of a single cluster in bytes.
+    pub fn get_cluster_size(&self) -> usize {
+        usize::from(self.boot_record.get_sectors_per_cluster()) * self.bytes_per_sector()
+    }
+
+    /// Number of clusters that are not in use by anything.
+    pub fn get_free_cluster_count(&self) -> u32 {
+        let mut free = 0;
+        for cluster in 2..self.get_cluster_count() + 2 {
+            if self.get_fat_entry(cluster as u16) == 0 {
+                free += 1;
+            }
+        }
+        free
+    }
+
+    /// Whether this volume uses 12-bit FAT entries.
+    pub fn is_fat12(&self) -> bool {
+        self.get_cluster_count() < FAT12_MAX_CLUSTERS
+    }
+
+    pub(crate) fn get_boot_record(&self) -> &VBR {
+        &self.boot_record
+    }
+
+    pub(crate) fn bytes_per_sector(&self) -> usize {
+        usize::from(self.boot_record.get_bytes_per_sector())
+    }
+
+    /// Byte offset of one of the copies of the FAT.
+    pub(crate) fn fat_offset(&self, copy: usize) -> usize {
+        let sector = usize::from(self.boot_record.get_reserved_sectors_count())
+            + copy * usize::from(self.boot_record.get_sectors_per_fat());
+        sector * self.bytes_per_sector()
+    }
+
+    /// Byte offset of the fixed-size root directory, right after the last FAT.
+    pub(crate) fn root_dir_offset(&self) -> usize {
+        self.fat_offset(usize::from(self.boot_record.get_fats_count()))
+    }
+
+    /// Size of the root directory in bytes, rounded up to whole sectors.
+    pub(crate) fn root_dir_size(&self) -> usize {
+        let bytes = usize::from(self.boot_record.get_root_dir_entries_count()) * ENTRY_SIZE;
+        num::integer::div_ceil(bytes, self.bytes_per_sector()) * self.bytes_per_sector()
+    }
+
+    /// Byte offset of the first data cluster, which is always cluster number 2.
+    fn data_offset(&self) -> usize {
+        self.root_dir_offset() + self.root_dir_size()
+    }
+
+    /// Byte offset of a data cluster.
+    pub(crate) fn cluster_offset(&self, cluster: u16) -> usize {
+        self.data_offset() + (usize::from(cluster) - 2) * self.get_cluster_size()
+    }
+
+    /// The value that marks the end of a cluster chain.
+    pub(crate) fn end_of_chain(&self) -> u16 {
+        if self.is_fat12() {
+            return 0x0FFF;
+        }
+        0xFFFF
+    }
+
+    /// The value that marks a cluster as bad, so it never gets used.
+    pub(crate) fn bad_cluster(&self) -> u16 {
+        self.end_of_chain() - 8
+    }
+
+    /// Read an entry from the first copy of the FAT.
+    pub(crate) fn get_fat_entry(&self, cluster: u16) -> u16 {
+        self.get_fat_entry_in(0, cluster)
+    }
+
+    /// Read an entry from a specific copy of the FAT.
+    pub(crate) fn get_fat_entry_in(&self, copy: usize, cluster: u16) -> u16 {
+        let cluster = usize::from(cluster);
+        if self.is_fat12() {
+            let offset = self.fat_offset(copy) + cluster + cluster / 2;
+            let pair = u16::from_le_bytes([self.data[offset], self.data[offset + 1]]);
+            if cluster % 2 == 1 {
+                return pair >> 4;
+            }
+            return pair & 0x0FFF;
+        }
+        let offset = self.fat_offset(copy) + cluster * 2;
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]])
+    }
+
+    /// Write an entry into every copy of the FAT, so they never disagree.
+    pub(crate) fn set_fat_entry(&mut self, cluster: u16, value: u16) {
+        for copy in 0..usize::from(self.boot_record.get_fats_count()) {
+            let cluster = usize::from(cluster);
+            if self.is_fat12() {
+                let offset = self.fat_offset(copy) + cluster + cluster / 2;
+                let pair = u16::from_le_bytes([self.data[offset], self.data[offset + 1]]);
+                let pair = if cluster % 2 == 1 {
+                    (pair & 0x000F) | (value << 4)
+                } else {
+                    (pair & 0xF000) | (value & 0x0FFF)
+                };
+                self.data[offset..offset + 2].copy_from_slice(&pair.to_le_bytes());
+            } else {
+                let offset = self.fat_offset(copy) + cluster * 2;
+                self.data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    /// Whether a number is a valid data cluster on this volume.
+    pub(crate) fn is_valid_cluster(&self, cluster: u16) -> bool {
+        cluster >= 2 && u32::from(cluster) < self.get_cluster_count() + 2
+    }
+
+    /// Follow a cluster chain through the FAT, starting at its first cluster. The walk stops at
+    /// the end-of-chain marker, at anything that isn't a valid cluster number, or when the chain
+    /// turns out to be longer than the volume, which means it loops.
+    pub(crate) fn get_chain(&self, first_cluster: u16) -> Vec<u16> {
+        let mut chain = Vec::<u16>::new();
+        let mut cluster = first_cluster;
+        while self.is_valid_cluster(cluster) && chain.len() < self.get_cluster_count() as usize {
+            chain.push(cluster);
+            cluster = self.get_fat_entry(cluster);
+        }
+        chain
+    }
+
+    /// Find a number of free clusters, lowest numbers first, and link them into a new chain.
+    /// Nothing gets touched if there's not enough room.
+    pub(crate) fn allocate_chain(&mut self, count: usize) -> Result<Vec<u16>, FsError> {
+        let mut chain = Vec::<u16>::with_capacity(count);
+        for cluster in 2..self.get_cluster_count() + 2 {
+            if chain.len() == count {
+                break;
+            }
+            if self.get_fat_entry(cluster as u16) == 0 {
+                chain.push(cluster as u16);
+            }
+        }
+        if chain.len() < count {
+            return Err(FsError::VolumeFull);
+        }
+        self.link_chain(&chain);
+        for cluster in &chain {
+            self.zero_cluster(*cluster);
+        }
+        Ok(chain)
+    }
+
+    /// Point every cluster in a chain at the next one and terminate it.
+    pub(crate) fn link_chain(&mut self, chain: &[u16]) {
+        for pair in chain.windows(2) {
+            self.set_fat_entry(pair[0], pair[1]);
+        }
+        if let Some(last) = chain.last() {
+            let end_of_chain = self.end_of_chain();
+            self.set_fat_entry(*last, end_of_chain);
+        }
+    }
+
+    /// Mark every cluster in a chain as free again.
+    pub(crate) fn free_chain(&mut self, first_cluster: u16) {
+        for cluster in self.get_chain(first_cluster) {
+            self.set_fat_entry(cluster, 0);
+        }
+    }
+
+    pub(crate) fn zero_cluster(&mut self, cluster: u16) {
+        let offset = self.cluster_offset(cluster);
+        let size = self.get_cluster_size();
+        self.data[offset..offset + size].fill(0);
+    }
+
+    /// Copy data into the clusters of a chain, one cluster-sized chunk at a time.
+    pub(crate) fn write_chain(&mut self, chain: &[u16], data: &[u8]) {
+        let cluster_size = self.get_cluster_size();
+        for (cluster, chunk) in chain.iter().zip(data.chunks(cluster_size)) {
+            let offset = self.cluster_offset(*cluster);
+            self.data[offset..offset + chunk.len()].copy_from_slice(chunk);
+        }
+    }
+
+    /// Byte offsets of all 32-byte entry slots of a directory.
+    fn dir_slots(&self, dir_cluster: u16) -> Vec<usize> {
+        if dir_cluster == 0 {
+            let start = self.root_dir_offset();
+            let count = usize::from(self.boot_record.get_root_dir_entries_count());
+            return (0..count).map(|index| start + index * ENTRY_SIZE).collect();
+        }
+        let per_cluster = self.get_cluster_size() / ENTRY_SIZE;
+        let mut slots = Vec::<usize>::new();
+        for cluster in self.get_chain(dir_cluster) {
+            let start = self.cluster_offset(cluster);
+            slots.extend((0..per_cluster).map(|index| start + index * ENTRY_SIZE));
+        }
+        slots
+    }
+
+    /// Walk a directory and return every entry in it, long names attached.
+    pub(crate) fn read_dir_at(&self, dir_cluster: u16) -> Vec<DirEntry> {
+        self.locate_dir_entries(dir_cluster)
+            .into_iter()
+            .map(|located| located.entry)
+            .collect()
+    }
+
+    /// Walk a directory and return every entry in it together with the slots it occupies.
+    pub(crate) fn locate_dir_entries(&self, dir_cluster: u16) -> Vec<LocatedEntry> {
+        let mut entries = Vec::<LocatedEntry>::new();
+        let mut long_name = LongNameReader::default();
+        let mut pending = Vec::<usize>::new();
+        for offset in self.dir_slots(dir_cluster) {
+            let bytes = &self.data[offset..offset + ENTRY_SIZE];
+            if bytes[0] == 0x00 {
+                break;
+            }
+            if bytes[0] == DELETED_MARKER {
+                long_name.reset();
+                pending.clear();
+                continue;
+            }
+            if lfn::is_lfn_entry(bytes) {
+                if bytes[0] & 0x40 != 0 {
+                    pending.clear();
+                }
+                long_name.push(bytes);
+                pending.push(offset);
+                continue;
+            }
+            let mut entry = DirEntry::from_bytes(bytes);
+            entry.codepage = self.codepage;
+            entry.long_name = long_name.finish(&entry.short_name);
+            let mut slots = Vec::<usize>::new();
+            if entry.long_name.is_some() {
+                slots.append(&mut pending);
+            }
+            pending.clear();
+            slots.push(offset);
+            entries.push(LocatedEntry { slots, entry });
+        }
+        entries
+    }
+
+    /// Find a run of consecutive free slots in a directory. Subdirectories grow by another
+    /// cluster when they're full, the root directory has a fixed size and can't.
+    pub(crate) fn find_free_slots(
+        &mut self,
+        dir_cluster: u16,
+        count: usize,
+    ) -> Result<Vec<usize>, FsError> {
+        loop {
+            let mut run = Vec::<usize>::with_capacity(count);
+            for offset in self.dir_slots(dir_cluster) {
+                let first = self.data[offset];
+                if first == 0x00 || first == DELETED_MARKER {
+                    run.push(offset);
+                    if run.len() == count {
+                        return Ok(run);
+                    }
+                } else {
+                    run.clear();
+                }
+            }
+            if dir_cluster == 0 {
+                return Err(FsError::DirectoryFull(String::from("\\")));
+            }
+            // A directory entry pointing at a cluster that isn't valid has no chain to grow,
+            // that takes a check of the volume to sort out.
+            let last = match self.get_chain(dir_cluster).last() {
+                Some(last) => *last,
+                None => return Err(FsError::Inconsistent(1)),
+            };
+            let extra = self.allocate_chain(1)?;
+            self.set_fat_entry(last, extra[0]);
+        }
+    }
+
+    /// Split a DOS path into its components. Both kinds of slashes are accepted and a leading
+    /// drive letter is ignored, so "C:\GAMES\DOOM" and "/games/doom" are the same thing.
+    pub(crate) fn split_path(path: &str) -> Vec<&str> {
+        let mut components: Vec<&str> = path
+            .split(['\\', '/'])
+            .filter(|component| !component.is_empty())
+            .collect();
+        if let Some(first) = components.first() {
+            if first.len() == 2 && first.ends_with(':') {
+                components.remove(0);
+            }
+        }
+        components
+    }
+
+    /// Look up the entry for a path. The root directory itself has no entry.
+    pub(crate) fn find_entry(&self, path: &str) -> Result<DirEntry, FsError> {
+        Ok(self.locate(path)?.entry)
+    }
+
+    /// Look up the entry for a path together with the slots it occupies in its directory.
+    pub(crate) fn locate(&self, path: &str) -> Result<LocatedEntry, FsError> {
+        let components = Volume::split_path(path);
+        if components.is_empty() {
+            return Err(FsError::NotFound(path.to_string()));
+        }
+        let mut dir_cluster = 0u16;
+        for (index, component) in components.iter().enumerate() {
+            let found = self
+                .locate_dir_entries(dir_cluster)
+                .into_iter()
+                .find(|located| {
+                    !located.entry.attributes.vol_id && located.entry.matches(component)
+                });
+            let located = match found {
+                Some(located) => located,
+                None => return Err(FsError::NotFound(path.to_string())),
+            };
+            if index == components.len() - 1 {
+                // The dot entries belong to the directory they're in, they can't be renamed
+                // or deleted like the entries they point at.
+                if located.entry.is_dot_entry() {
+                    return Err(FsError::InvalidName(path.to_string()));
+                }
+                return Ok(located);
+            }
+            if !located.entry.is_dir() {
+                return Err(FsError::NotADirectory(path.to_string()));
+            }
+            dir_cluster = located.entry.first_cluster;
+        }
+        unreachable!()
+    }
+
+    /// Find the first cluster of the directory at a path, 0 being the root directory.
+    pub(crate) fn resolve_dir(&self, path: &str) -> Result<u16, FsError> {
+        if Volume::split_path(path).is_empty() {
+            return Ok(0);
+        }
+        let entry = self.find_entry(path)?;
+        if !entry.is_dir() {
+            return Err(FsError::NotADirectory(path.to_string()));
+        }
+        Ok(entry.first_cluster)
+    }
+
+    /// Look up the directory entry for a file or directory by its DOS path.
+    pub fn get_entry(&self, path: &str) -> Result<DirEntry, FsError> {
+        self.find_entry(path)
+    }
+
+    /// Read the contents of a file by its DOS path, following its cluster chain through the FAT.
+    /// A chain that's shorter than the size in the directory entry gives as much as there is.
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        let entry = self.find_entry(path)?;
+        if entry.is_dir() {
+            return Err(FsError::IsADirectory(path.to_string()));
+        }
+        Ok(self.read_contents(&entry))
+    }
+
+    /// The contents of a file, or the raw entries of a directory, straight from its chain.
+    pub(crate) fn read_contents(&self, entry: &DirEntry) -> Vec<u8> {
+        let chain = self.get_chain(entry.first_cluster);
+        let cluster_size = self.get_cluster_size();
+        let size = if entry.is_dir() {
+            chain.len() * cluster_size
+        } else {
+            entry.size as usize
+        };
+        let mut data = Vec::<u8>::with_capacity(size);
+        for cluster in chain {
+            if data.len() >= size {
+                break;
+            }
+            let offset = self.cluster_offset(cluster);
+            let wanted = cluster_size.min(size - data.len());
+            data.extend_from_slice(&self.data[offset..offset + wanted]);
+        }
+        data
+    }
+
+    /// List all entries in a directory, including the "." and ".." entries of subdirectories.
+    pub fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, FsError> {
+        let dir_cluster = self.resolve_dir(path)?;
+        Ok(self.read_dir_at(dir_cluster))
+    }
+
+    /// Put an entry into a directory under a host name. The short name is derived from that
+    /// name, and if long names are enabled on this volume and the short name doesn't carry
+    /// the name as-is, a sequence of long file name entries goes in front of it.
+    pub(crate) fn insert_entry(
+        &mut self,
+        dir_cluster: u16,
+        name: &str,
+        mut entry: DirEntry,
+    ) -> Result<LocatedEntry, FsError> {
+        let existing = self.read_dir_at(dir_cluster);
+        if existing
+            .iter()
+            .any(|entry| !entry.attributes.vol_id && entry.matches(name))
+        {
+            return Err(FsError::AlreadyExists(name.to_string()));
+        }
+        let siblings: Vec<[u8; 11]> = existing.iter().map(|entry| entry.short_name).collect();
+        entry.short_name = File::normalize_name_in(name, &siblings, self.codepage)?;
+        entry.codepage = self.codepage;
+        let mut raw_entries = Vec::<[u8; 32]>::new();
+        if self.long_names && entry.get_short_name() != name {
+            raw_entries = lfn::build_entries(name, &entry.short_name)?;
+            entry.long_name = Some(name.to_string());
+        }
+        raw_entries.push(entry.as_bytes());
+        let slots = self.find_free_slots(dir_cluster, raw_entries.len())?;
+        for (offset, raw_entry) in slots.iter().zip(raw_entries) {
+            self.data[*offset..*offset + ENTRY_SIZE].copy_from_slice(&raw_entry);
+        }
+        Ok(LocatedEntry { slots, entry })
+    }
+
+    /// Overwrite the short entry at a slot with new contents.
+    pub(crate) fn write_entry(&mut self, offset: usize, entry: &DirEntry) {
+        self.data[offset..offset + ENTRY_SIZE].copy_from_slice(&entry.as_bytes());
+    }
+
+    /// Split a path into the path of its parent directory and its last component.
+    fn split_parent(path: &str) -> Result<(String, String), FsError> {
+        let mut components = Volume::split_path(path);
+        let name = match components.pop() {
+            Some(name) => name.to_string(),
+            None => return Err(FsError::InvalidName(path.to_string())),
+        };
+        Ok((components.join("\\"), name))
+    }
+
+    /// Create a new, empty subdirectory. Its parent has to exist already.
+    pub fn create_dir(&mut self, path: &str) -> Result<DirEntry, FsError> {
+        let (parent, name) = Volume::split_parent(path)?;
+        let parent_cluster = self.resolve_dir(&parent)?;
+        let chain = self.allocate_chain(1)?;
+        let attributes = FileAttributes {
+            is_dir: true,
+            ..Default::default()
+        };
+        let timestamp = self.default_timestamp(None);
+        let mut entry = DirEntry::new([0x20; 11], attributes, chain[0], 0);
+        entry.set_timestamps(timestamp, timestamp, timestamp);
+        let entry = match self.insert_entry(parent_cluster, &name, entry) {
+            Ok(located) => located.entry,
+            Err(error) => {
+                self.free_chain(chain[0]);
+                return Err(error);
+            }
+        };
+
+        // Every subdirectory starts with entries pointing at itself and at its parent.
+        let mut dot = entry.clone();
+        dot.long_name = None;
+        dot.short_name = *b".          ";
+        let offset = self.cluster_offset(chain[0]);
+        self.write_entry(offset, &dot);
+        dot.short_name = *b"..         ";
+        dot.first_cluster = parent_cluster;
+        self.write_entry(offset + ENTRY_SIZE, &dot);
+        Ok(entry)
+    }
+
+    /// Open an existing file for reading and writing.
+    pub fn open(&mut self, path: &str) -> Result<FileHandle<'_>, FsError> {
+        let located = self.locate(path)?;
+        if located.entry.is_dir() {
+            return Err(FsError::IsADirectory(path.to_string()));
+        }
+        Ok(FileHandle::new(self, located.get_offset(), located.entry))
+    }
+
+    /// Create a new, empty file and open it for reading and writing. A file that
+    /// already exists at the path gets truncated, the same way File::create works,
+    /// unless it is read-only.
+    pub fn create(&mut self, path: &str) -> Result<FileHandle<'_>, FsError> {
+        match self.locate(path) {
+            Ok(located) => {
+                if located.entry.is_dir() {
+                    return Err(FsError::IsADirectory(path.to_string()));
+                }
+                if located.entry.attributes.read_only {
+                    return Err(FsError::ReadOnly(path.to_string()));
+                }
+                let mut handle = FileHandle::new(self, located.get_offset(), located.entry);
+                handle.resize(0)?;
+                Ok(handle)
+            }
+            Err(FsError::NotFound(_)) => {
+                let (parent, name) = Volume::split_parent(path)?;
+                let dir_cluster = self.resolve_dir(&parent)?;
+                let timestamp = self.default_timestamp(None);
+                let mut entry = DirEntry::new([0x20; 11], FileAttributes::default(), 0, 0);
+                entry.set_timestamps(timestamp, timestamp, timestamp);
+                let located = self.insert_entry(dir_cluster, &name, entry)?;
+                Ok(FileHandle::new(self, located.get_offset(), located.entry))
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Mark all slots of an entry as deleted. The data and FAT are left alone.
+    fn delete_slots(&mut self, located: &LocatedEntry) {
+        for offset in &located.slots {
+            self.data[*offset] = DELETED_MARKER;
+        }
+    }
+
+    /// Delete a file: its directory entries get marked 0xE5 and its clusters are freed. Like
+    /// DEL, this refuses to delete read-only files.
+    pub fn remove_file(&mut self, path: &str) -> Result<(), FsError> {
+        let located = self.locate(path)?;
+        if located.entry.is_dir() {
+            return Err(FsError::IsADirectory(path.to_string()));
+        }
+        if located.entry.attributes.read_only {
+            return Err(FsError::ReadOnly(path.to_string()));
+        }
+        self.delete_slots(&located);
+        self.free_chain(located.entry.first_cluster);
+        Ok(())
+    }
+
+    /// Delete an empty directory.
+    pub fn remove_dir(&mut self, path: &str) -> Result<(), FsError> {
+        let located = self.locate(path)?;
+        if !located.entry.is_dir() {
+            return Err(FsError::NotADirectory(path.to_string()));
+        }
+        let children = self.read_dir_at(located.entry.first_cluster);
+        if children.iter().any(|child| !child.is_dot_entry()) {
+            return Err(FsError::DirectoryNotEmpty(path.to_string()));
+        }
+        self.delete_slots(&located);
+        self.free_chain(located.entry.first_cluster);
+        Ok(())
+    }
+
+    /// Delete a directory together with everything in it. Like DELTREE, read-only files go too.
+    pub fn remove_dir_all(&mut self, path: &str) -> Result<(), FsError> {
+        let entry = self.find_entry(path)?;
+        if !entry.is_dir() {
+            return Err(FsError::NotADirectory(path.to_string()));
+        }
+        for child in self.read_dir_at(entry.first_cluster) {
+            if child.is_dot_entry() || child.attributes.vol_id {
+                continue;
+            }
+            let child_path = format!("{}\\{}", path, child.get_short_name());
+            if child.is_dir() {
+                self.remove_dir_all(&child_path)?;
+            } else {
+                let located = self.locate(&child_path)?;
+                self.delete_slots(&located);
+                self.free_chain(located.entry.first_cluster);
+            }
+        }
+        self.remove_dir(path)
+    }
+
+    /// Rename or move a file or directory, like std::fs::rename. The new name goes through
+    /// the same 8.3 conversion as any other name. When a directory moves to another parent,
+    /// its ".." entry gets pointed at the new parent.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<DirEntry, FsError> {
+        let located = self.locate(from)?;
+        let (from_parent, _) = Volume::split_parent(from)?;
+        let from_dir = self.resolve_dir(&from_parent)?;
+        let (to_parent, to_name) = Volume::split_parent(to)?;
+        let to_dir = self.resolve_dir(&to_parent)?;
+        if located.entry.is_dir() && self.is_inside(to_dir, located.entry.first_cluster) {
+            return Err(FsError::InvalidMove(from.to_string()));
+        }
+
+        // Clear the old slots first so that changing only the case of a name works, but keep
+        // their contents around in case the new entry doesn't fit.
+        let saved: Vec<Vec<u8>> = located
+            .slots
+            .iter()
+            .map(|offset| self.data[*offset..*offset + ENTRY_SIZE].to_vec())
+            .collect();
+        self.delete_slots(&located);
+        let mut entry = located.entry.clone();
+        entry.long_name = None;
+        let moved = match self.insert_entry(to_dir, &to_name, entry) {
+            Ok(moved) => moved,
+            Err(error) => {
+                for (offset, bytes) in located.slots.iter().zip(saved) {
+                    self.data[*offset..*offset + ENTRY_SIZE].copy_from_slice(&bytes);
+                }
+                return Err(error);
+            }
+        };
+
+        if moved.entry.is_dir() && from_dir != to_dir {
+            let offset = self.cluster_offset(moved.entry.first_cluster) + ENTRY_SIZE;
+            let mut dot_dot = DirEntry::from_bytes(&self.data[offset..offset + ENTRY_SIZE]);
+            dot_dot.first_cluster = to_dir;
+            self.write_entry(offset, &dot_dot);
+        }
+        Ok(moved.entry)
+    }
+
+    /// Whether a directory is the same as, or somewhere below, another directory. This walks
+    /// up through the ".." entries until it reaches the root.
+    fn is_inside(&self, dir_cluster: u16, ancestor: u16) -> bool {
+        let mut current = dir_cluster;
+        let mut steps = 0;
+        while current != 0 && steps < self.get_cluster_count() {
+            if current == ancestor {
+                return true;
+            }
+            let parent = self
+                .read_dir_at(current)
+                .into_iter()
+                .find(|entry| &entry.short_name == b"..         ");
+            current = match parent {
+                Some(parent) => parent.first_cluster,
+                None => return false,
+            };
+            steps += 1;
+        }
+        false
+    }
+
+    /// Store a file in a directory, which has to exist already. The file's name is
+    /// converted to a short name, and gets a long name too if the volume has those enabled.
+    pub fn add_file(&mut self, directory: &str, file: File) -> Result<DirEntry, FsError> {
+        let dir_cluster = self.resolve_dir(directory)?;
+        let size =
+            u32::try_from(file.get_size()).map_err(|_| FsError::FileTooLarge(file.name.clone()))?;
+        let count = num::integer::div_ceil(file.get_size(), self.get_cluster_size());
+        let chain = self.allocate_chain(count)?;
+        self.write_chain(&chain, &file.data);
+        let first_cluster = chain.first().copied().unwrap_or(0);
+        let mut entry = DirEntry::new([0x20; 11], file.attributes.clone(), first_cluster, size);
+        let fallback = self.default_timestamp(file.host_modified);
+        let modified = file.modified.unwrap_or(fallback);
+        entry.set_timestamps(
+            file.created.unwrap_or(modified),
+            modified,
+            file.accessed.unwrap_or(modified),
+        );
+        match self.insert_entry(dir_cluster, &file.name, entry) {
+            Ok(located) => Ok(located.entry),
+            Err(error) => {
+                self.free_chain(first_cluster);
+                Err(error)
+            }
+        }
+    }
+}
@@ -7,4 +7,13 @@ pub mod sector;
 /// The FS module is meant to supplement what the fatfs crate already gives us.
 /// It mainly implements the VBR, BIOS Parameter block and the MS-DOS 6.22 Volume Boot Code.
 pub mod fs;
+
+/// The emu module boots disk images in a minimal 8086 PC, to see whether DOS gets control.
+pub mod emu;
+
+/// The manifest module reads container manifests and builds the images they describe.
+pub mod manifest;
+
+/// The os module holds the operating systems that can be installed on a disk image.
+pub mod os;
 pub mod partition;
@@ -1,25 +1,115 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use doscontainer::disk::Disk;
+use doscontainer::manifest::{build_batch, find_manifests, BuildCache, Manifest};
 use doscontainer::partition::Partition;
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[clap(version, about = "DOS Container generates MS-DOS compatible disk images.", long_about = None)]
+#[clap(subcommand_negates_reqs = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Path to the disk image to create
-    #[clap(short, long)]
-    path: String,
+    #[clap(short, long, required = true)]
+    path: Option<String>,
 
     /// Disk size in bytes
-    #[clap(short, long)]
-    size: usize,
+    #[clap(short, long, required = true)]
+    size: Option<usize>,
 
     /// Debug flag
     #[clap(short, long)]
     debug: bool,
 }
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Build the image a manifest describes
+    Build {
+        /// Path to the manifest, a TOML file
+        manifest: PathBuf,
+
+        /// Skip the build when the image is up to date according to this cache directory
+        #[clap(short, long)]
+        cache: Option<PathBuf>,
+    },
+    /// Build every manifest in a directory, or those matching a pattern like "games/*.toml"
+    Batch {
+        /// Directory of manifests or a pattern with * and ? wildcards
+        pattern: PathBuf,
+
+        /// Number of containers to build at the same time, one per CPU by default
+        #[clap(short, long)]
+        jobs: Option<usize>,
+
+        /// Also write the summary report to this file
+        #[clap(short, long)]
+        report: Option<PathBuf>,
+
+        /// Skip images that are up to date according to this cache directory
+        #[clap(short, long)]
+        cache: Option<PathBuf>,
+    },
+}
+
 fn main() {
     let args = Args::parse();
-    let mut disk = Disk::new(args.path.as_str(), args.size);
+    match args.command {
+        Some(Command::Build { manifest, cache }) => {
+            let cache = open_cache(cache);
+            let built = Manifest::load(&manifest).and_then(|manifest| match &cache {
+                Some(cache) => manifest.build_cached(cache),
+                None => manifest.build_to_file(),
+            });
+            match built {
+                Ok(output) => println!("{}  {}", output.digest, output.path.display()),
+                Err(error) => {
+                    eprintln!("{}: {}", manifest.display(), error);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Batch {
+            pattern,
+            jobs,
+            report,
+            cache,
+        }) => {
+            let cache = open_cache(cache);
+            let manifests = match find_manifests(&pattern) {
+                Ok(manifests) if !manifests.is_empty() => manifests,
+                Ok(_) => {
+                    eprintln!("{}: no manifests found", pattern.display());
+                    std::process::exit(1);
+                }
+                Err(error) => {
+                    eprintln!("{}: {}", pattern.display(), error);
+                    std::process::exit(1);
+                }
+            };
+            let jobs = jobs.unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, |count| count.get())
+            });
+            let summary = build_batch(&manifests, jobs, cache.as_ref());
+            println!("{}", summary);
+            if let Some(path) = report {
+                if let Err(error) = std::fs::write(&path, format!("{}\n", summary)) {
+                    eprintln!("{}: {}", path.display(), error);
+                    std::process::exit(1);
+                }
+            }
+            if summary.failed() > 0 {
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => {}
+    }
+    let (path, size) = (args.path.unwrap(), args.size.unwrap());
+    let mut disk = Disk::new(path.as_str(), size);
     let bootpart = Partition::new(&disk, 1, 6, 0);
     if args.debug {
         println!("{:?}", bootpart);
@@ -29,3 +119,15 @@ fn main() {
     // let disk = Disk::load(&args.path);
     // println!("{:?}", disk);
 }
+
+/// Open the build cache directory, if there is one, or give up.
+fn open_cache(dir: Option<PathBuf>) -> Option<BuildCache> {
+    let dir = dir?;
+    match BuildCache::open(&dir) {
+        Ok(cache) => Some(cache),
+        Err(error) => {
+            eprintln!("{}: {}", dir.display(), error);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use crate::fs::AttributeRules;
+use crate::manifest::{BuildCache, Manifest, ManifestError, Output};
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// How one manifest of a batch went.
+#[derive(Debug)]
+pub struct BatchEntry {
+    pub manifest: PathBuf,
+    pub result: Result<Output, ManifestError>,
+}
+
+/// The outcome of every manifest in a batch, in the order they were given.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub entries: Vec<BatchEntry>,
+}
+
+impl BatchReport {
+    pub fn succeeded(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.result.is_ok())
+            .count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.entries.len() - self.succeeded()
+    }
+
+    /// How many of the images that succeeded were up to date already.
+    pub fn cached(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| matches!(&entry.result, Ok(output) if output.cached))
+            .count()
+    }
+}
+
+impl fmt::Display for BatchReport {
+    /// One line per manifest with the size and digest of the image or the reason it failed,
+    /// tab separated so it's easy to pick apart, and a count at the end.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            match &entry.result {
+                Ok(output) => writeln!(
+                    f,
+                    "{}\t{}\t{}\t{}\t{}",
+                    if output.cached { "cached" } else { "ok" },
+                    entry.manifest.display(),
+                    output.path.display(),
+                    output.size,
+                    output.digest
+                )?,
+                Err(error) => {
+                    let error = error.to_string().replace('\n', " ");
+                    writeln!(f, "failed\t{}\t{}", entry.manifest.display(), error)?
+                }
+            }
+        }
+        write!(
+            f,
+            "{} built, {} up to date, {} failed",
+            self.succeeded() - self.cached(),
+            self.cached(),
+            self.failed()
+        )
+    }
+}
+
+/// Find the manifests to build: every .toml file in a directory, or the files matching a
+/// pattern with `*` and `?` wildcards in the last part of the path, like "games/*.toml".
+/// Anything else is taken as the path of a single manifest. The result is sorted by path.
+pub fn find_manifests(pattern: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let name = pattern
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (directory, wildcard) = if pattern.is_dir() {
+        (pattern.to_path_buf(), "*.toml".to_string())
+    } else if name.contains(['*', '?']) {
+        let parent = pattern.parent().unwrap_or(Path::new(""));
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        (parent.to_path_buf(), name)
+    } else {
+        return Ok(vec![pattern.to_path_buf()]);
+    };
+    let mut manifests = Vec::new();
+    for entry in std::fs::read_dir(&directory)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_file()
+            && AttributeRules::matches(wildcard.as_bytes(), name.as_bytes())
+        {
+            manifests.push(entry.path());
+        }
+    }
+    manifests.sort();
+    Ok(manifests)
+}
+
+/// Build many manifests at once on a number of worker threads. A manifest that fails, even
+/// by running into a bug that panics, doesn't stop the others: it just ends up as a failure
+/// in the report. Manifests that would write to the same image as one before them fail
+/// without being built, instead of overwriting each other halfway through.
+///
+/// The operating system files are compiled into the program, so every build in the batch
+/// installs from the same copy and nothing gets loaded per container. With a cache, images
+/// that are up to date get skipped and containers that share a disk layout and OS start
+/// from the same formatted disk.
+pub fn build_batch(
+    manifests: &[PathBuf],
+    workers: usize,
+    cache: Option<&BuildCache>,
+) -> BatchReport {
+    let loaded: Vec<Result<Manifest, ManifestError>> =
+        manifests.iter().map(|path| Manifest::load(path)).collect();
+    let mut outputs = HashMap::new();
+    let loaded: Vec<Result<Manifest, ManifestError>> = loaded
+        .into_iter()
+        .zip(manifests)
+        .map(|(manifest, path)| {
+            let manifest = manifest?;
+            match outputs.insert(manifest.output_path(), path) {
+                Some(first) => Err(ManifestError::Invalid(format!(
+                    "{} writes to the same image",
+                    first.display()
+                ))),
+                None => Ok(manifest),
+            }
+        })
+        .collect();
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<Output, ManifestError>>>> =
+        Mutex::new(manifests.iter().map(|_| None).collect());
+    let workers = workers.clamp(1, manifests.len().max(1));
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(manifest) = loaded.get(index) else {
+                    break;
+                };
+                if let Ok(manifest) = manifest {
+                    let result = build_one(manifest, cache);
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
+        }
+    });
+
+    let results = results.into_inner().unwrap();
+    let entries = manifests
+        .iter()
+        .zip(results)
+        .zip(loaded)
+        .map(|((path, result), loaded)| BatchEntry {
+            manifest: path.clone(),
+            result: match loaded {
+                Err(error) => Err(error),
+                Ok(_) => result.expect("every manifest gets built"),
+            },
+        })
+        .collect();
+    BatchReport { entries }
+}
+
+fn build_one(manifest: &Manifest, cache: Option<&BuildCache>) -> Result<Output, ManifestError> {
+    let build = || match cache {
+        Some(cache) => manifest.build_cached(cache),
+        None => manifest.build_to_file(),
+    };
+    match catch_unwind(AssertUnwindSafe(build)) {
+        Ok(result) => result,
+        Err(panic) => {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .map(|reason| reason.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown error".to_string());
+            Err(ManifestError::Crashed(reason))
+        }
+    }
+}
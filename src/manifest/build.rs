@@ -0,0 +1,263 @@
+use crate::disk::chs::CHS;
+use crate::disk::Disk;
+use crate::fs::{AttributeRules, File, FsError, Volume};
+use crate::manifest::{Manifest, ManifestError, Output, OutputFormat, StartupFile};
+use crate::os::AutoexecBat;
+use crate::partition::Partition;
+use sha2::{Digest, Sha256};
+use std::io::{BufWriter, Write};
+
+/// The largest disk the Bochs geometry calculation handles, anything bigger needs translation.
+const MAX_DISK_SIZE: u64 = 528482304;
+const SECTORS_PER_TRACK: u8 = 63;
+/// Partitions start at the second track, like FDISK puts them.
+const FIRST_LBA: u32 = 63;
+
+impl Manifest {
+    /// Build the container in memory. The order is the one SYS.COM and a person at the DOS
+    /// prompt would follow: format, system files first so they get the first root directory
+    /// slots and the first clusters, then CONFIG.SYS and AUTOEXEC.BAT, then the imports in
+    /// manifest order, and the label last. Every timestamp comes from the source date epoch
+    /// and the serial number from a seed, so the same manifest and inputs always give the
+    /// same image.
+    pub fn build(&self) -> Result<Disk, ManifestError> {
+        let (disk, partition) = self.install_os()?;
+        self.finish(disk, partition)
+    }
+
+    /// Build the container and write it to its output path, in the format from the manifest.
+    pub fn build_to_file(&self) -> Result<Output, ManifestError> {
+        self.write(&self.build()?)
+    }
+
+    /// The first part of a build: the disk with a freshly formatted partition that has the
+    /// operating system on it. This only depends on the disk, the layout of the file system,
+    /// the OS and the source date epoch, which is what lets the build cache hand out copies
+    /// of it to other manifests.
+    pub(crate) fn install_os(&self) -> Result<(Disk, Partition), ManifestError> {
+        let disk = self.new_disk()?;
+        let partition_bytes = match self.partition.size {
+            Some(size) => {
+                let available = (disk.sector_count as u64).saturating_sub(u64::from(FIRST_LBA));
+                if size.0 / 512 > available {
+                    return Err(ManifestError::Invalid(format!(
+                        "a partition of {} bytes doesn't fit on the disk",
+                        size.0
+                    )));
+                }
+                size.0
+            }
+            None => 0,
+        };
+        let mut partition = Partition::new(&disk, 1, FIRST_LBA, partition_bytes);
+        let volume = partition.format_with(&self.partition.format_options())?;
+        volume.set_long_names(self.partition.long_names);
+        volume.set_source_date_epoch(Some(self.epoch()));
+
+        for (name, data) in self.os.profile().get_system_files() {
+            volume.add_file("", File::new(name.to_string(), data.to_vec()))?;
+        }
+        Ok((disk, partition))
+    }
+
+    /// Everything after the operating system: the startup files, the imports, the serial
+    /// number and the label. Generated startup files get checked once the imports are in.
+    pub(crate) fn finish(
+        &self,
+        mut disk: Disk,
+        mut partition: Partition,
+    ) -> Result<Disk, ManifestError> {
+        let volume = partition
+            .get_volume_mut()
+            .expect("install_os() formats the partition");
+        match &self.config_sys {
+            Some(StartupFile::Text(text)) => {
+                volume.add_file("", File::new("CONFIG.SYS".to_string(), dos_text(text)))?;
+            }
+            Some(StartupFile::Generated(config)) => {
+                config.install(volume)?;
+            }
+            None => {}
+        }
+        match &self.autoexec_bat {
+            Some(StartupFile::Text(text)) => {
+                volume.add_file("", File::new("AUTOEXEC.BAT".to_string(), dos_text(text)))?;
+            }
+            Some(StartupFile::Generated(autoexec)) => {
+                self.autoexec_for_menu(autoexec).install(volume)?;
+            }
+            None => {}
+        }
+        for import in &self.imports {
+            let source = self.base.join(&import.source);
+            let rules = match &import.rules {
+                Some(path) => {
+                    let path = self.base.join(path);
+                    AttributeRules::load(&path).map_err(|error| ManifestError::Io(path, error))?
+                }
+                None => AttributeRules::new(),
+            };
+            let target = make_dirs(volume, &import.target)?;
+            let report = if source.is_dir() {
+                volume.import_dir(&source, &target, &rules)
+            } else {
+                std::fs::File::open(&source)
+                    .and_then(|archive| volume.import_archive(archive, &target, &rules))
+            }
+            .map_err(|error| ManifestError::Io(source.clone(), error))?;
+            if !report.is_complete() {
+                return Err(ManifestError::Incomplete(source, report));
+            }
+        }
+        // Drivers and games usually come in with the imports, so this can only be checked now.
+        if let Some(StartupFile::Generated(config)) = &self.config_sys {
+            let problems = config.validate(volume);
+            if !problems.is_empty() {
+                return Err(ManifestError::Config(problems));
+            }
+        }
+        if let Some(StartupFile::Generated(autoexec)) = &self.autoexec_bat {
+            let mut problems = self.autoexec_for_menu(autoexec).validate(volume);
+            if let Some(StartupFile::Generated(config)) = &self.config_sys {
+                problems.extend(autoexec.check_menu(config));
+            }
+            if !problems.is_empty() {
+                return Err(ManifestError::Autoexec(problems));
+            }
+        }
+
+        let seed = match (&self.partition.serial_seed, &self.name) {
+            (Some(seed), _) | (None, Some(seed)) => seed.clone(),
+            (None, None) => self.output_path().to_string_lossy().to_string(),
+        };
+        volume.set_serial_from_seed(&seed);
+        if let Some(label) = &self.partition.label {
+            partition.set_label(label)?;
+        }
+        disk.push_partition(partition);
+        Ok(disk)
+    }
+
+    /// The generated AUTOEXEC.BAT with a section for every profile of a generated CONFIG.SYS.
+    fn autoexec_for_menu(&self, autoexec: &AutoexecBat) -> AutoexecBat {
+        match &self.config_sys {
+            Some(StartupFile::Generated(config)) => autoexec.for_menu(config),
+            _ => autoexec.clone(),
+        }
+    }
+
+    /// Write a built container to the output path, in the format from the manifest.
+    pub(crate) fn write(&self, disk: &Disk) -> Result<Output, ManifestError> {
+        let path = self.output_path();
+        let io_error = |error| ManifestError::Io(path.clone(), error);
+        let file = std::fs::File::create(&path).map_err(io_error)?;
+        let mut writer = HashingWriter {
+            inner: BufWriter::new(file),
+            hasher: Sha256::new(),
+            size: 0,
+        };
+        match self.output.format {
+            OutputFormat::Raw => disk.write_image(&mut writer),
+            OutputFormat::Vhd => disk.write_vhd(&mut writer),
+        }
+        .and_then(|_| writer.flush())
+        .map_err(io_error)?;
+        Ok(Output {
+            size: writer.size,
+            digest: format!("{:x}", writer.hasher.finalize()),
+            path,
+            cached: false,
+        })
+    }
+
+    /// An empty disk of the size or geometry from the manifest.
+    fn new_disk(&self) -> Result<Disk, ManifestError> {
+        let path = self.output_path();
+        let path = path.to_string_lossy();
+        let disk = match (&self.disk.size, &self.disk.geometry) {
+            (Some(_), Some(_)) => {
+                return Err(ManifestError::Invalid(
+                    "set either the size or the geometry of the disk, not both".to_string(),
+                ))
+            }
+            (None, None) => {
+                return Err(ManifestError::Invalid(
+                    "the disk needs a size or a geometry".to_string(),
+                ))
+            }
+            (Some(size), None) => {
+                if size.0 < 1024 * 1024 || size.0 >= MAX_DISK_SIZE {
+                    return Err(ManifestError::Invalid(format!(
+                        "disks have to be at least 1MB and smaller than {} bytes",
+                        MAX_DISK_SIZE
+                    )));
+                }
+                Disk::new(&path, size.0 as usize)
+            }
+            (None, Some(geometry)) => {
+                if !(2..=1024).contains(&geometry.cylinders) || !(1..=16).contains(&geometry.heads)
+                {
+                    return Err(ManifestError::Invalid(format!(
+                        "a geometry of {} cylinders and {} heads isn't supported",
+                        geometry.cylinders, geometry.heads
+                    )));
+                }
+                let sectors = usize::from(geometry.cylinders)
+                    * usize::from(geometry.heads)
+                    * usize::from(SECTORS_PER_TRACK);
+                let mut disk = Disk::new(&path, sectors * 512);
+                disk.geometry = CHS::new(geometry.cylinders, geometry.heads, SECTORS_PER_TRACK);
+                disk
+            }
+        };
+        Ok(disk)
+    }
+}
+
+/// Hashes everything on its way to the file, so the digest is that of the file as written.
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Text for DOS: CR LF line endings, including after the last line.
+fn dos_text(text: &str) -> Vec<u8> {
+    let mut data = Vec::with_capacity(text.len());
+    for line in text.lines() {
+        data.extend_from_slice(line.as_bytes());
+        data.extend_from_slice(b"\r\n");
+    }
+    data
+}
+
+/// Create a directory on the volume along with any parents that are missing, and return its
+/// path with the short names it ended up with.
+fn make_dirs(volume: &mut Volume, path: &str) -> Result<String, FsError> {
+    let mut current = String::new();
+    for name in path.split(['\\', '/']).filter(|name| !name.is_empty()) {
+        let target = Volume::join_path(&current, name);
+        current = match volume.get_entry(&target) {
+            Ok(entry) if entry.is_dir() => Volume::join_path(&current, &entry.get_short_name()),
+            Ok(_) => return Err(FsError::NotADirectory(target)),
+            Err(_) => {
+                let entry = volume.create_dir(&target)?;
+                Volume::join_path(&current, &entry.get_short_name())
+            }
+        };
+    }
+    Ok(current)
+}
@@ -0,0 +1,232 @@
+use crate::disk::Disk;
+use crate::manifest::{Manifest, ManifestError, Output};
+use crate::partition::Partition;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+/// Remembers what earlier builds produced, so images whose inputs haven't changed since
+/// don't get built again. Builds are recorded on the host in a directory, one small file per
+/// build named after the digest of everything that went into it, see
+/// Manifest::input_digest(). A record holds the digest, size and modification time of the
+/// image that was written. As long as the image is still there with that size and
+/// modification time, it's taken as is.
+///
+/// The cache also keeps freshly formatted disks with the operating system installed in
+/// memory, one for every combination of disk, file system layout, OS and source date epoch.
+/// Builds that share those start from a copy instead of formatting and installing again,
+/// which helps a lot when a batch has many containers of the same size. A disk can take up
+/// hundreds of megabytes, so only the few that were used last are kept.
+#[derive(Debug)]
+pub struct BuildCache {
+    dir: PathBuf,
+    /// Formatted disks by what they were made from, the one used last at the end.
+    templates: Mutex<Vec<(String, Template)>>,
+}
+
+/// A formatted disk with the operating system on it, shared between the builds that use it.
+type Template = Arc<(Disk, Partition)>;
+
+/// How many formatted disks the cache keeps in memory at most.
+const MAX_TEMPLATES: usize = 4;
+
+/// What a record file says about an image.
+#[derive(Debug, PartialEq)]
+struct Record {
+    digest: String,
+    size: u64,
+    modified: u128,
+}
+
+impl BuildCache {
+    /// Use a directory on the host for the records, it gets created if it isn't there yet.
+    pub fn open(dir: &Path) -> io::Result<BuildCache> {
+        std::fs::create_dir_all(dir)?;
+        Ok(BuildCache {
+            dir: dir.to_path_buf(),
+            templates: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// A copy of the disk with the operating system installed that the manifest starts from.
+    fn template(&self, manifest: &Manifest) -> Result<(Disk, Partition), ManifestError> {
+        let key = format!(
+            "{:?} {:?} {:?} {} {:?} {:?}",
+            manifest.disk,
+            manifest.partition.size,
+            manifest.partition.format_options(),
+            manifest.partition.long_names,
+            manifest.os,
+            manifest.source_date_epoch
+        );
+        let cached = {
+            let mut templates = self.templates.lock().unwrap();
+            let position = templates.iter().position(|(existing, _)| *existing == key);
+            position.map(|position| {
+                let template = templates.remove(position);
+                let disk = Arc::clone(&template.1);
+                templates.push(template);
+                disk
+            })
+        };
+        let template = match cached {
+            Some(template) => template,
+            None => {
+                // Formatting happens outside the lock so other builds don't wait on it. Two
+                // builds that need the same new template both make it, which does no harm.
+                let template = Arc::new(manifest.install_os()?);
+                let mut templates = self.templates.lock().unwrap();
+                templates.retain(|(existing, _)| *existing != key);
+                if templates.len() >= MAX_TEMPLATES {
+                    templates.remove(0);
+                }
+                templates.push((key, Arc::clone(&template)));
+                template
+            }
+        };
+        let (mut disk, partition) = (*template).clone();
+        disk.path = manifest.output_path();
+        Ok((disk, partition))
+    }
+
+    fn record_path(&self, inputs: &str) -> PathBuf {
+        self.dir.join(inputs)
+    }
+
+    fn lookup(&self, inputs: &str) -> Option<Record> {
+        let text = std::fs::read_to_string(self.record_path(inputs)).ok()?;
+        let mut fields = text.split_whitespace();
+        Some(Record {
+            digest: fields.next()?.to_string(),
+            size: fields.next()?.parse().ok()?,
+            modified: fields.next()?.parse().ok()?,
+        })
+    }
+
+    /// Write the record through a temporary file, so an interrupted build never leaves a
+    /// record behind that only has half of what it should say.
+    fn store(&self, inputs: &str, output: &Output) -> io::Result<()> {
+        let metadata = std::fs::metadata(&output.path)?;
+        let text = format!(
+            "{} {} {}\n",
+            output.digest,
+            output.size,
+            modified_nanos(&metadata)
+        );
+        let temporary = self.record_path(&format!("{}.tmp", inputs));
+        std::fs::write(&temporary, text)?;
+        std::fs::rename(&temporary, self.record_path(inputs))
+    }
+}
+
+impl Manifest {
+    /// SHA-256 over everything that decides what the image looks like: the manifest itself,
+    /// the operating system files, and the names and contents of everything it imports,
+    /// including the attribute rules. The version of this program is part of it too, since
+    /// a newer version may well build a different image from the same inputs. Timestamps
+    /// on the host don't count, the volume gets its timestamps from the manifest.
+    pub fn input_digest(&self) -> Result<String, ManifestError> {
+        let mut hasher = Sha256::new();
+        feed(&mut hasher, env!("CARGO_PKG_VERSION").as_bytes());
+        // The same manifest reached through another relative path is still the same manifest.
+        let mut manifest = self.clone();
+        manifest.base = std::fs::canonicalize(&self.base).unwrap_or_else(|_| self.base.clone());
+        feed(&mut hasher, format!("{:?}", manifest).as_bytes());
+        for (name, data) in self.os.profile().get_system_files() {
+            feed(&mut hasher, name.as_bytes());
+            feed(&mut hasher, data);
+        }
+        for import in &self.imports {
+            let source = self.base.join(&import.source);
+            hash_host_path(&mut hasher, &source, "")
+                .map_err(|error| ManifestError::Io(source.clone(), error))?;
+            if let Some(rules) = &import.rules {
+                let path = self.base.join(rules);
+                let data =
+                    std::fs::read(&path).map_err(|error| ManifestError::Io(path.clone(), error))?;
+                feed(&mut hasher, &data);
+            }
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Like build_to_file(), but leave the image alone when the cache says it was built from
+    /// the same inputs before and it's still there, and start from a cached copy of the
+    /// formatted disk with the OS on it otherwise.
+    pub fn build_cached(&self, cache: &BuildCache) -> Result<Output, ManifestError> {
+        let inputs = self.input_digest()?;
+        let path = self.output_path();
+        if let Some(record) = cache.lookup(&inputs) {
+            let unchanged = std::fs::metadata(&path)
+                .map(|metadata| {
+                    metadata.len() == record.size && modified_nanos(&metadata) == record.modified
+                })
+                .unwrap_or(false);
+            if unchanged {
+                return Ok(Output {
+                    path,
+                    size: record.size,
+                    digest: record.digest,
+                    cached: true,
+                });
+            }
+        }
+        let (disk, partition) = cache.template(self)?;
+        let output = self.write(&self.finish(disk, partition)?)?;
+        cache
+            .store(&inputs, &output)
+            .map_err(|error| ManifestError::Io(cache.record_path(&inputs), error))?;
+        Ok(output)
+    }
+}
+
+/// Hash a piece of data along with its length, so the boundaries between pieces count too.
+fn feed(hasher: &mut Sha256, data: &[u8]) {
+    hasher.update((data.len() as u64).to_le_bytes());
+    hasher.update(data);
+}
+
+/// Hash a file, or a directory with everything in it by name and contents, in the same order
+/// the import walks it. Symbolic links inside the tree aren't followed, just like the import
+/// doesn't, so only where they point counts.
+fn hash_host_path(hasher: &mut Sha256, host: &Path, relative: &str) -> io::Result<()> {
+    let metadata = if relative.is_empty() {
+        std::fs::metadata(host)?
+    } else {
+        std::fs::symlink_metadata(host)?
+    };
+    if metadata.is_symlink() {
+        feed(hasher, format!("{} ->", relative).as_bytes());
+        let target = std::fs::read_link(host)?;
+        feed(hasher, target.to_string_lossy().as_bytes());
+        return Ok(());
+    }
+    if !metadata.is_dir() {
+        feed(hasher, relative.as_bytes());
+        feed(hasher, &std::fs::read(host)?);
+        return Ok(());
+    }
+    feed(hasher, format!("{}/", relative).as_bytes());
+    let mut children = std::fs::read_dir(host)?.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let name = child.file_name().to_string_lossy().to_string();
+        let relative = if relative.is_empty() {
+            name
+        } else {
+            format!("{}/{}", relative, name)
+        };
+        hash_host_path(hasher, &child.path(), &relative)?;
+    }
+    Ok(())
+}
+
+fn modified_nanos(metadata: &std::fs::Metadata) -> u128 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos())
+}
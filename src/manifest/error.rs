@@ -0,0 +1,72 @@
+use crate::fs::{FsError, ImportReport};
+use crate::os::{AutoexecProblem, ConfigProblem};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Everything that can stop a manifest from turning into an image.
+#[derive(Debug)]
+pub enum ManifestError {
+    /// Reading the manifest or something it points at, or writing the image, failed.
+    Io(PathBuf, io::Error),
+    /// The manifest isn't valid TOML or has fields that don't belong there.
+    Parse(String),
+    /// The manifest asks for something that can't be built.
+    Invalid(String),
+    /// The file system refused something the manifest asked for.
+    Fs(FsError),
+    /// Not everything from an import made it onto the volume.
+    Incomplete(PathBuf, ImportReport),
+    /// The generated CONFIG.SYS doesn't work with what ended up on the volume.
+    Config(Vec<ConfigProblem>),
+    /// The generated AUTOEXEC.BAT doesn't work with what ended up on the volume.
+    Autoexec(Vec<AutoexecProblem>),
+    /// The build ran into a bug and panicked, this is what the panic said.
+    Crashed(String),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestError::Io(path, error) => write!(f, "{}: {}", path.display(), error),
+            ManifestError::Parse(reason) => write!(f, "invalid manifest: {}", reason),
+            ManifestError::Invalid(reason) => write!(f, "can't build this manifest: {}", reason),
+            ManifestError::Fs(error) => write!(f, "{}", error),
+            ManifestError::Incomplete(path, report) => {
+                write!(
+                    f,
+                    "{}: {} entries failed to import",
+                    path.display(),
+                    report.errors.len()
+                )?;
+                for (host, error) in &report.errors {
+                    write!(f, "\n  {}: {}", host.display(), error)?;
+                }
+                Ok(())
+            }
+            ManifestError::Config(problems) => {
+                write!(f, "CONFIG.SYS has {} problems", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            ManifestError::Autoexec(problems) => {
+                write!(f, "AUTOEXEC.BAT has {} problems", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            ManifestError::Crashed(reason) => write!(f, "the build crashed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+impl From<FsError> for ManifestError {
+    fn from(error: FsError) -> ManifestError {
+        ManifestError::Fs(error)
+    }
+}
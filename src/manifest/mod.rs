@@ -0,0 +1,319 @@
+use crate::fs::{FormatOptions, Timestamp};
+use crate::os::{AutoexecBat, ConfigSys, OsProfile};
+use serde::de::{DeserializeOwned, Error};
+use serde::{Deserialize, Deserializer};
+use std::path::{Path, PathBuf};
+
+pub use crate::manifest::batch::{build_batch, find_manifests, BatchEntry, BatchReport};
+pub use crate::manifest::cache::BuildCache;
+pub use crate::manifest::error::ManifestError;
+
+mod batch;
+mod build;
+mod cache;
+mod error;
+
+#[cfg(test)]
+mod tests;
+
+/// Everything that goes into a container, written down as a TOML file so that building it
+/// again gives the exact same image. Paths in a manifest are relative to the directory the
+/// manifest lives in. Only the disk size or geometry is required, everything else has a
+/// default: MS-DOS 6.22 on a single partition that takes up the whole disk, written as a raw
+/// image next to the manifest.
+///
+/// ```toml
+/// name = "keen4"
+/// os = "msdos622"
+/// source_date_epoch = 770000000
+/// config_sys = """
+/// FILES=30
+/// BUFFERS=20
+/// """
+/// autoexec_bat = '''
+/// @ECHO OFF
+/// CD \GAMES\KEEN4
+/// '''
+///
+/// [disk]
+/// size = "32M"
+///
+/// [partition]
+/// label = "KEEN"
+/// cluster_size = 2048
+///
+/// [[import]]
+/// source = "keen4"
+/// target = "GAMES\\KEEN4"
+/// rules = "keen4.rules"
+///
+/// [[import]]
+/// source = "extras.zip"
+///
+/// [output]
+/// path = "keen4.vhd"
+/// format = "vhd"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Name of the container. It names the image and seeds the volume serial number unless
+    /// those are set explicitly.
+    pub name: Option<String>,
+    #[serde(default)]
+    pub os: Os,
+    /// Seconds since the Unix epoch, used for every timestamp on the volume. Without one, all
+    /// files and directories get January 1st, 1980.
+    pub source_date_epoch: Option<u64>,
+    pub disk: DiskSpec,
+    #[serde(default)]
+    pub partition: PartitionSpec,
+    /// Host directories and ZIP archives to copy onto the volume, in this order.
+    #[serde(default, rename = "import")]
+    pub imports: Vec<ImportSpec>,
+    /// Contents of C:\CONFIG.SYS, line endings get converted to CR LF. It can also be a list
+    /// of directives, or a table with boot profiles for a startup menu, see ConfigSys. Those
+    /// get checked against the files on the volume.
+    pub config_sys: Option<StartupFile<ConfigSys>>,
+    /// Contents of C:\AUTOEXEC.BAT, line endings get converted to CR LF. It can also be a
+    /// table that describes it, see AutoexecBat, which gets checked against the files on the
+    /// volume.
+    pub autoexec_bat: Option<StartupFile<AutoexecBat>>,
+    #[serde(default)]
+    pub output: OutputSpec,
+    /// Where relative paths in the manifest start from.
+    #[serde(skip)]
+    pub base: PathBuf,
+}
+
+/// The operating system to install on the container.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Os {
+    #[default]
+    Msdos622,
+    None,
+}
+
+impl Os {
+    pub fn profile(&self) -> OsProfile {
+        match self {
+            Os::Msdos622 => OsProfile::msdos622(),
+            Os::None => OsProfile::none(),
+        }
+    }
+}
+
+/// CONFIG.SYS or AUTOEXEC.BAT, written out as text in the manifest or generated from
+/// something that knows what goes into them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StartupFile<T> {
+    Text(String),
+    Generated(T),
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for StartupFile<T> {
+    /// A string is the text of the file, anything else has to be what generates it. This
+    /// goes through a TOML value instead of an untagged enum, so a mistake in a generated
+    /// file gets an error that says what's wrong with it.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match toml::Value::deserialize(deserializer)? {
+            toml::Value::String(text) => Ok(StartupFile::Text(text)),
+            value => T::deserialize(value)
+                .map(StartupFile::Generated)
+                .map_err(D::Error::custom),
+        }
+    }
+}
+
+/// The size of the disk, either directly or through its geometry. Disks are limited to what
+/// INT 13h can address without translation, a little over 500MB.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DiskSpec {
+    /// Size in bytes, like 33554432 or "32M". The geometry follows from the size the way
+    /// the Bochs BIOS calculates it.
+    pub size: Option<Size>,
+    /// An explicit geometry for when an emulator or a game expects a specific drive.
+    pub geometry: Option<Geometry>,
+}
+
+/// Cylinders and heads of a disk. Tracks always have 63 sectors, like on every disk this
+/// tool makes.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Geometry {
+    pub cylinders: u16,
+    pub heads: u8,
+}
+
+/// The partition and the file system on it. There's just the one, it's active and it starts
+/// at the second track.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PartitionSpec {
+    /// Size of the partition, the rest of the disk if it's not set.
+    pub size: Option<Size>,
+    pub label: Option<String>,
+    /// Cluster size in bytes. Partitions under about 4MB are too small for FAT16 and need
+    /// this set to get FAT12.
+    pub cluster_size: Option<u32>,
+    pub root_entries: Option<u16>,
+    pub fats: Option<u8>,
+    pub reserved_sectors: Option<u16>,
+    /// Store long file names next to the 8.3 names, for Windows 95 and later.
+    #[serde(default)]
+    pub long_names: bool,
+    /// Seed for the volume serial number, the name of the container by default.
+    pub serial_seed: Option<String>,
+}
+
+impl PartitionSpec {
+    pub fn format_options(&self) -> FormatOptions {
+        let mut options = FormatOptions::new();
+        if let Some(bytes) = self.cluster_size {
+            options = options.cluster_size(bytes);
+        }
+        if let Some(entries) = self.root_entries {
+            options = options.root_entries(entries);
+        }
+        if let Some(count) = self.fats {
+            options = options.fats(count);
+        }
+        if let Some(sectors) = self.reserved_sectors {
+            options = options.reserved_sectors(sectors);
+        }
+        options
+    }
+}
+
+/// A directory or ZIP archive on the host that gets copied onto the volume.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ImportSpec {
+    pub source: PathBuf,
+    /// Directory on the volume to copy into, created when it doesn't exist. The root
+    /// directory by default.
+    #[serde(default)]
+    pub target: String,
+    /// An attribute rules file, see AttributeRules for the format.
+    pub rules: Option<PathBuf>,
+}
+
+/// Where the image goes and in what format.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OutputSpec {
+    /// Path of the image, the name of the container with the extension of the format by
+    /// default.
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub format: OutputFormat,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// A plain sector by sector image.
+    #[default]
+    Raw,
+    /// A fixed size VHD: the raw image with a footer that carries the geometry.
+    Vhd,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Raw => "img",
+            OutputFormat::Vhd => "vhd",
+        }
+    }
+}
+
+/// What build_to_file() or build_cached() wrote.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Output {
+    pub path: PathBuf,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// SHA-256 of the file as a lowercase hexadecimal string, the same sha256sum prints.
+    pub digest: String,
+    /// The image was already there from an earlier build of the same inputs, so it was left
+    /// alone instead of being built again.
+    pub cached: bool,
+}
+
+/// A size in bytes. Manifests can write it as a plain number or as a string with a K, M or
+/// G suffix, which count in powers of 1024 like DOS does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Size(pub u64);
+
+impl Size {
+    /// Parse sizes like "512", "640K", "32M" or "1G". A trailing B is allowed as well.
+    pub fn parse(text: &str) -> Option<Size> {
+        let text = text.trim().to_ascii_uppercase();
+        let text = text.strip_suffix('B').unwrap_or(&text);
+        let (digits, multiplier) = match text.chars().last()? {
+            'K' => (&text[..text.len() - 1], 1 << 10),
+            'M' => (&text[..text.len() - 1], 1 << 20),
+            'G' => (&text[..text.len() - 1], 1 << 30),
+            _ => (text, 1),
+        };
+        let value: u64 = digits.trim().parse().ok()?;
+        value.checked_mul(multiplier).map(Size)
+    }
+}
+
+impl<'de> Deserialize<'de> for Size {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Written {
+            Bytes(u64),
+            Text(String),
+        }
+        match Written::deserialize(deserializer)? {
+            Written::Bytes(bytes) => Ok(Size(bytes)),
+            Written::Text(text) => Size::parse(&text)
+                .ok_or_else(|| D::Error::custom(format!("invalid size: {}", text))),
+        }
+    }
+}
+
+impl Manifest {
+    /// Parse a manifest from TOML. Relative paths in it start from the current directory
+    /// until base is set.
+    pub fn parse(text: &str) -> Result<Manifest, ManifestError> {
+        toml::from_str(text).map_err(|error| ManifestError::Parse(error.to_string()))
+    }
+
+    /// Read a manifest from a file on the host. Relative paths in it start from the
+    /// directory the manifest is in.
+    pub fn load(path: &Path) -> Result<Manifest, ManifestError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| ManifestError::Io(path.to_path_buf(), error))?;
+        let mut manifest = Manifest::parse(&text)?;
+        manifest.base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(manifest)
+    }
+
+    /// Where the image gets written.
+    pub fn output_path(&self) -> PathBuf {
+        match &self.output.path {
+            Some(path) => self.base.join(path),
+            None => {
+                let name = self.name.as_deref().unwrap_or("container");
+                let extension = self.output.format.extension();
+                self.base.join(format!("{}.{}", name, extension))
+            }
+        }
+    }
+
+    /// The fixed moment in time every timestamp on the volume gets.
+    pub fn epoch(&self) -> Timestamp {
+        match self.source_date_epoch {
+            Some(seconds) => Timestamp::from_unix(seconds),
+            None => Timestamp::default(),
+        }
+    }
+}
@@ -0,0 +1,532 @@
+use crate::disk::chs::CHS;
+use crate::emu::BootOutcome;
+use crate::fs::FsError;
+use crate::manifest::{
+    build_batch, find_manifests, BuildCache, Geometry, Manifest, ManifestError, Os, OutputFormat,
+    Size, StartupFile,
+};
+use crate::os::{AutoexecProblem, ConfigProblem, Directive};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// A fresh, empty scratch directory on the host for manifests and the files they import.
+fn scratch_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("doscontainer-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+#[test]
+fn parse_manifest() {
+    let manifest = Manifest::parse(
+        r#"
+        name = "keen4"
+        os = "none"
+        source_date_epoch = 770000000
+        config_sys = "FILES=30"
+
+        [disk]
+        geometry = { cylinders = 100, heads = 16 }
+
+        [partition]
+        size = "20MB"
+        label = "KEEN"
+        cluster_size = 2048
+
+        [[import]]
+        source = "keen4"
+        target = "GAMES\\KEEN4"
+
+        [[import]]
+        source = "extras.zip"
+
+        [output]
+        format = "vhd"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(manifest.os, Os::None);
+    assert_eq!(
+        manifest.disk.geometry,
+        Some(Geometry {
+            cylinders: 100,
+            heads: 16
+        })
+    );
+    assert_eq!(manifest.partition.size, Some(Size(20 * 1024 * 1024)));
+    assert_eq!(manifest.imports.len(), 2);
+    assert_eq!(manifest.imports[0].target, "GAMES\\KEEN4");
+    assert_eq!(manifest.imports[1].target, "");
+    assert_eq!(manifest.output.format, OutputFormat::Vhd);
+    assert_eq!(manifest.output_path(), PathBuf::from("keen4.vhd"));
+
+    let defaults = Manifest::parse("[disk]\nsize = 33554432").unwrap();
+    assert_eq!(defaults.os, Os::Msdos622);
+    assert_eq!(defaults.disk.size, Some(Size(33554432)));
+    assert_eq!(defaults.output_path(), PathBuf::from("container.img"));
+
+    assert!(matches!(
+        Manifest::parse("[disk]\nsize = \"32 parsecs\""),
+        Err(ManifestError::Parse(_))
+    ));
+    assert!(matches!(
+        Manifest::parse("[disk]\nsize = \"32M\"\nflavour = \"vanilla\""),
+        Err(ManifestError::Parse(_))
+    ));
+}
+
+#[test]
+fn parse_sizes() {
+    assert_eq!(Size::parse("512"), Some(Size(512)));
+    assert_eq!(Size::parse("640K"), Some(Size(655360)));
+    assert_eq!(Size::parse("32m"), Some(Size(33554432)));
+    assert_eq!(Size::parse("1 GB"), Some(Size(1073741824)));
+    assert_eq!(Size::parse("M"), None);
+    assert_eq!(Size::parse(""), None);
+}
+
+/// A manifest with everything in it builds an image that boots, with all files where they
+/// belong, and builds the exact same image the second time.
+#[test]
+fn build_manifest() {
+    let base = scratch_dir("manifest");
+    std::fs::create_dir_all(base.join("keen4")).unwrap();
+    std::fs::write(base.join("keen4/keen4e.exe"), b"MZ").unwrap();
+    std::fs::write(base.join("keen4.rules"), "KEEN4E.EXE R\n").unwrap();
+    let mut archive = zip::ZipWriter::new(std::fs::File::create(base.join("extras.zip")).unwrap());
+    archive
+        .start_file("utils/list.com", zip::write::FileOptions::default())
+        .unwrap();
+    archive.write_all(&[0xCD, 0x20]).unwrap();
+    archive.finish().unwrap();
+    std::fs::write(
+        base.join("keen4.toml"),
+        r#"
+        name = "keen4"
+        source_date_epoch = 770000000
+        config_sys = "FILES=30\nBUFFERS=20\n"
+        autoexec_bat = '''
+        @ECHO OFF
+        '''
+
+        [disk]
+        size = "16M"
+
+        [partition]
+        label = "Keen 4"
+
+        [[import]]
+        source = "keen4"
+        target = "GAMES\\KEEN4"
+        rules = "keen4.rules"
+
+        [[import]]
+        source = "extras.zip"
+        "#,
+    )
+    .unwrap();
+
+    let manifest = Manifest::load(&base.join("keen4.toml")).unwrap();
+    let disk = manifest.build().unwrap();
+    assert_eq!(disk.digest(), manifest.build().unwrap().digest());
+    let volume = disk.get_partition(0).unwrap().get_volume().unwrap();
+    let root: Vec<String> = volume
+        .read_dir("")
+        .unwrap()
+        .iter()
+        .filter(|entry| !entry.get_attributes().vol_id)
+        .map(|entry| entry.get_short_name())
+        .collect();
+    assert_eq!(
+        root,
+        [
+            "IO.SYS",
+            "MSDOS.SYS",
+            "COMMAND.COM",
+            "DRVSPACE.BIN",
+            "CONFIG.SYS",
+            "AUTOEXEC.BAT",
+            "GAMES",
+            "UTILS"
+        ]
+    );
+    assert_eq!(
+        volume.read_file("CONFIG.SYS").unwrap(),
+        b"FILES=30\r\nBUFFERS=20\r\n"
+    );
+    let keen = volume.get_entry("GAMES\\KEEN4\\KEEN4E.EXE").unwrap();
+    assert!(keen.get_attributes().read_only);
+    assert_eq!(keen.get_modified(), manifest.epoch());
+    assert_eq!(volume.read_file("UTILS\\LIST.COM").unwrap(), [0xCD, 0x20]);
+    assert_eq!(volume.get_label(), Some("KEEN 4".to_string()));
+    assert!(disk.verify_boot().is_bootable());
+    assert_eq!(disk.boot_test().outcome, BootOutcome::Booted);
+
+    let output = manifest.build_to_file().unwrap();
+    let written = std::fs::read(&output.path).unwrap();
+    std::fs::remove_dir_all(&base).unwrap();
+    assert_eq!(output.path, base.join("keen4.img"));
+    assert_eq!(output.size, 16 * 1024 * 1024);
+    assert_eq!(written.len() as u64, output.size);
+    assert_eq!(output.digest, format!("{:x}", Sha256::digest(&written)));
+    assert_eq!(output.digest, disk.digest());
+}
+
+#[test]
+fn build_rejects_impossible_manifests() {
+    let invalid = |text: &str| match Manifest::parse(text).unwrap().build() {
+        Err(ManifestError::Invalid(reason)) => reason,
+        other => panic!("{:?}", other.map(|disk| disk.digest())),
+    };
+    assert_eq!(invalid("[disk]"), "the disk needs a size or a geometry");
+    assert_eq!(
+        invalid("[disk]\nsize = \"16M\"\ngeometry = { cylinders = 100, heads = 16 }"),
+        "set either the size or the geometry of the disk, not both"
+    );
+    assert_eq!(
+        invalid("[disk]\nsize = \"16M\"\n[partition]\nsize = \"32M\""),
+        "a partition of 33554432 bytes doesn't fit on the disk"
+    );
+    assert!(matches!(
+        Manifest::parse("[disk]\nsize = \"16M\"\n[[import]]\nsource = \"/nonexistent\"")
+            .unwrap()
+            .build(),
+        Err(ManifestError::Io(_, _))
+    ));
+    assert!(matches!(
+        Manifest::parse("[disk]\nsize = \"16M\"\n[partition]\ncluster_size = 3000")
+            .unwrap()
+            .build(),
+        Err(ManifestError::Fs(_))
+    ));
+}
+
+/// Disks too small for FAT16 get a clean error, or FAT12 when the manifest picks a cluster size.
+#[test]
+fn build_small_disks() {
+    assert!(matches!(
+        Manifest::parse("[disk]\nsize = \"2M\"").unwrap().build(),
+        Err(ManifestError::Fs(FsError::InvalidSize(_)))
+    ));
+    let manifest =
+        Manifest::parse("[disk]\nsize = \"2M\"\n[partition]\ncluster_size = 2048").unwrap();
+    let disk = manifest.build().unwrap();
+    let volume = disk.get_partition(0).unwrap().get_volume().unwrap();
+    assert!(volume.is_fat12());
+    assert_eq!(volume.get_cluster_size(), 2048);
+    assert_eq!(disk.get_partition(0).unwrap().partition_type, 0x01);
+    assert!(disk.verify_boot().is_bootable());
+    assert_eq!(disk.boot_test().outcome, BootOutcome::Booted);
+}
+
+/// The boot code needs the geometry of the disk in the BPB to find IO.SYS.
+#[test]
+fn build_with_geometry() {
+    let manifest = Manifest::parse("[disk]\ngeometry = { cylinders = 200, heads = 4 }").unwrap();
+    let disk = manifest.build().unwrap();
+    assert_eq!(disk.geometry, CHS::new(200, 4, 63));
+    assert!(disk.verify_boot().is_bootable());
+    assert_eq!(disk.boot_test().outcome, BootOutcome::Booted);
+}
+
+#[test]
+fn build_batch_of_manifests() {
+    let base = scratch_dir("batch");
+    for name in ["a", "b", "c"] {
+        let text = format!("name = \"{}\"\n[disk]\nsize = \"8M\"", name);
+        std::fs::write(base.join(format!("{}.toml", name)), text).unwrap();
+    }
+    std::fs::write(
+        base.join("broken.TOML"),
+        "[disk]\nsize = \"8M\"\ncolour = 1",
+    )
+    .unwrap();
+    std::fs::write(
+        base.join("same.toml"),
+        "name = \"a\"\n[disk]\nsize = \"16M\"",
+    )
+    .unwrap();
+    std::fs::write(base.join("notes.txt"), "not a manifest").unwrap();
+
+    let manifests = find_manifests(&base).unwrap();
+    let names: Vec<String> = manifests
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(
+        names,
+        ["a.toml", "b.toml", "broken.TOML", "c.toml", "same.toml"]
+    );
+    assert_eq!(find_manifests(&base.join("?.toml")).unwrap().len(), 3);
+    assert_eq!(
+        find_manifests(&base.join("a.toml")).unwrap(),
+        [base.join("a.toml")]
+    );
+
+    let report = build_batch(&manifests, 2, None);
+    assert_eq!((report.succeeded(), report.failed()), (3, 2));
+    assert_eq!(report.entries[0].manifest, base.join("a.toml"));
+    let output = report.entries[0].result.as_ref().unwrap();
+    assert_eq!(output.path, base.join("a.img"));
+    assert_eq!(output.size, 8 * 1024 * 1024);
+    assert!(matches!(
+        report.entries[2].result,
+        Err(ManifestError::Parse(_))
+    ));
+    assert!(matches!(
+        report.entries[4].result,
+        Err(ManifestError::Invalid(_))
+    ));
+    assert!(base.join("c.img").exists());
+    let text = report.to_string();
+    assert!(text.ends_with("3 built, 0 up to date, 2 failed"));
+    assert_eq!(
+        text.lines().filter(|line| line.starts_with("ok\t")).count(),
+        3
+    );
+
+    // One worker builds the same images as many.
+    let serial = build_batch(&manifests, 1, None);
+    std::fs::remove_dir_all(&base).unwrap();
+    assert_eq!(
+        serial.entries[1].result.as_ref().unwrap(),
+        report.entries[1].result.as_ref().unwrap()
+    );
+}
+
+#[test]
+fn build_with_cache() {
+    let base = scratch_dir("cache");
+    std::fs::create_dir_all(base.join("game")).unwrap();
+    std::fs::write(base.join("game/game.exe"), b"MZ").unwrap();
+    let manifest = "[disk]\nsize = \"8M\"\n[[import]]\nsource = \"game\"";
+    std::fs::write(
+        base.join("game.toml"),
+        format!("name = \"game\"\n{}", manifest),
+    )
+    .unwrap();
+    std::fs::write(
+        base.join("other.toml"),
+        format!("name = \"other\"\n{}", manifest),
+    )
+    .unwrap();
+    let cache = BuildCache::open(&base.join("cache")).unwrap();
+    let game = Manifest::load(&base.join("game.toml")).unwrap();
+    let other = Manifest::load(&base.join("other.toml")).unwrap();
+
+    let first = game.build_cached(&cache).unwrap();
+    assert!(!first.cached);
+    assert_eq!(first.digest, game.build().unwrap().digest());
+    let again = game.build_cached(&cache).unwrap();
+    assert!(again.cached);
+    assert_eq!(
+        (again.digest, again.size),
+        (first.digest.clone(), first.size)
+    );
+
+    // The second manifest starts from the disk the first one formatted, and still ends up
+    // with the image it would have had without the cache.
+    let built = other.build_cached(&cache).unwrap();
+    assert!(!built.cached);
+    assert_eq!(built.digest, other.build().unwrap().digest());
+
+    // Changing an imported file changes the inputs, replacing the image makes it stale.
+    let inputs = game.input_digest().unwrap();
+    std::fs::write(base.join("game/game.exe"), b"MZ\x90").unwrap();
+    assert_ne!(game.input_digest().unwrap(), inputs);
+    let rebuilt = game.build_cached(&cache).unwrap();
+    assert!(!rebuilt.cached);
+    assert_ne!(rebuilt.digest, first.digest);
+    std::fs::write(&rebuilt.path, b"not an image").unwrap();
+    let repaired = game.build_cached(&cache).unwrap();
+    std::fs::remove_dir_all(&base).unwrap();
+    assert!(!repaired.cached);
+    assert_eq!(repaired.digest, rebuilt.digest);
+}
+
+/// A link back up the tree mustn't send hashing or the import around in circles.
+#[cfg(unix)]
+#[test]
+fn symlink_loops_in_imports() {
+    let base = scratch_dir("symlinks");
+    std::fs::create_dir_all(base.join("game/data")).unwrap();
+    std::fs::write(base.join("game/game.exe"), b"MZ").unwrap();
+    std::os::unix::fs::symlink(base.join("game"), base.join("game/data/loop")).unwrap();
+    let manifest = Manifest::parse("[disk]\nsize = \"8M\"\n[[import]]\nsource = \"game\"").unwrap();
+    let manifest = Manifest {
+        base: base.clone(),
+        ..manifest
+    };
+    let result = manifest.build();
+    let inputs = manifest.input_digest().unwrap();
+    std::fs::remove_file(base.join("game/data/loop")).unwrap();
+    std::os::unix::fs::symlink(base.join("elsewhere"), base.join("game/data/loop")).unwrap();
+    assert_ne!(manifest.input_digest().unwrap(), inputs);
+    std::fs::remove_dir_all(&base).unwrap();
+    match result {
+        Err(ManifestError::Incomplete(_, report)) => {
+            assert_eq!(
+                report.errors,
+                [(
+                    base.join("game/data/loop"),
+                    FsError::SymbolicLink("loop".to_string())
+                )]
+            );
+            assert_eq!(report.mapping.len(), 2);
+        }
+        other => panic!("{:?}", other.map(|disk| disk.digest())),
+    }
+}
+
+#[test]
+fn build_generated_config_sys() {
+    let base = scratch_dir("config");
+    std::fs::create_dir_all(base.join("dos")).unwrap();
+    std::fs::write(base.join("dos/himem.sys"), b"driver").unwrap();
+    let manifest = |device: &str| {
+        format!(
+            "config_sys = [\n\
+             {{ device = '{}' }},\n\
+             {{ dos = [\"high\"] }},\n\
+             {{ files = 30 }},\n\
+             ]\n\
+             [disk]\nsize = \"8M\"\n\
+             [[import]]\nsource = \"dos\"\ntarget = \"DOS\"\n",
+            device
+        )
+    };
+    std::fs::write(base.join("good.toml"), manifest("C:\\DOS\\HIMEM.SYS")).unwrap();
+    std::fs::write(base.join("bad.toml"), manifest("C:\\DOS\\EMM386.EXE")).unwrap();
+
+    let good = Manifest::load(&base.join("good.toml")).unwrap();
+    match &good.config_sys {
+        Some(StartupFile::Generated(config)) => {
+            assert_eq!(config.directives[2], Directive::Files(30))
+        }
+        other => panic!("{:?}", other),
+    }
+    let disk = good.build().unwrap();
+    let volume = disk.get_partition(0).unwrap().get_volume().unwrap();
+    let names: Vec<String> = volume
+        .read_dir("")
+        .unwrap()
+        .iter()
+        .map(|entry| entry.get_short_name())
+        .collect();
+    assert_eq!(names[4], "CONFIG.SYS");
+    assert_eq!(
+        volume.read_file("CONFIG.SYS").unwrap(),
+        b"DEVICE=C:\\DOS\\HIMEM.SYS\r\nDOS=HIGH\r\nFILES=30\r\n"
+    );
+
+    let bad = Manifest::load(&base.join("bad.toml")).unwrap().build();
+    std::fs::remove_dir_all(&base).unwrap();
+    match bad {
+        Err(ManifestError::Config(problems)) => assert_eq!(
+            problems,
+            [ConfigProblem::MissingFile {
+                directive: "DEVICE",
+                path: "C:\\DOS\\EMM386.EXE".to_string()
+            }]
+        ),
+        other => panic!("{:?}", other.map(|disk| disk.digest())),
+    }
+    let typo = Manifest::parse("config_sys = [{ file = 30 }]\n[disk]\nsize = \"8M\"");
+    match typo {
+        Err(ManifestError::Parse(reason)) => assert!(reason.contains("file"), "{}", reason),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn build_generated_autoexec_bat() {
+    let base = scratch_dir("autoexec");
+    std::fs::create_dir_all(base.join("keen4")).unwrap();
+    std::fs::write(base.join("keen4/keen4e.exe"), b"MZ").unwrap();
+    let manifest = |command: &str| {
+        format!(
+            "[disk]\nsize = \"8M\"\n\
+             [autoexec_bat]\n\
+             blaster = {{ address = 0x220, irq = 7, dma = 1, type = 3 }}\n\
+             launch = {{ directory = 'C:\\GAMES\\KEEN4', command = '{}' }}\n\
+             [[import]]\nsource = \"keen4\"\ntarget = 'GAMES\\KEEN4'\n",
+            command
+        )
+    };
+    std::fs::write(base.join("good.toml"), manifest("KEEN4E")).unwrap();
+    std::fs::write(base.join("bad.toml"), manifest("KEEN5E")).unwrap();
+
+    let disk = Manifest::load(&base.join("good.toml"))
+        .unwrap()
+        .build()
+        .unwrap();
+    let volume = disk.get_partition(0).unwrap().get_volume().unwrap();
+    assert_eq!(
+        volume.read_file("AUTOEXEC.BAT").unwrap(),
+        b"@ECHO OFF\r\nSET BLASTER=A220 I7 D1 T3\r\nC:\r\nCD C:\\GAMES\\KEEN4\r\nKEEN4E\r\n\x1A"
+    );
+    assert_eq!(disk.boot_test().outcome, BootOutcome::Booted);
+
+    let bad = Manifest::load(&base.join("bad.toml")).unwrap().build();
+    std::fs::remove_dir_all(&base).unwrap();
+    match bad {
+        Err(ManifestError::Autoexec(problems)) => assert_eq!(
+            problems,
+            [AutoexecProblem::MissingProgram("KEEN5E".to_string())]
+        ),
+        other => panic!("{:?}", other.map(|disk| disk.digest())),
+    }
+}
+
+#[test]
+fn build_boot_menu() {
+    let manifest = |section: &str| {
+        Manifest::parse(&format!(
+            r#"
+            [disk]
+            size = "8M"
+
+            [config_sys]
+            common = [{{ files = 30 }}]
+            timeout = 3
+
+            [[config_sys.profile]]
+            name = "EMS"
+            title = "Expanded memory"
+
+            [[config_sys.profile]]
+            name = "XMS"
+
+            [autoexec_bat]
+            commands = ["VER"]
+
+            [[autoexec_bat.profile]]
+            name = "{}"
+            set = {{ mode = "xms" }}
+            "#,
+            section
+        ))
+        .unwrap()
+    };
+    let disk = manifest("XMS").build().unwrap();
+    let volume = disk.get_partition(0).unwrap().get_volume().unwrap();
+    let config = String::from_utf8(volume.read_file("CONFIG.SYS").unwrap()).unwrap();
+    assert!(config.starts_with("[MENU]\r\nMENUITEM=EMS,Expanded memory\r\nMENUITEM=XMS\r\n"));
+    assert!(config.contains("MENUDEFAULT=EMS,3\r\n\r\n[COMMON]\r\nFILES=30\r\n"));
+    assert_eq!(
+        volume.read_file("AUTOEXEC.BAT").unwrap(),
+        b"@ECHO OFF\r\nVER\r\nGOTO %CONFIG%\r\n\r\n:EMS\r\nGOTO END\r\n\r\n\
+          :XMS\r\nSET MODE=xms\r\nGOTO END\r\n\r\n:END\r\n\x1A"
+    );
+    assert_eq!(disk.boot_test().outcome, BootOutcome::Booted);
+
+    match manifest("CDROM").build() {
+        Err(ManifestError::Autoexec(problems)) => assert_eq!(
+            problems,
+            [AutoexecProblem::UnknownProfile("CDROM".to_string())]
+        ),
+        other => panic!("{:?}", other.map(|disk| disk.digest())),
+    }
+}
@@ -0,0 +1,395 @@
+use crate::fs::{DirEntry, File, FsError, Volume};
+use crate::os::{find_on_boot_drive, BootPath, ConfigSys};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// What DOS tries, in this order, for a command typed without an extension.
+const EXTENSIONS: [&str; 3] = ["COM", "EXE", "BAT"];
+
+/// An AUTOEXEC.BAT that sets up the environment, loads the drivers and TSRs a game needs and
+/// then starts it, so the container boots straight into the game. The lines come out in a
+/// fixed order: PROMPT, PATH, the environment variables, the programs to load high, other
+/// commands, and finally changing to the game's directory and starting it. In a manifest:
+///
+/// ```toml
+/// [autoexec_bat]
+/// prompt = "$P$G"
+/// path = ['C:\DOS']
+/// temp = 'C:\TEMP'
+/// blaster = { address = 0x220, irq = 5, dma = 1, type = 4 }
+/// loadhigh = ['C:\DOS\MOUSE.COM', 'KEYB US,,C:\DOS\KEYBOARD.SYS']
+/// launch = { directory = 'C:\GAMES\KEEN4', command = "KEEN4E" }
+/// ```
+///
+/// With boot profiles in CONFIG.SYS, every profile can get its own section that runs after
+/// the common part and before the game starts, picked with GOTO %CONFIG%:
+///
+/// ```toml
+/// [[autoexec_bat.profile]]
+/// name = "CDROM"
+/// loadhigh = ['C:\DOS\MSCDEX.EXE /D:MSCD001']
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AutoexecBat {
+    /// Leave echo on, so every line shows up on the screen while it runs. It's turned off
+    /// with @ECHO OFF by default.
+    #[serde(default)]
+    pub echo: bool,
+    pub prompt: Option<String>,
+    /// Directories to look for programs in, C:\DOS for instance.
+    #[serde(default)]
+    pub path: Vec<String>,
+    /// Directory for temporary files, set as TEMP.
+    pub temp: Option<String>,
+    pub blaster: Option<Blaster>,
+    pub ultrasnd: Option<Ultrasnd>,
+    /// Any other environment variables. They get set in order of their names.
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+    /// TSRs and drivers to load into upper memory with LH, like MSCDEX, MOUSE and KEYB. The
+    /// program is looked up in the root directory and on the PATH like DOS would.
+    #[serde(default)]
+    pub loadhigh: Vec<String>,
+    /// More commands to run before the game starts, exactly as written.
+    #[serde(default)]
+    pub commands: Vec<String>,
+    /// What to do differently for each boot profile of CONFIG.SYS.
+    #[serde(default, rename = "profile")]
+    pub profiles: Vec<ProfileSection>,
+    pub launch: Option<Launch>,
+}
+
+/// The part of AUTOEXEC.BAT that only runs for one boot profile.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileSection {
+    /// The name of the profile in CONFIG.SYS.
+    pub name: String,
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+    #[serde(default)]
+    pub loadhigh: Vec<String>,
+    #[serde(default)]
+    pub commands: Vec<String>,
+}
+
+/// The BLASTER variable that tells games where to find a Sound Blaster.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Blaster {
+    /// The base I/O port, usually 0x220.
+    pub address: u16,
+    pub irq: u8,
+    /// The 8-bit DMA channel.
+    pub dma: u8,
+    /// The 16-bit DMA channel of a Sound Blaster 16.
+    pub high_dma: Option<u8>,
+    /// The MPU-401 port of a Sound Blaster 16, usually 0x330.
+    pub mpu: Option<u16>,
+    /// The model: 1 for the original, 3 for a Pro, 6 for a 16 and so on.
+    #[serde(rename = "type")]
+    pub card_type: Option<u8>,
+}
+
+/// The ULTRASND and ULTRADIR variables for a Gravis Ultrasound.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Ultrasnd {
+    /// The base I/O port, usually 0x240.
+    pub port: u16,
+    pub dma: u8,
+    pub record_dma: u8,
+    pub irq: u8,
+    pub midi_irq: u8,
+    /// Where the Ultrasound software and patches are, for ULTRADIR.
+    pub directory: Option<String>,
+}
+
+/// The game to start at the end: change to its directory and run the command.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Launch {
+    pub directory: String,
+    pub command: String,
+}
+
+/// Something in an AUTOEXEC.BAT that won't work with the files on the boot volume.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AutoexecProblem {
+    /// A program to load or launch isn't where DOS would look for it. DOS only finds files
+    /// by their 8.3 names.
+    MissingProgram(String),
+    /// A directory to change to or to keep files in isn't on the volume.
+    MissingDirectory(String),
+    /// A line that should start a program is empty.
+    NoCommand,
+    /// A section for a boot profile that CONFIG.SYS doesn't have, so it never runs.
+    UnknownProfile(String),
+}
+
+impl fmt::Display for AutoexecProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AutoexecProblem::MissingProgram(command) => {
+                write!(f, "{}: no such program on the boot volume", command)
+            }
+            AutoexecProblem::MissingDirectory(path) => {
+                write!(f, "{}: no such directory on the boot volume", path)
+            }
+            AutoexecProblem::NoCommand => write!(f, "a program to start has no command"),
+            AutoexecProblem::UnknownProfile(name) => {
+                write!(f, ":{}: CONFIG.SYS has no profile by that name", name)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Blaster {
+    /// Like A220 I5 D1 H5 P330 T6, the way the Sound Blaster setup writes it.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "A{:X} I{} D{}", self.address, self.irq, self.dma)?;
+        if let Some(channel) = self.high_dma {
+            write!(f, " H{}", channel)?;
+        }
+        if let Some(port) = self.mpu {
+            write!(f, " P{:X}", port)?;
+        }
+        if let Some(card_type) = self.card_type {
+            write!(f, " T{}", card_type)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Ultrasnd {
+    /// Like 240,1,1,11,7, the order the Ultrasound setup writes them in.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:X},{},{},{},{}",
+            self.port, self.dma, self.record_dma, self.irq, self.midi_irq
+        )
+    }
+}
+
+impl AutoexecBat {
+    pub fn new() -> AutoexecBat {
+        AutoexecBat::default()
+    }
+
+    /// The lines of the file, without line endings.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if !self.echo {
+            lines.push("@ECHO OFF".to_string());
+        }
+        if let Some(prompt) = &self.prompt {
+            lines.push(format!("PROMPT {}", prompt));
+        }
+        if !self.path.is_empty() {
+            lines.push(format!("PATH {}", self.path.join(";")));
+        }
+        if let Some(temp) = &self.temp {
+            lines.push(format!("SET TEMP={}", temp));
+        }
+        if let Some(blaster) = &self.blaster {
+            lines.push(format!("SET BLASTER={}", blaster));
+        }
+        if let Some(ultrasnd) = &self.ultrasnd {
+            lines.push(format!("SET ULTRASND={}", ultrasnd));
+            if let Some(directory) = &ultrasnd.directory {
+                lines.push(format!("SET ULTRADIR={}", directory));
+            }
+        }
+        AutoexecBat::push_commands(&mut lines, &self.set, &self.loadhigh, &self.commands);
+        if !self.profiles.is_empty() {
+            lines.push("GOTO %CONFIG%".to_string());
+            for section in &self.profiles {
+                lines.push(String::new());
+                lines.push(format!(":{}", section.name));
+                AutoexecBat::push_commands(
+                    &mut lines,
+                    &section.set,
+                    &section.loadhigh,
+                    &section.commands,
+                );
+                lines.push("GOTO END".to_string());
+            }
+            lines.push(String::new());
+            lines.push(":END".to_string());
+        }
+        if let Some(launch) = &self.launch {
+            // CD doesn't switch drives, that takes a line of its own.
+            if let Some((drive, _)) = launch.directory.split_once(':') {
+                lines.push(format!("{}:", drive.to_ascii_uppercase()));
+            }
+            lines.push(format!("CD {}", launch.directory));
+            lines.push(launch.command.clone());
+        }
+        lines
+    }
+
+    fn push_commands(
+        lines: &mut Vec<String>,
+        set: &BTreeMap<String, String>,
+        loadhigh: &[String],
+        commands: &[String],
+    ) {
+        for (name, value) in set {
+            lines.push(format!("SET {}={}", name.to_ascii_uppercase(), value));
+        }
+        for command in loadhigh {
+            lines.push(format!("LH {}", command));
+        }
+        lines.extend(commands.iter().cloned());
+    }
+
+    /// A copy with a section for every profile of the CONFIG.SYS, in the order of its menu,
+    /// so GOTO %CONFIG% always finds its label. Profiles without a section get an empty one,
+    /// and sections for profiles CONFIG.SYS doesn't have go at the end. Without any sections
+    /// there's nothing to jump to and the copy is the same.
+    pub fn for_menu(&self, config: &ConfigSys) -> AutoexecBat {
+        let mut autoexec = self.clone();
+        if self.profiles.is_empty() {
+            return autoexec;
+        }
+        let mut sections = self.profiles.clone();
+        autoexec.profiles = config
+            .profile_names()
+            .into_iter()
+            .map(|name| {
+                match sections
+                    .iter()
+                    .position(|section| section.name.eq_ignore_ascii_case(name))
+                {
+                    Some(index) => sections.remove(index),
+                    None => ProfileSection {
+                        name: name.to_string(),
+                        ..ProfileSection::default()
+                    },
+                }
+            })
+            .collect();
+        autoexec.profiles.append(&mut sections);
+        autoexec
+    }
+
+    /// The sections that don't belong to any profile of the CONFIG.SYS.
+    pub fn check_menu(&self, config: &ConfigSys) -> Vec<AutoexecProblem> {
+        let names = config.profile_names();
+        self.profiles
+            .iter()
+            .filter(|section| {
+                !names
+                    .iter()
+                    .any(|name| section.name.eq_ignore_ascii_case(name))
+            })
+            .map(|section| AutoexecProblem::UnknownProfile(section.name.clone()))
+            .collect()
+    }
+
+    /// The contents of the file: CR LF line endings and a Ctrl-Z end of file marker, the way
+    /// COPY CON leaves it.
+    pub fn render(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for line in self.lines() {
+            data.extend_from_slice(line.as_bytes());
+            data.extend_from_slice(b"\r\n");
+        }
+        data.push(0x1A);
+        data
+    }
+
+    /// Write the file to the root directory of the boot volume.
+    pub fn install(&self, volume: &mut Volume) -> Result<DirEntry, FsError> {
+        volume.add_file("", File::new("AUTOEXEC.BAT".to_string(), self.render()))
+    }
+
+    /// Check that every program to load and the game to launch can be found, and that the
+    /// directories exist. Do this once everything has been copied onto the volume. Paths on
+    /// other drives than C: are left alone, those can be a CD-ROM or a RAM disk that only
+    /// exists once the machine is running. An empty list means the batch file will run.
+    pub fn validate(&self, volume: &Volume) -> Vec<AutoexecProblem> {
+        let mut problems = Vec::new();
+        let mut directories: Vec<&str> = self.path.iter().map(String::as_str).collect();
+        directories.extend(self.temp.as_deref());
+        directories.extend(
+            self.ultrasnd
+                .as_ref()
+                .and_then(|gus| gus.directory.as_deref()),
+        );
+        for directory in directories {
+            if !matches!(
+                find_on_boot_drive(volume, directory),
+                BootPath::Directory | BootPath::OtherDrive
+            ) {
+                problems.push(AutoexecProblem::MissingDirectory(directory.to_string()));
+            }
+        }
+        let sections = self.profiles.iter().flat_map(|section| &section.loadhigh);
+        for command in self.loadhigh.iter().chain(sections) {
+            problems.extend(self.check_program(volume, "", command));
+        }
+        if let Some(launch) = &self.launch {
+            match find_on_boot_drive(volume, &launch.directory) {
+                BootPath::Directory => {
+                    problems.extend(self.check_program(volume, &launch.directory, &launch.command))
+                }
+                BootPath::OtherDrive => {}
+                BootPath::File | BootPath::Missing => {
+                    problems.push(AutoexecProblem::MissingDirectory(launch.directory.clone()))
+                }
+            }
+        }
+        problems
+    }
+
+    /// Look for the program a command line starts, the way COMMAND.COM does: a program with
+    /// a path as it's written, without one in the current directory and then on the PATH.
+    /// Without an extension, .COM, .EXE and .BAT are tried. With a directory on another
+    /// drive on the PATH, a program that isn't on the boot volume might well be there.
+    fn check_program(
+        &self,
+        volume: &Volume,
+        current: &str,
+        command: &str,
+    ) -> Option<AutoexecProblem> {
+        let program = match command.split_whitespace().next() {
+            Some(program) => program,
+            None => return Some(AutoexecProblem::NoCommand),
+        };
+        let mut directories = vec![current];
+        if !program.contains(['\\', '/', ':']) {
+            directories.extend(self.path.iter().map(String::as_str));
+        }
+        let name = program.rsplit(['\\', '/', ':']).next().unwrap_or(program);
+        let candidates: Vec<String> = if name.contains('.') {
+            vec![program.to_string()]
+        } else {
+            EXTENSIONS
+                .iter()
+                .map(|extension| format!("{}.{}", program, extension))
+                .collect()
+        };
+        for directory in directories {
+            for candidate in &candidates {
+                let (drive, rest) = match candidate.split_once(':') {
+                    Some((drive, rest)) => (format!("{}:", drive), rest),
+                    None => (String::new(), candidate.as_str()),
+                };
+                let path = if rest.starts_with(['\\', '/']) {
+                    candidate.clone()
+                } else {
+                    format!("{}{}", drive, Volume::join_path(directory, rest))
+                };
+                match find_on_boot_drive(volume, &path) {
+                    BootPath::File | BootPath::OtherDrive => return None,
+                    BootPath::Directory | BootPath::Missing => {}
+                }
+            }
+        }
+        Some(AutoexecProblem::MissingProgram(program.to_string()))
+    }
+}
@@ -0,0 +1,562 @@
+use crate::fs::{DirEntry, File, FsError, Volume};
+use crate::os::{find_on_boot_drive, BootPath};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{Error, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+
+/// The most items a startup menu can have.
+const MAX_PROFILES: usize = 9;
+/// The longest a block name or the text of a menu item can be.
+const MAX_MENU_TEXT: usize = 70;
+/// Names that CONFIG.SYS uses for its own blocks, and the label the AUTOEXEC.BAT that goes
+/// with a menu jumps to at the end.
+const RESERVED_NAMES: [&str; 3] = ["MENU", "COMMON", "END"];
+
+/// A CONFIG.SYS put together from directives instead of written by hand. The directives end
+/// up in the file in the order they're in here, which matters: HIMEM.SYS has to be loaded
+/// before EMM386.EXE, and that one before anything gets loaded high. In a manifest it's a
+/// list of single-key tables, one per line of the file:
+///
+/// ```toml
+/// config_sys = [
+///     { device = 'C:\DOS\HIMEM.SYS' },
+///     { device = 'C:\DOS\EMM386.EXE NOEMS' },
+///     { dos = ["high", "umb"] },
+///     { files = 30 },
+///     { buffers = 20 },
+///     { devicehigh = 'C:\DOS\ANSI.SYS' },
+///     { country = { code = 31, codepage = 437, file = 'C:\DOS\COUNTRY.SYS' } },
+///     { shell = 'C:\COMMAND.COM C:\ /E:1024 /P' },
+/// ]
+/// ```
+///
+/// Games that need different memory setups get boot profiles, which MS-DOS 6.22 offers as
+/// a startup menu. The directives above go into a [COMMON] block every profile gets, and
+/// each profile adds its own block. The name of the chosen profile ends up in %CONFIG%,
+/// which AUTOEXEC.BAT can jump on, see AutoexecBat. In a manifest that's a table:
+///
+/// ```toml
+/// [config_sys]
+/// common = [{ device = 'C:\DOS\HIMEM.SYS' }, { files = 30 }]
+/// default = "EMS"
+/// timeout = 5
+///
+/// [[config_sys.profile]]
+/// name = "EMS"
+/// title = "Expanded memory for Wing Commander"
+/// directives = [{ device = 'C:\DOS\EMM386.EXE RAM' }]
+///
+/// [[config_sys.profile]]
+/// name = "XMS"
+/// title = "Extended memory only"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigSys {
+    /// Everything when there are no profiles, what they have in common when there are.
+    pub directives: Vec<Directive>,
+    /// The entries of the startup menu, in the order they're shown.
+    pub profiles: Vec<BootProfile>,
+    /// The profile that's picked when nobody chooses, the first one if this isn't set.
+    pub default: Option<String>,
+    /// Seconds to wait for a choice before the default gets picked, 0 to 90. Without it the
+    /// menu waits forever.
+    pub timeout: Option<u8>,
+}
+
+/// One entry of the startup menu and the block of CONFIG.SYS that goes with it.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BootProfile {
+    /// The name of the block, which is what %CONFIG% gets set to. No spaces, and only the
+    /// first eight characters count for a label in AUTOEXEC.BAT.
+    pub name: String,
+    /// The text in the menu, the name is shown if there isn't one.
+    pub title: Option<String>,
+    #[serde(default)]
+    pub directives: Vec<Directive>,
+}
+
+/// One line of CONFIG.SYS. Drivers, the shell and programs to install are given as the
+/// command line DOS gets to see: the path of the file, then its arguments.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Directive {
+    /// Load a device driver.
+    Device(String),
+    /// Load a device driver into upper memory if there's room, conventional memory if not.
+    /// The command line may start with the /L and /S switches or SIZE= before the path.
+    DeviceHigh(String),
+    /// Where DOS loads itself and whether it manages upper memory blocks.
+    Dos(Vec<DosOption>),
+    /// The number of files that can be open at the same time, 8 to 255.
+    Files(u8),
+    /// The number of disk buffers, 1 to 99.
+    Buffers(u8),
+    /// The last drive letter DOS makes room for.
+    LastDrive(char),
+    /// The command interpreter and its arguments.
+    Shell(String),
+    /// Run a memory resident program while CONFIG.SYS is processed, before the shell starts.
+    Install(String),
+    /// The number of hardware interrupt stacks and the size of each, 0 or 8 to 64 stacks of
+    /// 0 or 32 to 512 bytes.
+    Stacks(u8, u16),
+    /// Country specific formats for dates, times and currency.
+    Country(Country),
+}
+
+/// The values of DOS=, which combine like in DOS=HIGH,UMB.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DosOption {
+    High,
+    Low,
+    Umb,
+    NoUmb,
+}
+
+/// The values of COUNTRY=. Without a file, DOS looks for COUNTRY.SYS in the root directory.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Country {
+    /// The international dialling code, 1 for the United States, 31 for the Netherlands.
+    pub code: u16,
+    pub codepage: Option<u16>,
+    pub file: Option<String>,
+}
+
+/// Something in a CONFIG.SYS that DOS would trip over when booting the container.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigProblem {
+    /// A directive points at a file that isn't on the boot volume, or isn't reachable by its
+    /// 8.3 name, which is all DOS knows about at this point.
+    MissingFile {
+        directive: &'static str,
+        path: String,
+    },
+    /// A directive points at a file on another drive than the boot drive, which doesn't
+    /// exist yet while CONFIG.SYS is processed.
+    OtherDrive {
+        directive: &'static str,
+        path: String,
+    },
+    /// A directive that needs a file doesn't name one.
+    NoFile(&'static str),
+    /// A number DOS won't accept for this directive.
+    OutOfRange { directive: &'static str, value: u16 },
+    /// LASTDRIVE has to be a letter.
+    InvalidDrive(char),
+    /// A profile name that's empty, too long, has characters a block name can't have, or
+    /// is one of MENU, COMMON and END. Also a menu text that's too long.
+    InvalidProfileName(String),
+    /// Two profiles that would end up at the same label in AUTOEXEC.BAT, which only looks
+    /// at the first eight characters without minding case.
+    DuplicateProfile(String),
+    /// The default profile isn't one of the profiles.
+    UnknownDefault(String),
+    /// More profiles than fit in the menu.
+    TooManyProfiles(usize),
+    /// DOS=HIGH and DOS=LOW, or DOS=UMB and DOS=NOUMB at the same time.
+    Conflicting(DosOption, DosOption),
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigProblem::MissingFile { directive, path } => {
+                write!(f, "{}: {} is not on the boot volume", directive, path)
+            }
+            ConfigProblem::OtherDrive { directive, path } => {
+                write!(f, "{}: {} is not on the boot drive", directive, path)
+            }
+            ConfigProblem::NoFile(directive) => write!(f, "{}: no file given", directive),
+            ConfigProblem::OutOfRange { directive, value } => {
+                write!(f, "{}: {} is out of range", directive, value)
+            }
+            ConfigProblem::InvalidDrive(drive) => {
+                write!(f, "LASTDRIVE: {} is not a drive letter", drive)
+            }
+            ConfigProblem::InvalidProfileName(name) => {
+                write!(f, "[MENU]: {} can't be used as a menu item", name)
+            }
+            ConfigProblem::DuplicateProfile(name) => {
+                write!(f, "[MENU]: {} is too much like another profile", name)
+            }
+            ConfigProblem::UnknownDefault(name) => {
+                write!(f, "MENUDEFAULT: there's no profile called {}", name)
+            }
+            ConfigProblem::TooManyProfiles(count) => {
+                write!(f, "[MENU]: {} profiles, only {} fit", count, MAX_PROFILES)
+            }
+            ConfigProblem::Conflicting(first, second) => write!(
+                f,
+                "DOS: {} and {} can't be used together",
+                first.keyword(),
+                second.keyword()
+            ),
+        }
+    }
+}
+
+impl DosOption {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            DosOption::High => "HIGH",
+            DosOption::Low => "LOW",
+            DosOption::Umb => "UMB",
+            DosOption::NoUmb => "NOUMB",
+        }
+    }
+}
+
+impl Directive {
+    /// The name of the directive as it's written in CONFIG.SYS.
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Directive::Device(_) => "DEVICE",
+            Directive::DeviceHigh(_) => "DEVICEHIGH",
+            Directive::Dos(_) => "DOS",
+            Directive::Files(_) => "FILES",
+            Directive::Buffers(_) => "BUFFERS",
+            Directive::LastDrive(_) => "LASTDRIVE",
+            Directive::Shell(_) => "SHELL",
+            Directive::Install(_) => "INSTALL",
+            Directive::Stacks(_, _) => "STACKS",
+            Directive::Country(_) => "COUNTRY",
+        }
+    }
+
+    /// The file this directive loads, as written. DEVICEHIGH can have switches for the
+    /// memory region before the path, those are skipped.
+    pub fn file(&self) -> Option<&str> {
+        let command = match self {
+            Directive::Device(command)
+            | Directive::Shell(command)
+            | Directive::Install(command) => command,
+            Directive::DeviceHigh(command) => {
+                return command.split_whitespace().find(|word| {
+                    !word.starts_with('/') && !word.to_ascii_uppercase().starts_with("SIZE=")
+                })
+            }
+            Directive::Country(country) => return country.file.as_deref(),
+            _ => return None,
+        };
+        command.split_whitespace().next()
+    }
+}
+
+impl<'de> Deserialize<'de> for ConfigSys {
+    /// A list of directives for a plain CONFIG.SYS, or a table with the common directives
+    /// and the profiles for one with a menu.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Menu {
+            #[serde(default)]
+            common: Vec<Directive>,
+            #[serde(default, rename = "profile")]
+            profiles: Vec<BootProfile>,
+            default: Option<String>,
+            timeout: Option<u8>,
+        }
+
+        struct ConfigVisitor;
+
+        impl<'de> Visitor<'de> for ConfigVisitor {
+            type Value = ConfigSys;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a list of directives or a table with profiles")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<ConfigSys, A::Error> {
+                Ok(ConfigSys {
+                    directives: Vec::deserialize(SeqAccessDeserializer::new(seq))?,
+                    ..ConfigSys::default()
+                })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<ConfigSys, A::Error> {
+                let menu = Menu::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(ConfigSys {
+                    directives: menu.common,
+                    profiles: menu.profiles,
+                    default: menu.default,
+                    timeout: menu.timeout,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(ConfigVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for Directive {
+    /// A table with a single key, the name of the directive in lowercase. This reads the
+    /// table as a struct instead of letting serde deal with the enum, because the TOML
+    /// values that StartupFile goes through only know about enums without data.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Written {
+            device: Option<String>,
+            devicehigh: Option<String>,
+            dos: Option<Vec<DosOption>>,
+            files: Option<u8>,
+            buffers: Option<u8>,
+            lastdrive: Option<char>,
+            shell: Option<String>,
+            install: Option<String>,
+            stacks: Option<(u8, u16)>,
+            country: Option<Country>,
+        }
+        let written = Written::deserialize(deserializer)?;
+        let directives: Vec<Directive> = [
+            written.device.map(Directive::Device),
+            written.devicehigh.map(Directive::DeviceHigh),
+            written.dos.map(Directive::Dos),
+            written.files.map(Directive::Files),
+            written.buffers.map(Directive::Buffers),
+            written.lastdrive.map(Directive::LastDrive),
+            written.shell.map(Directive::Shell),
+            written.install.map(Directive::Install),
+            written
+                .stacks
+                .map(|(count, size)| Directive::Stacks(count, size)),
+            written.country.map(Directive::Country),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        match <[Directive; 1]>::try_from(directives) {
+            Ok([directive]) => Ok(directive),
+            Err(directives) => Err(D::Error::custom(format!(
+                "a CONFIG.SYS line needs one directive, not {}",
+                directives.len()
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}=", self.keyword())?;
+        match self {
+            Directive::Device(command)
+            | Directive::DeviceHigh(command)
+            | Directive::Shell(command)
+            | Directive::Install(command) => write!(f, "{}", command),
+            Directive::Dos(options) => {
+                let keywords: Vec<&str> = options.iter().map(DosOption::keyword).collect();
+                write!(f, "{}", keywords.join(","))
+            }
+            Directive::Files(count) | Directive::Buffers(count) => write!(f, "{}", count),
+            Directive::LastDrive(drive) => write!(f, "{}", drive.to_ascii_uppercase()),
+            Directive::Stacks(count, size) => write!(f, "{},{}", count, size),
+            // The country code always has three digits in the DOS manual, like 031.
+            Directive::Country(country) => {
+                write!(f, "{:03}", country.code)?;
+                match (&country.codepage, &country.file) {
+                    (Some(codepage), Some(file)) => write!(f, ",{},{}", codepage, file),
+                    (Some(codepage), None) => write!(f, ",{}", codepage),
+                    (None, Some(file)) => write!(f, ",,{}", file),
+                    (None, None) => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl ConfigSys {
+    pub fn new() -> ConfigSys {
+        ConfigSys::default()
+    }
+
+    /// Add a directive after the ones that are there already.
+    pub fn push(&mut self, directive: Directive) -> &mut ConfigSys {
+        self.directives.push(directive);
+        self
+    }
+
+    /// The names of the profiles, in menu order.
+    pub fn profile_names(&self) -> Vec<&str> {
+        self.profiles
+            .iter()
+            .map(|profile| profile.name.as_str())
+            .collect()
+    }
+
+    /// The lines of the file, without line endings. With profiles, that's a [MENU] block,
+    /// the [COMMON] block, a block for every profile, and an empty [COMMON] block at the end
+    /// for installers that append their drivers to CONFIG.SYS, the way MS-DOS recommends.
+    pub fn lines(&self) -> Vec<String> {
+        if self.profiles.is_empty() {
+            return self.directives.iter().map(Directive::to_string).collect();
+        }
+        let mut lines = vec!["[MENU]".to_string()];
+        for profile in &self.profiles {
+            lines.push(match &profile.title {
+                Some(title) => format!("MENUITEM={},{}", profile.name, title),
+                None => format!("MENUITEM={}", profile.name),
+            });
+        }
+        let default = self.default.as_deref().unwrap_or(&self.profiles[0].name);
+        match self.timeout {
+            Some(timeout) => lines.push(format!("MENUDEFAULT={},{}", default, timeout)),
+            None => lines.push(format!("MENUDEFAULT={}", default)),
+        }
+        let blocks = std::iter::once(("COMMON", &self.directives)).chain(
+            self.profiles
+                .iter()
+                .map(|profile| (profile.name.as_str(), &profile.directives)),
+        );
+        for (name, directives) in blocks {
+            lines.push(String::new());
+            lines.push(format!("[{}]", name));
+            lines.extend(directives.iter().map(Directive::to_string));
+        }
+        lines.push(String::new());
+        lines.push("[COMMON]".to_string());
+        lines
+    }
+
+    /// The contents of the file: one line per directive, with CR LF line endings.
+    pub fn render(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for line in self.lines() {
+            data.extend_from_slice(line.as_bytes());
+            data.extend_from_slice(b"\r\n");
+        }
+        data
+    }
+
+    /// Write the file to the root directory of the boot volume. Do this right after the
+    /// system files, so it ends up next to them like it would after SETUP.
+    pub fn install(&self, volume: &mut Volume) -> Result<DirEntry, FsError> {
+        volume.add_file("", File::new("CONFIG.SYS".to_string(), self.render()))
+    }
+
+    /// Check every directive against what DOS accepts and against the volume: every driver,
+    /// shell, program and country file has to be on it. Profiles need names that work as
+    /// menu items and as labels in AUTOEXEC.BAT. Do this once everything has been copied
+    /// onto the volume. An empty list means DOS will be happy with it.
+    pub fn validate(&self, volume: &Volume) -> Vec<ConfigProblem> {
+        let mut problems = self.check_menu();
+        let profiles = self.profiles.iter().flat_map(|profile| &profile.directives);
+        for directive in self.directives.iter().chain(profiles) {
+            let keyword = directive.keyword();
+            let out_of_range = |value: u16| ConfigProblem::OutOfRange {
+                directive: keyword,
+                value,
+            };
+            match directive {
+                Directive::Files(count) if *count < 8 => {
+                    problems.push(out_of_range(u16::from(*count)))
+                }
+                Directive::Buffers(count) if !(1..=99).contains(count) => {
+                    problems.push(out_of_range(u16::from(*count)))
+                }
+                Directive::Stacks(count, size) => {
+                    if *count != 0 && !(8..=64).contains(count) {
+                        problems.push(out_of_range(u16::from(*count)));
+                    }
+                    if *size != 0 && !(32..=512).contains(size) {
+                        problems.push(out_of_range(*size));
+                    }
+                }
+                Directive::LastDrive(drive) if !drive.is_ascii_alphabetic() => {
+                    problems.push(ConfigProblem::InvalidDrive(*drive))
+                }
+                Directive::Dos(options) => {
+                    for (first, second) in [
+                        (DosOption::High, DosOption::Low),
+                        (DosOption::Umb, DosOption::NoUmb),
+                    ] {
+                        if options.contains(&first) && options.contains(&second) {
+                            problems.push(ConfigProblem::Conflicting(first, second));
+                        }
+                    }
+                }
+                Directive::Country(country) if !(1..=999).contains(&country.code) => {
+                    problems.push(out_of_range(country.code))
+                }
+                _ => {}
+            }
+
+            let file = match directive {
+                // The United States are built into DOS, every other country needs the file.
+                Directive::Country(country) if country.file.is_none() && country.code != 1 => {
+                    Some("\\COUNTRY.SYS")
+                }
+                _ => directive.file(),
+            };
+            match file {
+                Some(path) => problems.extend(ConfigSys::check_file(volume, keyword, path)),
+                None if matches!(
+                    directive,
+                    Directive::Device(_)
+                        | Directive::DeviceHigh(_)
+                        | Directive::Shell(_)
+                        | Directive::Install(_)
+                ) =>
+                {
+                    problems.push(ConfigProblem::NoFile(keyword))
+                }
+                None => {}
+            }
+        }
+        problems
+    }
+
+    /// The problems with the profiles and the menu they make.
+    fn check_menu(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+        if self.profiles.len() > MAX_PROFILES {
+            problems.push(ConfigProblem::TooManyProfiles(self.profiles.len()));
+        }
+        let mut labels: Vec<String> = Vec::new();
+        for profile in &self.profiles {
+            let name = &profile.name;
+            let label: String = name.to_ascii_uppercase().chars().take(8).collect();
+            if name.is_empty()
+                || name.len() > MAX_MENU_TEXT
+                || name.contains([' ', '\\', '/', ',', ';', '=', '[', ']'])
+                || RESERVED_NAMES.contains(&name.to_ascii_uppercase().as_str())
+                || profile
+                    .title
+                    .as_ref()
+                    .is_some_and(|title| title.len() > MAX_MENU_TEXT)
+            {
+                problems.push(ConfigProblem::InvalidProfileName(name.clone()));
+            } else if labels.contains(&label) {
+                problems.push(ConfigProblem::DuplicateProfile(name.clone()));
+            }
+            labels.push(label);
+        }
+        if let Some(default) = &self.default {
+            if !self.profiles.iter().any(|profile| &profile.name == default) {
+                problems.push(ConfigProblem::UnknownDefault(default.clone()));
+            }
+        }
+        if let Some(timeout) = self.timeout {
+            if timeout > 90 {
+                problems.push(ConfigProblem::OutOfRange {
+                    directive: "MENUDEFAULT",
+                    value: u16::from(timeout),
+                });
+            }
+        }
+        problems
+    }
+
+    fn check_file(volume: &Volume, directive: &'static str, path: &str) -> Option<ConfigProblem> {
+        let path = path.to_string();
+        match find_on_boot_drive(volume, &path) {
+            BootPath::File => None,
+            BootPath::OtherDrive => Some(ConfigProblem::OtherDrive { directive, path }),
+            BootPath::Directory | BootPath::Missing => {
+                Some(ConfigProblem::MissingFile { directive, path })
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::fs::Volume;
+
+pub use crate::os::autoexec::{
+    AutoexecBat, AutoexecProblem, Blaster, Launch, ProfileSection, Ultrasnd,
+};
+pub use crate::os::config::{BootProfile, ConfigProblem, ConfigSys, Country, Directive, DosOption};
+
+mod autoexec;
+mod config;
+
+#[cfg(test)]
+mod tests;
+
+/// An operating system that can be put on a container. For now that's just the system files
+/// it needs on the boot volume, in the order they have to go into the root directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OsProfile {
+    name: &'static str,
+    system_files: Vec<(&'static str, &'static [u8])>,
+}
+
+impl OsProfile {
+    /// MS-DOS 6.22, as installed by SYS.COM. IO.SYS and MSDOS.SYS have to be the first two
+    /// entries in the root directory or the boot code won't find them.
+    pub fn msdos622() -> OsProfile {
+        OsProfile {
+            name: "MS-DOS 6.22",
+            system_files: vec![
+                ("IO.SYS", include_bytes!("IO.SYS")),
+                ("MSDOS.SYS", include_bytes!("MSDOS.SYS")),
+                ("COMMAND.COM", include_bytes!("COMMAND.COM")),
+                ("DRVSPACE.BIN", include_bytes!("DRVSPACE.BIN")),
+            ],
+        }
+    }
+
+    /// No operating system at all, for data-only containers.
+    pub fn none() -> OsProfile {
+        OsProfile {
+            name: "None",
+            system_files: Vec::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        self.name
+    }
+
+    /// Names and contents of the system files, in installation order.
+    pub fn get_system_files(&self) -> &[(&'static str, &'static [u8])] {
+        &self.system_files
+    }
+}
+
+/// What a path in CONFIG.SYS or AUTOEXEC.BAT leads to on the boot volume.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum BootPath {
+    File,
+    Directory,
+    Missing,
+    /// The path is on another drive than C:, the volume can't say anything about it.
+    OtherDrive,
+}
+
+/// Look up a path the way DOS does on the boot drive: on C: or without a drive, starting
+/// from the root directory, and by 8.3 names only since DOS knows nothing about long names.
+pub(crate) fn find_on_boot_drive(volume: &Volume, path: &str) -> BootPath {
+    if let Some((drive, _)) = path.split_once(':') {
+        if !drive.eq_ignore_ascii_case("C") {
+            return BootPath::OtherDrive;
+        }
+    }
+    let mut current = String::new();
+    let mut found = BootPath::Directory;
+    for component in Volume::split_path(path) {
+        current = Volume::join_path(&current, component);
+        found = match volume.get_entry(&current) {
+            Ok(entry) if !entry.get_short_name().eq_ignore_ascii_case(component) => {
+                return BootPath::Missing
+            }
+            Ok(entry) if entry.is_dir() => BootPath::Directory,
+            Ok(_) => BootPath::File,
+            Err(_) => return BootPath::Missing,
+        };
+    }
+    found
+}
@@ -0,0 +1,425 @@
+use crate::fs::File;
+use crate::fs::Volume;
+use crate::os::{
+    AutoexecBat, AutoexecProblem, Blaster, BootProfile, ConfigProblem, ConfigSys, Country,
+    Directive, DosOption, Launch, ProfileSection, Ultrasnd,
+};
+
+fn config(directives: Vec<Directive>) -> ConfigSys {
+    ConfigSys {
+        directives,
+        ..ConfigSys::default()
+    }
+}
+
+#[test]
+fn render_config_sys() {
+    let mut config = ConfigSys::new();
+    config
+        .push(Directive::Device("C:\\DOS\\HIMEM.SYS".to_string()))
+        .push(Directive::Dos(vec![DosOption::High, DosOption::Umb]))
+        .push(Directive::Files(30))
+        .push(Directive::Buffers(20))
+        .push(Directive::LastDrive('z'))
+        .push(Directive::Stacks(9, 256))
+        .push(Directive::Country(Country {
+            code: 31,
+            codepage: Some(437),
+            file: Some("C:\\DOS\\COUNTRY.SYS".to_string()),
+        }))
+        .push(Directive::Country(Country {
+            code: 49,
+            codepage: None,
+            file: Some("C:\\DOS\\COUNTRY.SYS".to_string()),
+        }))
+        .push(Directive::Shell(
+            "C:\\COMMAND.COM C:\\ /E:1024 /P".to_string(),
+        ));
+    assert_eq!(
+        String::from_utf8(config.render()).unwrap(),
+        "DEVICE=C:\\DOS\\HIMEM.SYS\r\n\
+         DOS=HIGH,UMB\r\n\
+         FILES=30\r\n\
+         BUFFERS=20\r\n\
+         LASTDRIVE=Z\r\n\
+         STACKS=9,256\r\n\
+         COUNTRY=031,437,C:\\DOS\\COUNTRY.SYS\r\n\
+         COUNTRY=049,,C:\\DOS\\COUNTRY.SYS\r\n\
+         SHELL=C:\\COMMAND.COM C:\\ /E:1024 /P\r\n"
+    );
+    assert_eq!(ConfigSys::new().render(), b"");
+
+    let mut volume = Volume::new(94532);
+    volume
+        .add_file("", File::new("IO.SYS".to_string(), vec![0; 10]))
+        .unwrap();
+    let entry = config.install(&mut volume).unwrap();
+    assert_eq!(entry.get_short_name(), "CONFIG.SYS");
+    assert_eq!(volume.read_file("CONFIG.SYS").unwrap(), config.render());
+}
+
+#[test]
+fn validate_config_sys() {
+    let mut volume = Volume::new(94532);
+    volume.set_long_names(true);
+    volume
+        .add_file("", File::new("COMMAND.COM".to_string(), vec![0; 10]))
+        .unwrap();
+    volume.create_dir("DOS").unwrap();
+    for name in ["HIMEM.SYS", "EMM386.EXE", "ANSI.SYS", "SHARE.EXE"] {
+        volume
+            .add_file("DOS", File::new(name.to_string(), vec![0; 10]))
+            .unwrap();
+    }
+    volume
+        .add_file("DOS", File::new("MouseDriver.sys".to_string(), vec![0; 10]))
+        .unwrap();
+
+    let working = config(vec![
+        Directive::Device("C:\\DOS\\HIMEM.SYS".to_string()),
+        Directive::Device("c:\\dos\\emm386.exe NOEMS".to_string()),
+        Directive::Dos(vec![DosOption::High, DosOption::Umb]),
+        Directive::DeviceHigh("/L:1,12048 \\DOS\\ANSI.SYS /X".to_string()),
+        Directive::Install("C:/DOS/SHARE.EXE".to_string()),
+        Directive::Stacks(0, 0),
+        Directive::Country(Country {
+            code: 1,
+            codepage: None,
+            file: None,
+        }),
+        Directive::Shell("COMMAND.COM /P".to_string()),
+    ]);
+    assert_eq!(working.validate(&volume), []);
+
+    let broken = config(vec![
+        Directive::Device("C:\\DOS\\MOUSE.SYS".to_string()),
+        Directive::Device("C:\\DOS\\MouseDriver.sys".to_string()),
+        Directive::DeviceHigh("D:\\CDROM.SYS".to_string()),
+        Directive::Install("C:\\DOS".to_string()),
+        Directive::Shell(" ".to_string()),
+        Directive::Files(4),
+        Directive::Buffers(100),
+        Directive::Stacks(4, 1024),
+        Directive::LastDrive('1'),
+        Directive::Dos(vec![DosOption::High, DosOption::Low, DosOption::Umb]),
+        Directive::Country(Country {
+            code: 31,
+            codepage: None,
+            file: None,
+        }),
+    ]);
+    let missing = |directive, path: &str| ConfigProblem::MissingFile {
+        directive,
+        path: path.to_string(),
+    };
+    let out_of_range = |directive, value| ConfigProblem::OutOfRange { directive, value };
+    assert_eq!(
+        broken.validate(&volume),
+        [
+            missing("DEVICE", "C:\\DOS\\MOUSE.SYS"),
+            // DOS only knows the short name, MOUSED~1.SYS.
+            missing("DEVICE", "C:\\DOS\\MouseDriver.sys"),
+            ConfigProblem::OtherDrive {
+                directive: "DEVICEHIGH",
+                path: "D:\\CDROM.SYS".to_string()
+            },
+            missing("INSTALL", "C:\\DOS"),
+            ConfigProblem::NoFile("SHELL"),
+            out_of_range("FILES", 4),
+            out_of_range("BUFFERS", 100),
+            out_of_range("STACKS", 4),
+            out_of_range("STACKS", 1024),
+            ConfigProblem::InvalidDrive('1'),
+            ConfigProblem::Conflicting(DosOption::High, DosOption::Low),
+            missing("COUNTRY", "\\COUNTRY.SYS"),
+        ]
+    );
+}
+
+#[test]
+fn render_autoexec_bat() {
+    let mut autoexec = AutoexecBat::new();
+    assert_eq!(autoexec.render(), b"@ECHO OFF\r\n\x1A");
+
+    autoexec.prompt = Some("$P$G".to_string());
+    autoexec.path = vec!["C:\\DOS".to_string(), "C:\\UTILS".to_string()];
+    autoexec.temp = Some("C:\\TEMP".to_string());
+    autoexec.blaster = Some(Blaster {
+        address: 0x220,
+        irq: 5,
+        dma: 1,
+        high_dma: Some(5),
+        mpu: Some(0x330),
+        card_type: Some(6),
+    });
+    autoexec.ultrasnd = Some(Ultrasnd {
+        port: 0x240,
+        dma: 1,
+        record_dma: 1,
+        irq: 11,
+        midi_irq: 7,
+        directory: Some("C:\\ULTRASND".to_string()),
+    });
+    autoexec
+        .set
+        .insert("midi".to_string(), "SYNTH:2 MAP:E".to_string());
+    autoexec.loadhigh = vec![
+        "MSCDEX /D:MSCD001".to_string(),
+        "C:\\DOS\\MOUSE.COM".to_string(),
+    ];
+    autoexec.commands = vec!["MODE CON RATE=32 DELAY=1".to_string()];
+    autoexec.launch = Some(Launch {
+        directory: "C:\\GAMES\\KEEN4".to_string(),
+        command: "KEEN4E /NOJOYS".to_string(),
+    });
+    let mut expected = "@ECHO OFF\r\n\
+                        PROMPT $P$G\r\n\
+                        PATH C:\\DOS;C:\\UTILS\r\n\
+                        SET TEMP=C:\\TEMP\r\n\
+                        SET BLASTER=A220 I5 D1 H5 P330 T6\r\n\
+                        SET ULTRASND=240,1,1,11,7\r\n\
+                        SET ULTRADIR=C:\\ULTRASND\r\n\
+                        SET MIDI=SYNTH:2 MAP:E\r\n\
+                        LH MSCDEX /D:MSCD001\r\n\
+                        LH C:\\DOS\\MOUSE.COM\r\n\
+                        MODE CON RATE=32 DELAY=1\r\n\
+                        C:\r\n\
+                        CD C:\\GAMES\\KEEN4\r\n\
+                        KEEN4E /NOJOYS\r\n"
+        .as_bytes()
+        .to_vec();
+    expected.push(0x1A);
+    assert_eq!(autoexec.render(), expected);
+
+    let mut volume = Volume::new(94532);
+    autoexec.install(&mut volume).unwrap();
+    assert_eq!(volume.read_file("AUTOEXEC.BAT").unwrap(), expected);
+}
+
+#[test]
+fn validate_autoexec_bat() {
+    let mut volume = Volume::new(94532);
+    volume.set_long_names(true);
+    for directory in ["DOS", "TEMP", "GAMES", "GAMES\\KEEN4"] {
+        volume.create_dir(directory).unwrap();
+    }
+    for (directory, name) in [
+        ("DOS", "MSCDEX.EXE"),
+        ("DOS", "MOUSE.COM"),
+        ("DOS", "KeyboardDriver.com"),
+        ("GAMES\\KEEN4", "KEEN4E.EXE"),
+        ("GAMES\\KEEN4", "START.BAT"),
+        ("", "SMARTDRV.EXE"),
+    ] {
+        volume
+            .add_file(directory, File::new(name.to_string(), vec![0; 10]))
+            .unwrap();
+    }
+
+    let mut autoexec = AutoexecBat::new();
+    autoexec.path = vec!["C:\\DOS".to_string(), "D:\\".to_string()];
+    autoexec.temp = Some("C:\\TEMP".to_string());
+    autoexec.loadhigh = vec![
+        "MSCDEX /D:MSCD001 /L:D".to_string(),
+        "mouse".to_string(),
+        "SMARTDRV.EXE 2048".to_string(),
+        "C:\\DOS\\MOUSE".to_string(),
+        "D:\\DRIVERS\\CDROM.EXE".to_string(),
+    ];
+    autoexec.launch = Some(Launch {
+        directory: "\\GAMES\\KEEN4".to_string(),
+        command: "KEEN4E".to_string(),
+    });
+    assert_eq!(autoexec.validate(&volume), []);
+    autoexec.launch = Some(Launch {
+        directory: "C:\\GAMES\\KEEN4".to_string(),
+        command: "START".to_string(),
+    });
+    assert_eq!(autoexec.validate(&volume), []);
+
+    // A program that isn't on C: could be on another drive on the PATH, so leave that out.
+    autoexec.path.pop();
+    autoexec.temp = Some("C:\\TMP".to_string());
+    autoexec.loadhigh = vec![
+        "KEYB US".to_string(),
+        // Only the long name is KeyboardDriver.com, DOS knows it as KEYBOA~1.COM.
+        "KeyboardDriver".to_string(),
+        "KEEN4E".to_string(),
+        " ".to_string(),
+    ];
+    autoexec.launch = Some(Launch {
+        directory: "C:\\GAMES\\KEEN5".to_string(),
+        command: "KEEN5E".to_string(),
+    });
+    assert_eq!(
+        autoexec.validate(&volume),
+        [
+            AutoexecProblem::MissingDirectory("C:\\TMP".to_string()),
+            AutoexecProblem::MissingProgram("KEYB".to_string()),
+            AutoexecProblem::MissingProgram("KeyboardDriver".to_string()),
+            AutoexecProblem::MissingProgram("KEEN4E".to_string()),
+            AutoexecProblem::NoCommand,
+            AutoexecProblem::MissingDirectory("C:\\GAMES\\KEEN5".to_string()),
+        ]
+    );
+}
+
+fn profile(name: &str, title: Option<&str>, directives: Vec<Directive>) -> BootProfile {
+    BootProfile {
+        name: name.to_string(),
+        title: title.map(str::to_string),
+        directives,
+    }
+}
+
+#[test]
+fn render_boot_menu() {
+    let mut config = config(vec![
+        Directive::Device("C:\\DOS\\HIMEM.SYS".to_string()),
+        Directive::Files(30),
+    ]);
+    config.profiles = vec![
+        profile(
+            "EMS",
+            Some("Expanded memory"),
+            vec![Directive::Device("C:\\DOS\\EMM386.EXE RAM".to_string())],
+        ),
+        profile("XMS", None, vec![]),
+        profile("CDROM", Some("Extended memory and CD-ROM"), vec![]),
+    ];
+    config.timeout = Some(5);
+    assert_eq!(config.profile_names(), ["EMS", "XMS", "CDROM"]);
+    assert_eq!(
+        String::from_utf8(config.render()).unwrap(),
+        "[MENU]\r\n\
+         MENUITEM=EMS,Expanded memory\r\n\
+         MENUITEM=XMS\r\n\
+         MENUITEM=CDROM,Extended memory and CD-ROM\r\n\
+         MENUDEFAULT=EMS,5\r\n\
+         \r\n\
+         [COMMON]\r\n\
+         DEVICE=C:\\DOS\\HIMEM.SYS\r\n\
+         FILES=30\r\n\
+         \r\n\
+         [EMS]\r\n\
+         DEVICE=C:\\DOS\\EMM386.EXE RAM\r\n\
+         \r\n\
+         [XMS]\r\n\
+         \r\n\
+         [CDROM]\r\n\
+         \r\n\
+         [COMMON]\r\n"
+    );
+    config.default = Some("XMS".to_string());
+    config.timeout = None;
+    assert!(config.lines().contains(&"MENUDEFAULT=XMS".to_string()));
+
+    // Without sections AUTOEXEC.BAT doesn't need to jump anywhere.
+    let mut autoexec = AutoexecBat::new();
+    autoexec.loadhigh = vec!["MOUSE".to_string()];
+    autoexec.launch = Some(Launch {
+        directory: "\\GAMES\\WC".to_string(),
+        command: "WC".to_string(),
+    });
+    assert_eq!(autoexec.for_menu(&config), autoexec);
+
+    autoexec.profiles = vec![
+        ProfileSection {
+            name: "cdrom".to_string(),
+            loadhigh: vec!["MSCDEX /D:MSCD001".to_string()],
+            ..ProfileSection::default()
+        },
+        ProfileSection {
+            name: "GUS".to_string(),
+            ..ProfileSection::default()
+        },
+    ];
+    let autoexec = autoexec.for_menu(&config);
+    let mut expected = "@ECHO OFF\r\n\
+                        LH MOUSE\r\n\
+                        GOTO %CONFIG%\r\n\
+                        \r\n\
+                        :EMS\r\n\
+                        GOTO END\r\n\
+                        \r\n\
+                        :XMS\r\n\
+                        GOTO END\r\n\
+                        \r\n\
+                        :cdrom\r\n\
+                        LH MSCDEX /D:MSCD001\r\n\
+                        GOTO END\r\n\
+                        \r\n\
+                        :GUS\r\n\
+                        GOTO END\r\n\
+                        \r\n\
+                        :END\r\n\
+                        CD \\GAMES\\WC\r\n\
+                        WC\r\n"
+        .as_bytes()
+        .to_vec();
+    expected.push(0x1A);
+    assert_eq!(autoexec.render(), expected);
+    assert_eq!(
+        autoexec.check_menu(&config),
+        [AutoexecProblem::UnknownProfile("GUS".to_string())]
+    );
+}
+
+#[test]
+fn validate_boot_menu() {
+    let mut volume = Volume::new(94532);
+    volume.create_dir("DOS").unwrap();
+    volume
+        .add_file("DOS", File::new("HIMEM.SYS".to_string(), vec![0; 10]))
+        .unwrap();
+    let mut menu = config(vec![Directive::Device("C:\\DOS\\HIMEM.SYS".to_string())]);
+    menu.profiles = vec![
+        profile("EMS", None, vec![]),
+        profile("XMS", Some("Extended memory"), vec![]),
+    ];
+    menu.default = Some("XMS".to_string());
+    menu.timeout = Some(90);
+    assert_eq!(menu.validate(&volume), []);
+
+    menu.profiles = vec![
+        profile(
+            "EMS",
+            None,
+            vec![Directive::Device("C:\\DOS\\EMM386.EXE".to_string())],
+        ),
+        profile("Two words", None, vec![]),
+        profile("common", None, vec![]),
+        profile("", None, vec![]),
+        profile("CDROMMSCDEX", None, vec![]),
+        profile("cdrommscd", None, vec![]),
+        profile("TOOLONG", Some(&"-".repeat(71)), vec![]),
+    ];
+    menu.timeout = Some(91);
+    let invalid = |name: &str| ConfigProblem::InvalidProfileName(name.to_string());
+    assert_eq!(
+        menu.validate(&volume),
+        [
+            invalid("Two words"),
+            invalid("common"),
+            invalid(""),
+            // AUTOEXEC.BAT would take both to the label :CDROMMSC.
+            ConfigProblem::DuplicateProfile("cdrommscd".to_string()),
+            invalid("TOOLONG"),
+            ConfigProblem::UnknownDefault("XMS".to_string()),
+            ConfigProblem::OutOfRange {
+                directive: "MENUDEFAULT",
+                value: 91
+            },
+            ConfigProblem::MissingFile {
+                directive: "DEVICE",
+                path: "C:\\DOS\\EMM386.EXE".to_string()
+            },
+        ]
+    );
+    menu.profiles = (1..=10)
+        .map(|number| profile(&format!("P{}", number), None, vec![]))
+        .collect();
+    menu.default = None;
+    menu.timeout = None;
+    assert_eq!(menu.validate(&volume), [ConfigProblem::TooManyProfiles(10)]);
+}
@@ -2,12 +2,13 @@ use crate::disk::chs::*;
 use crate::disk::*;
 use crate::fs::fat::FAT;
 use crate::fs::vbr::VBR;
+use crate::fs::{CheckReport, Codepage, FormatOptions, FsError, Problem, Volume};
 
 #[cfg(test)]
 mod tests;
 
 #[allow(non_snake_case)]
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Partition {
     pub(crate) offset: u16,
     pub(crate) flag_byte: u8,
@@ -19,6 +20,7 @@ pub struct Partition {
     pub(crate) last_lba: u32,
     pub(crate) boot_record: VBR,
     pub(crate) FAT: FAT,
+    pub(crate) volume: Option<Volume>,
 }
 
 impl Partition {
@@ -52,22 +54,113 @@ impl Partition {
             panic!("Can't have more than 4 partitions, starting at offset 1. You tried to create one at the wrong place.");
         }
 
+        let mut boot_record = VBR::for_size(requested_sectors);
+        boot_record.set_geometry(
+            u16::from(disk.geometry.sector),
+            u16::from(disk.geometry.head),
+        );
+
         // Compose the Partition struct and return it.
         let my_partition = Partition {
             offset: 0x1be,
             flag_byte: 0x80,
             first_sector: CHS::from_lba(&disk.geometry, start_sector),
-            partition_type: 0x06,
+            partition_type: boot_record.get_partition_type(),
             last_sector: last_chs,
             first_lba: start_sector,
             last_lba: last_lba,
             sector_count: requested_sectors,
-            boot_record: VBR::new(requested_sectors),
-            FAT: FAT::new(requested_sectors),
+            FAT: FAT::from_boot_record(&boot_record),
+            boot_record,
+            volume: None,
         };
         return my_partition;
     }
 
+    /// Put a fresh, empty FAT file system on this partition using its Volume Boot Record.
+    /// Formatting an already formatted partition throws away everything that was on it.
+    pub fn format(&mut self) -> &mut Volume {
+        self.volume.insert(Volume::format(self.boot_record.clone()))
+    }
+
+    /// Format this partition with a layout other than the default for its size. The boot
+    /// record and the FAT follow the options, the label and the geometry are kept. When the
+    /// options don't work for a partition of this size nothing changes and the reason comes
+    /// back.
+    pub fn format_with(&mut self, options: &FormatOptions) -> Result<&mut Volume, FsError> {
+        let mut boot_record = VBR::from_options(self.sector_count, options)?;
+        boot_record.set_volume_label(self.boot_record.get_volume_label());
+        boot_record.set_geometry(
+            self.boot_record.get_sectors_per_track(),
+            self.boot_record.get_heads_count(),
+        );
+        self.set_boot_record(boot_record);
+        Ok(self.format())
+    }
+
+    /// Switch to another boot record, with the FAT and the partition type to go with it.
+    pub(crate) fn set_boot_record(&mut self, boot_record: VBR) {
+        self.FAT = FAT::from_boot_record(&boot_record);
+        self.partition_type = boot_record.get_partition_type();
+        self.boot_record = boot_record;
+    }
+
+    /// The file system on this partition, if it has been formatted.
+    pub fn get_volume(&self) -> Option<&Volume> {
+        self.volume.as_ref()
+    }
+
+    /// The file system on this partition, if it has been formatted.
+    pub fn get_volume_mut(&mut self) -> Option<&mut Volume> {
+        self.volume.as_mut()
+    }
+
+    /// Set the volume label of this partition. Before formatting it ends up in the boot
+    /// record and format() writes it to the root directory, afterwards the volume gets it
+    /// directly.
+    pub fn set_label(&mut self, label: &str) -> Result<(), FsError> {
+        match self.volume.as_mut() {
+            Some(volume) => {
+                volume.set_label(label)?;
+                self.boot_record = volume.get_boot_record().clone();
+            }
+            None => {
+                let label = Codepage::default().encode_label(label)?;
+                self.boot_record.set_volume_label(label);
+            }
+        }
+        Ok(())
+    }
+
+    /// The volume label of this partition, from the volume once it has been formatted.
+    pub fn get_label(&self) -> Option<String> {
+        match &self.volume {
+            Some(volume) => volume.get_label(),
+            None => match self.boot_record.get_volume_label() {
+                label if &label == b"NO NAME    " => None,
+                label => Some(Codepage::default().decode_label(&label)),
+            },
+        }
+    }
+
+    /// Check the file system on this partition for consistency, optionally repairing it.
+    /// On top of what Volume::check finds, this also compares the size of the file system
+    /// with the size of the partition in the partition table. That one is never repaired.
+    pub fn check(&mut self, repair: bool) -> Option<CheckReport> {
+        let sector_count = self.sector_count;
+        let volume = self.volume.as_mut()?;
+        let mut report = volume.check(repair);
+        let boot_record = volume.get_boot_record().get_volume_sectors_count();
+        if boot_record != sector_count {
+            let problem = Problem::SectorCountMismatch {
+                partition: sector_count,
+                boot_record,
+            };
+            report.push(problem, false);
+        }
+        Some(report)
+    }
+
     /// The first byte of the partition on the underlying disk, as a u64 for easy consumption by StreamSlice
     pub fn get_start_offset(&self) -> u64 {
         let start_offset = self.first_lba * 512;
@@ -128,12 +221,13 @@ impl Partition {
             flag_byte: entry[0],
             last_sector: CHS::from_bytes(last_chs_bytes),
             first_sector: CHS::from_bytes(first_chs_bytes),
-            partition_type: 0x06,
+            partition_type: entry[4],
             first_lba: first_lba,
             sector_count: sector_count,
-            boot_record: VBR::new(sector_count),
+            boot_record: VBR::for_size(sector_count),
             last_lba: sector_count + (first_lba - 1),
-            FAT: FAT::new(sector_count),
+            FAT: FAT::from_boot_record(&VBR::for_size(sector_count)),
+            volume: None,
         }
     }
 }
@@ -1,4 +1,5 @@
 use crate::disk::Disk;
+use crate::fs::FormatOptions;
 use crate::partition::Partition;
 
 #[test]
@@ -50,3 +51,53 @@ fn partition__100mb_roundtrip() {
     assert_eq!(partition.boot_record, reconstituted_partition.boot_record);
     assert_eq!(partition.FAT, reconstituted_partition.FAT);
 }
+
+#[test]
+fn partition_label_before_and_after_format() {
+    let disk = Disk::new("bogus_test_file.raw", 50000000);
+    let mut partition = Partition::new(&disk, 1, 63, 0);
+    assert_eq!(partition.get_label(), None);
+    partition.set_label("msdos622").unwrap();
+    assert_eq!(partition.get_label(), Some("MSDOS622".to_string()));
+    let volume = partition.format();
+    assert_eq!(volume.get_label(), Some("MSDOS622".to_string()));
+    partition.set_label("renamed").unwrap();
+    assert_eq!(partition.boot_record.get_volume_label(), *b"RENAMED    ");
+    assert_eq!(partition.get_label(), Some("RENAMED".to_string()));
+}
+
+#[test]
+fn partition_format_with_options() {
+    let disk = Disk::new("bogus_test_file.raw", 50000000);
+    let mut partition = Partition::new(&disk, 1, 63, 0);
+    partition.set_label("custom").unwrap();
+    let options = FormatOptions::new().cluster_size(16384).root_entries(2048);
+    let volume = partition.format_with(&options).unwrap();
+    assert_eq!(volume.get_boot_record().get_sectors_per_cluster(), 32);
+    assert_eq!(volume.get_label(), Some("CUSTOM".to_string()));
+    assert_eq!(partition.FAT.get_cluster_size(), 16384);
+    // Around 3000 clusters of 16K make this a FAT12 volume, despite its 50MB.
+    assert_eq!(partition.partition_type, 0x01);
+    assert_eq!(
+        partition.FAT.get_cluster_count(),
+        partition.boot_record.get_cluster_count()
+    );
+    assert!(partition
+        .format_with(&FormatOptions::new().cluster_size(512))
+        .is_err());
+    assert_eq!(partition.boot_record.get_sectors_per_cluster(), 32);
+}
+
+/// The partition table tells DOS which FAT it's looking at, so the type follows the volume.
+#[test]
+fn partition_type_follows_the_file_system() {
+    let disk = Disk::new("bogus_test_file.raw", 20000000);
+    assert_eq!(Partition::new(&disk, 1, 63, 0).partition_type, 0x04);
+    let disk = Disk::new("bogus_test_file.raw", 50000000);
+    let partition = Partition::new(&disk, 1, 63, 0);
+    assert_eq!(partition.partition_type, 0x06);
+    let mut entry = [0u8; 16];
+    entry.copy_from_slice(&partition.as_bytes());
+    entry[4] = 0x0E;
+    assert_eq!(Partition::from_bytes(entry).partition_type, 0x0E);
+}